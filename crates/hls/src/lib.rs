@@ -94,7 +94,8 @@ struct AppState {
     pub is_ended: Arc<AtomicBool>,
 }
 
-pub fn run(
+/// Serve playlists and segments on the current tokio runtime
+pub async fn serve(
     segment_size: u64,
     current_segment: Arc<AtomicU64>,
     is_ended: Arc<AtomicBool>,
@@ -114,12 +115,20 @@ pub fn run(
             is_ended,
         });
 
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+pub fn run(
+    segment_size: u64,
+    current_segment: Arc<AtomicU64>,
+    is_ended: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()?;
 
-    let listener = rt.block_on(tokio::net::TcpListener::bind("0.0.0.0:3000"))?;
-    rt.block_on(async { axum::serve(listener, app).await })?;
-
-    Ok(())
+    rt.block_on(serve(segment_size, current_segment, is_ended))
 }
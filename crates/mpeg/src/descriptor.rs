@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result, ensure};
 
 use crate::{
     constants::descriptor_tags,
@@ -14,20 +14,34 @@ pub mod private_data_indicator;
 pub enum Descriptor {
     PrivateDataIndicator(PrivateDataIndicatorDescriptor),
     Mpeg4Video(Mpeg4VideoDescriptor),
+    /// Kept as is
+    Unknown {
+        tag: u8,
+        data: Vec<u8>,
+    },
 }
 
 impl Descriptor {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        ensure!(raw.len() >= 2, "Descriptor too short: {} bytes", raw.len());
+
         let descriptor_tag = raw[0];
+        let descriptor_length = usize::from(raw[1]);
+        let raw = raw
+            .get(..2 + descriptor_length)
+            .context("Descriptor truncated")?;
 
         Ok(match descriptor_tag {
             descriptor_tags::MPEG4_VIDEO_DESCRIPTOR => {
                 Self::Mpeg4Video(Mpeg4VideoDescriptor::deserialize(raw)?)
             }
 
-            _ => todo!("Descriptor tag '{descriptor_tag}'"),
+            tag => Self::Unknown {
+                tag,
+                data: Vec::from(&raw[2..]),
+            },
         })
     }
 
@@ -35,6 +49,7 @@ impl Descriptor {
         match self {
             Descriptor::PrivateDataIndicator(d) => d.size(),
             Descriptor::Mpeg4Video(d) => d.size(),
+            Descriptor::Unknown { data, .. } => data.len() + 2,
         }
     }
 }
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Result, ensure};
 
 use crate::constants::descriptor_tags::MPEG4_VIDEO_DESCRIPTOR;
 
@@ -14,6 +14,12 @@ impl Mpeg4VideoDescriptor {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn deserialize(raw: &[u8]) -> Result<Self> {
+        ensure!(
+            raw.len() == Self::DESCRIPTOR_LENGTH as usize + 2,
+            "Wrong descriptor size: {} bytes",
+            raw.len()
+        );
+
         Ok(Self {
             mpeg4_visual_profile_and_level: raw[2],
         })
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 #[derive(Debug)]
 pub struct PrivateDataIndicatorDescriptor {
@@ -12,7 +12,9 @@ impl PrivateDataIndicatorDescriptor {
     /// Error while parsing raw bytes
     pub fn deserialize(raw: &[u8]) -> Result<Self> {
         Ok(Self {
-            private_data_indicator: u32::from_be_bytes(raw[2..6].try_into()?),
+            private_data_indicator: u32::from_be_bytes(
+                raw.get(2..6).context("Descriptor too short")?.try_into()?,
+            ),
         })
     }
 
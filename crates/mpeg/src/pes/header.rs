@@ -1,4 +1,4 @@
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail, ensure};
 use bit::{Bit, Bits};
 use itertools::Itertools;
 
@@ -16,11 +16,81 @@ impl PesExtension {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        todo!()
+        let mut iter = raw.iter().copied();
+
+        let flags = iter.next().context("Missing PES extension flags")?;
+
+        let pes_private_data = flags
+            .bit(0)
+            .then(|| {
+                iter.next_array::<16>()
+                    .map(u128::from_be_bytes)
+                    .context("Missing PES private data")
+            })
+            .transpose()?;
+
+        let pack_header_field = flags
+            .bit(1)
+            .then(|| {
+                let length = iter.next().context("Missing pack field length")?;
+                // The pack header itself is skipped
+                for _ in 0..length {
+                    iter.next().context("Pack header too short")?;
+                }
+                Ok::<_, anyhow::Error>(length)
+            })
+            .transpose()?;
+
+        let program_packet_seq_cntr = flags
+            .bit(2)
+            .then(|| {
+                iter.next_array::<2>()
+                    .map(|raw| raw.bits::<u8>(1, 7))
+                    .context("Missing program packet sequence counter")
+            })
+            .transpose()?;
+
+        let pstd_buffer = flags
+            .bit(3)
+            .then(|| {
+                iter.next_array::<2>()
+                    .map(|raw| raw.bits::<u16>(2, 14))
+                    .context("Missing P-STD buffer")
+            })
+            .transpose()?;
+
+        let pes_extension_field_data = flags
+            .bit(7)
+            .then(|| {
+                let length = iter.next().context("Missing PES extension field length")? & 0x7F;
+                let data: Vec<u8> = iter.by_ref().take(length.into()).collect();
+                ensure!(data.len() == length.into(), "PES extension field too short");
+                Ok(data)
+            })
+            .transpose()?;
+
+        Ok(Self {
+            pes_private_data,
+            pack_header_field,
+            program_packet_seq_cntr,
+            pstd_buffer,
+            pes_extension_field_data,
+        })
     }
 
     pub fn size(&self) -> usize {
-        todo!()
+        let mut size = 1;
+
+        self.pes_private_data.inspect(|_| size += 16);
+        self.pack_header_field
+            .inspect(|length| size += 1 + usize::from(*length));
+        self.program_packet_seq_cntr.inspect(|_| size += 2);
+        self.pstd_buffer.inspect(|_| size += 2);
+        self.pes_extension_field_data
+            .as_ref()
+            .inspect(|data| size += 1 + data.len());
+
+        size
     }
 }
 
@@ -43,36 +113,40 @@ pub enum PtsDts {
 }
 
 impl PtsDts {
-    pub fn pts_from_raw(raw: [u8; 5]) -> Self {
+    /// # Errors
+    /// Wrong marker bits
+    pub fn pts_from_raw(raw: [u8; 5]) -> Result<Self> {
         let pts_check = raw.bits::<u8>(0, 4);
-        assert_eq!(pts_check, 0b0010);
+        ensure!(pts_check == 0b0010, "Wrong PTS prefix: {pts_check:04b}");
 
         let mut pts = 0;
         pts |= raw.bits::<u64>(4, 3) << 29;
         pts |= raw.bits::<u64>(8, 15) << 15;
-        pts |= raw.bits::<u64>(16, 15);
+        pts |= raw.bits::<u64>(24, 15);
 
-        Self::Pts { pts }
+        Ok(Self::Pts { pts })
     }
 
-    pub fn pts_dts_from_raw(raw: [u8; 10]) -> Self {
+    /// # Errors
+    /// Wrong marker bits
+    pub fn pts_dts_from_raw(raw: [u8; 10]) -> Result<Self> {
         let pts_check = raw.bits::<u8>(0, 4);
-        assert_eq!(pts_check, 0b0011);
+        ensure!(pts_check == 0b0011, "Wrong PTS prefix: {pts_check:04b}");
 
         let mut pts = 0;
         pts |= raw.bits::<u64>(4, 3) << 29;
         pts |= raw.bits::<u64>(8, 15) << 15;
         pts |= raw.bits::<u64>(24, 15);
 
-        let pts_check = raw.bits::<u8>(40, 4);
-        assert_eq!(pts_check, 0b0001);
+        let dts_check = raw.bits::<u8>(40, 4);
+        ensure!(dts_check == 0b0001, "Wrong DTS prefix: {dts_check:04b}");
 
         let mut dts = 0;
         dts |= raw.bits::<u64>(44, 3) << 29;
         dts |= raw.bits::<u64>(48, 15) << 15;
         dts |= raw.bits::<u64>(64, 15);
 
-        Self::PtsDts { pts, dts }
+        Ok(Self::PtsDts { pts, dts })
     }
 
     pub fn pts(&self) -> u64 {
@@ -112,10 +186,10 @@ impl PesHeader {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn deserialize(raw: &[u8]) -> Result<Self> {
-        let mut iter = raw.iter().copied();
+        ensure!(raw.len() >= 3, "PES header too short: {} bytes", raw.len());
 
         // Octet 0
-        let octet_0 = iter.next().unwrap();
+        let octet_0 = raw[0];
         // Bits [1..=2] = '10'
         let pes_scrambling_control = (octet_0 & 0b0011_0000) >> 4;
         let pes_priority = octet_0.bit(4);
@@ -124,7 +198,7 @@ impl PesHeader {
         let original_or_copy = octet_0.bit(7).into();
 
         // Octet 1
-        let octet_1 = iter.next().unwrap();
+        let octet_1 = raw[1];
         let flags_pts_dts = (octet_1 & 0b1100_0000) >> 6;
         let flag_escr = octet_1.bit(2);
         let flag_es_rate = octet_1.bit(3);
@@ -134,33 +208,60 @@ impl PesHeader {
         let flag_pes_extension = octet_1.bit(7);
 
         // Octet 2
-        let pes_header_data_length = iter.next().unwrap();
+        let pes_header_data_length = raw[2];
+
+        // Optional fields must not run past the header data
+        let data = raw
+            .get(3..3 + usize::from(pes_header_data_length))
+            .context("PES header data too short")?;
+        let mut iter = data.iter().copied();
 
         let pts_dts = match flags_pts_dts {
-            0b10 => Some(PtsDts::pts_from_raw(iter.next_array::<5>().unwrap())),
-            0b11 => Some(PtsDts::pts_dts_from_raw(iter.next_array::<10>().unwrap())),
+            0b10 => Some(PtsDts::pts_from_raw(
+                iter.next_array::<5>().context("Missing PTS")?,
+            )?),
+            0b11 => Some(PtsDts::pts_dts_from_raw(
+                iter.next_array::<10>().context("Missing PTS/DTS")?,
+            )?),
             0b01 => bail!("Illegal"),
             _ => None,
         };
 
-        let escr = flag_escr.then(|| {
-            let raw_num = iter.next_array::<6>().unwrap();
-            let mut res = 0;
-            res |= raw_num.bits::<u64>(2, 3) << 29;
-            res |= raw_num.bits::<u64>(6, 15) << 15;
-            res |= raw_num.bits::<u64>(22, 15);
-            // TODO: ESCR_extension
-            res
-        });
+        let escr = flag_escr
+            .then(|| {
+                let raw_num = iter.next_array::<6>().context("Missing ESCR")?;
+                let mut res = 0;
+                res |= raw_num.bits::<u64>(2, 3) << 29;
+                res |= raw_num.bits::<u64>(6, 15) << 15;
+                res |= raw_num.bits::<u64>(22, 15);
+                // TODO: ESCR_extension
+                Ok::<_, anyhow::Error>(res)
+            })
+            .transpose()?;
 
-        let es_rate = flag_es_rate.then(|| iter.next_array::<3>().unwrap().bits::<u32>(1, 22));
+        let es_rate = flag_es_rate
+            .then(|| {
+                iter.next_array::<3>()
+                    .map(|raw| raw.bits::<u32>(1, 22))
+                    .context("Missing ES rate")
+            })
+            .transpose()?;
 
-        let dsm_trick_mode = flag_dsm_trick_mode.then(|| iter.next().unwrap());
+        let dsm_trick_mode = flag_dsm_trick_mode
+            .then(|| iter.next().context("Missing DSM trick mode"))
+            .transpose()?;
 
-        let additional_copy_info = flag_additional_copy_info.then(|| iter.next().unwrap());
+        let additional_copy_info = flag_additional_copy_info
+            .then(|| iter.next().context("Missing additional copy info"))
+            .transpose()?;
 
-        let previous_pes_crc =
-            flag_pes_crc.then(|| iter.next_array::<2>().unwrap().bits::<u16>(0, 16));
+        let previous_pes_crc = flag_pes_crc
+            .then(|| {
+                iter.next_array::<2>()
+                    .map(u16::from_be_bytes)
+                    .context("Missing previous PES CRC")
+            })
+            .transpose()?;
 
         let pes_extension = flag_pes_extension
             .then(|| PesExtension::from_raw(&iter.collect::<Vec<_>>()))
@@ -183,23 +284,8 @@ impl PesHeader {
         })
     }
 
-    /// Get size of raw content in bytes
+    /// Get size of raw content in bytes (including stuffing)
     pub fn size(&self) -> usize {
-        let mut size = 3;
-
-        size += match self.pts_dts {
-            Some(PtsDts::Pts { .. }) => 5,
-            Some(PtsDts::PtsDts { .. }) => 10,
-            None => 0,
-        };
-
-        self.escr.inspect(|_| size += 6);
-        self.es_rate.inspect(|_| size += 3);
-        self.dsm_trick_mode.inspect(|_| size += 1);
-        self.additional_copy_info.inspect(|_| size += 1);
-        self.previous_pes_crc.inspect(|_| size += 2);
-        self.pes_extension.as_ref().inspect(|p| size += p.size());
-
-        size
+        3 + usize::from(self.pes_header_data_length)
     }
 }
@@ -1,11 +1,11 @@
-use anyhow::Result;
+use anyhow::{Result, ensure};
 
 use crate::{constants::stream_ids::GROUP_NO_HEADER, pes::header::PesHeader};
 
 #[derive(Debug)]
 pub struct PesPacket {
     pub stream_id: u8,
-    pes_packet_length: u16,
+    pub pes_packet_length: u16,
     pub pes_header: Option<PesHeader>,
     pub pes_data: Vec<u8>,
 }
@@ -14,9 +14,12 @@ impl PesPacket {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        ensure!(raw.len() >= 6, "PES packet too short: {} bytes", raw.len());
+
         // Octets [0..=2] = Pack start code prefix (0x000001)
+        ensure!(raw[0..3] == [0, 0, 1], "Missing PES start code");
         let stream_id = raw[3];
-        let pes_packet_length = u16::from_be_bytes(raw[4..6].try_into()?);
+        let pes_packet_length = u16::from_be_bytes([raw[4], raw[5]]);
 
         let pes_header = (!GROUP_NO_HEADER.contains(&stream_id))
             .then(|| PesHeader::deserialize(&raw[6..]))
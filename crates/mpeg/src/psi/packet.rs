@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 
 use crate::{
     constants::table_ids,
@@ -15,7 +15,8 @@ pub enum Section {
 
 #[derive(Debug)]
 pub struct ProgramSpecificInformation {
-    pointer: u8,
+    /// Offset of the section (`pointer_field` + 1)
+    pub pointer: usize,
 
     pub section: Section,
 }
@@ -24,17 +25,18 @@ impl ProgramSpecificInformation {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let pointer = raw[0] + 1;
-        let table_id = raw[pointer as usize];
+        let pointer = usize::from(*raw.first().context("Empty PSI")?) + 1;
+        let section_raw = raw.get(pointer..).context("Pointer past the end")?;
+        let table_id = *section_raw.first().context("Missing table ID")?;
 
         let section = match table_id {
-            table_ids::PROGRAM_ASSOCIATION_SECTION => Section::PAS(
-                ProgramAssociationSection::from_raw(&raw[pointer as usize..])?,
-            ),
+            table_ids::PROGRAM_ASSOCIATION_SECTION => {
+                Section::PAS(ProgramAssociationSection::from_raw(section_raw)?)
+            }
             table_ids::TS_PROGRAM_MAP_SECTION => {
-                Section::PMS(TsProgramMapSection::from_raw(&raw[pointer as usize..])?)
+                Section::PMS(TsProgramMapSection::from_raw(section_raw)?)
             }
-            _ => todo!("{table_id}"),
+            _ => bail!("Unsupported table ID: {table_id}"),
         };
 
         Ok(Self { pointer, section })
@@ -1,4 +1,4 @@
-use anyhow::{Result, bail};
+use anyhow::{Result, bail, ensure};
 use bit::{Bit, Bits};
 
 #[derive(Debug)]
@@ -11,7 +11,7 @@ pub struct ProgramAssociation {
 pub struct ProgramAssociationSection {
     pub table_id: u8,
     pub section_syntax_indicator: bool,
-    section_length: u16,
+    pub section_length: u16,
     pub transport_stream_id: u16,
     pub version_number: u8,
     pub current_next_indicator: bool,
@@ -20,7 +20,7 @@ pub struct ProgramAssociationSection {
 
     pub programs: Vec<ProgramAssociation>,
 
-    crc_32: u32,
+    pub crc_32: u32,
 }
 
 impl ProgramAssociationSection {
@@ -31,10 +31,23 @@ impl ProgramAssociationSection {
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
         const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_MPEG_2);
 
+        ensure!(raw.len() >= 3, "Section too short: {} bytes", raw.len());
+
         let table_id = raw[0];
         let section_syntax_indicator = raw[1].bit(0);
-        let section_length = u16::from_be_bytes(raw[1..3].try_into()?) & !(0b11_11_11 << 10);
-        let transport_stream_id = u16::from_be_bytes(raw[3..5].try_into()?);
+        let section_length = u16::from_be_bytes([raw[1], raw[2]]) & !(0b11_11_11 << 10);
+        ensure!(
+            section_length >= 9,
+            "Section length too small: {section_length}"
+        );
+        ensure!(
+            raw.len() >= 3 + section_length as usize,
+            "Section truncated: {} < {} bytes",
+            raw.len(),
+            3 + section_length
+        );
+
+        let transport_stream_id = u16::from_be_bytes([raw[3], raw[4]]);
         let version_number = raw[5..].bits::<u8>(2, 5);
         let current_next_indicator = raw[5..].bit(7);
         let section_number = raw[6];
@@ -46,9 +59,8 @@ impl ProgramAssociationSection {
         for i in 0..programs_count {
             let offset = 8 + (i * 4);
             let program = ProgramAssociation {
-                program_number: u16::from_be_bytes(raw[offset..(offset + 2)].try_into()?),
-                program_id: u16::from_be_bytes(raw[(offset + 2)..(offset + 4)].try_into()?)
-                    & !(0b111 << 13),
+                program_number: u16::from_be_bytes([raw[offset], raw[offset + 1]]),
+                program_id: u16::from_be_bytes([raw[offset + 2], raw[offset + 3]]) & !(0b111 << 13),
             };
             programs.push(program);
         }
@@ -1,4 +1,4 @@
-use anyhow::{Result, bail};
+use anyhow::{Result, bail, ensure};
 use bit::{Bit, Bits};
 
 use crate::descriptor::Descriptor;
@@ -7,7 +7,7 @@ use crate::descriptor::Descriptor;
 pub struct ProgramDefinition {
     pub stream_type: u8,
     pub elementary_pid: u16,
-    es_info_length: u16,
+    pub es_info_length: u16,
 
     pub descriptors: Vec<Descriptor>,
 }
@@ -16,9 +16,18 @@ impl ProgramDefinition {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        ensure!(
+            raw.len() >= 5,
+            "Program definition too short: {} bytes",
+            raw.len()
+        );
+
         let stream_type = raw[0];
-        let elementary_pid = u16::from_be_bytes(raw[1..3].try_into()?) & !(0b111 << 13);
-        let es_info_length = u16::from_be_bytes(raw[3..5].try_into()?) & !(0b1111 << 12);
+        let elementary_pid = u16::from_be_bytes([raw[1], raw[2]]) & !(0b111 << 13);
+        let es_info_length = u16::from_be_bytes([raw[3], raw[4]]) & !(0b1111 << 12);
+        let raw = raw
+            .get(..5 + es_info_length as usize)
+            .ok_or_else(|| anyhow::anyhow!("ES info truncated: {es_info_length} bytes"))?;
 
         let mut descriptors = Vec::new();
 
@@ -48,7 +57,7 @@ impl ProgramDefinition {
 pub struct TsProgramMapSection {
     pub table_id: u8,
     pub section_syntax_indicator: bool,
-    section_length: u16,
+    pub section_length: u16,
     pub program_number: u16,
     pub version_number: u8,
     pub current_next_indicator: bool,
@@ -60,7 +69,7 @@ pub struct TsProgramMapSection {
     pub program_info: Vec<Descriptor>,
     pub program_definitions: Vec<ProgramDefinition>,
 
-    crc_32: u32,
+    pub crc_32: u32,
 }
 
 impl TsProgramMapSection {
@@ -69,23 +78,41 @@ impl TsProgramMapSection {
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
         const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_MPEG_2);
 
+        ensure!(raw.len() >= 3, "Section too short: {} bytes", raw.len());
+
         let table_id = raw[0];
         let section_syntax_indicator = raw[1].bit(0);
-        let section_length = u16::from_be_bytes(raw[1..3].try_into()?) & !(0b11_11_11 << 10);
-        let program_number = u16::from_be_bytes(raw[3..5].try_into()?);
+        let section_length = u16::from_be_bytes([raw[1], raw[2]]) & !(0b11_11_11 << 10);
+        ensure!(
+            section_length >= 13,
+            "Section length too small: {section_length}"
+        );
+        ensure!(
+            raw.len() >= 3 + section_length as usize,
+            "Section truncated: {} < {} bytes",
+            raw.len(),
+            3 + section_length
+        );
+
+        let program_number = u16::from_be_bytes([raw[3], raw[4]]);
         let version_number = raw[5..].bits::<u8>(2, 5);
         let current_next_indicator = raw[5..].bit(7);
         let section_number = raw[6];
         let last_section_number = raw[7];
 
-        let pcr_pid = u16::from_be_bytes(raw[8..10].try_into()?) & !(0b111 << 13);
+        let pcr_pid = u16::from_be_bytes([raw[8], raw[9]]) & !(0b111 << 13);
 
         // Program info (descriptors)
-        let program_info_length = u16::from_be_bytes(raw[10..12].try_into()?) & !(0b1111 << 12);
+        let program_info_length = u16::from_be_bytes([raw[10], raw[11]]) & !(0b1111 << 12);
+        let program_info_end = 12 + program_info_length as usize;
+        ensure!(
+            program_info_end < section_length as usize,
+            "Program info too long: {program_info_length} bytes"
+        );
         let mut offset = 12;
         let mut program_info = Vec::new();
-        while offset < (12 + program_info_length as usize) {
-            let desc = Descriptor::from_raw(&raw[offset..])?;
+        while offset < program_info_end {
+            let desc = Descriptor::from_raw(&raw[offset..program_info_end])?;
             offset += desc.size();
             program_info.push(desc);
         }
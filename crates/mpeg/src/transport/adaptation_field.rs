@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result, ensure};
 use bit::{Bit, Bits};
 
 use crate::transport::adaptation_field_extension::AdaptationFieldExtension;
@@ -26,7 +26,17 @@ impl AdaptationField {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let length = raw[0];
+        let length = *raw.first().context("Empty adaptation field")?;
+        ensure!(length > 0, "Empty adaptation field");
+
+        // Optional fields must not run past the declared length
+        let raw = raw
+            .get(..=length as usize)
+            .context("Adaptation field too short")?;
+        let field = |offset: usize, len: usize| {
+            raw.get(offset..offset + len)
+                .context("Adaptation field too short")
+        };
 
         let discontinuity_indicator = raw[1].bit(0);
         let random_access_indicator = raw[1].bit(1);
@@ -38,34 +48,44 @@ impl AdaptationField {
         let pcr = flags
             .bit(3)
             .then(|| {
-                let base = raw[offset..].bits::<u64>(0, 33);
-                let ext = raw[offset..].bits::<u64>(39, 9);
-                base * 300 + ext
+                let raw = field(offset, 6)?;
+                let base = raw.bits::<u64>(0, 33);
+                let ext = raw.bits::<u64>(39, 9);
+                Ok::<_, anyhow::Error>(base * 300 + ext)
             })
+            .transpose()?
             .inspect(|_| offset += 6);
 
         let opcr = flags
             .bit(4)
             .then(|| {
-                let base = raw[offset..].bits::<u64>(0, 33);
-                let ext = raw[offset..].bits::<u64>(39, 9);
-                base * 300 + ext
+                let raw = field(offset, 6)?;
+                let base = raw.bits::<u64>(0, 33);
+                let ext = raw.bits::<u64>(39, 9);
+                Ok::<_, anyhow::Error>(base * 300 + ext)
             })
+            .transpose()?
             .inspect(|_| offset += 6);
 
-        let splice_countdown = flags.bit(5).then(|| raw[offset]).inspect(|_| offset += 1);
+        let splice_countdown = flags
+            .bit(5)
+            .then(|| field(offset, 1).map(|raw| raw[0]))
+            .transpose()?
+            .inspect(|_| offset += 1);
 
-        let transport_private_data = flags.bit(6).then(|| {
-            let len = raw[offset] as usize;
-            offset += 1;
-            let data = Vec::from(&raw[offset..(offset + len)]);
-            offset += len;
-            data
-        });
+        let transport_private_data = flags
+            .bit(6)
+            .then(|| {
+                let len = field(offset, 1)?[0] as usize;
+                let data = Vec::from(field(offset + 1, len)?);
+                offset += 1 + len;
+                Ok::<_, anyhow::Error>(data)
+            })
+            .transpose()?;
 
         let adaptation_field_extension = flags
             .bit(7)
-            .then(|| AdaptationFieldExtension::from_raw(&raw[offset..]))
+            .then(|| AdaptationFieldExtension::from_raw(&raw[offset.min(raw.len())..]))
             .transpose()?;
 
         Ok(Self {
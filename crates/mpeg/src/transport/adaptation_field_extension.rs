@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bit::{Bit, Bits};
 
 #[derive(Debug)]
@@ -27,39 +27,56 @@ impl AdaptationFieldExtension {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let length = raw[0];
-        let flags = raw[1];
+        let length = *raw.first().context("Empty adaptation field extension")?;
+
+        // Optional fields must not run past the declared length
+        let raw = raw
+            .get(..=length as usize)
+            .context("Adaptation field extension too short")?;
+        let field = |offset: usize, len: usize| {
+            raw.get(offset..offset + len)
+                .context("Adaptation field extension too short")
+        };
+
+        let flags = field(1, 1)?[0];
 
         let mut offset = 2;
 
         let ltw = flags
             .bit(0)
-            .then(|| AdaptationFieldExtensionLtw {
-                valid_flag: raw[offset..].bit(0),
-                offset: raw[offset..].bits::<u16>(1, 15),
+            .then(|| {
+                let raw = field(offset, 2)?;
+                Ok::<_, anyhow::Error>(AdaptationFieldExtensionLtw {
+                    valid_flag: raw.bit(0),
+                    offset: raw.bits::<u16>(1, 15),
+                })
             })
+            .transpose()?
             .inspect(|_| offset += 2);
 
         let piecewise_rate = flags
             .bit(1)
-            .then(|| raw[offset..].bits::<u32>(2, 22))
+            .then(|| field(offset, 3).map(|raw| raw.bits::<u32>(2, 22)))
+            .transpose()?
             .inspect(|_| offset += 3);
 
         let splice_type = flags
             .bit(2)
             .then(|| {
-                let splice_type = raw[offset..].bits::<u8>(0, 4);
+                let raw = field(offset, 5)?;
+                let splice_type = raw.bits::<u8>(0, 4);
 
                 let mut dts_next_au = 0;
-                dts_next_au |= raw[offset..].bits::<u64>(4, 3) << 29;
-                dts_next_au |= raw[offset..].bits::<u64>(8, 15) << 15;
-                dts_next_au |= raw[offset..].bits::<u64>(16, 15);
+                dts_next_au |= raw.bits::<u64>(4, 3) << 29;
+                dts_next_au |= raw.bits::<u64>(8, 15) << 15;
+                dts_next_au |= raw.bits::<u64>(24, 15);
 
-                AdaptationFieldExtensionSeamlessSplice {
+                Ok::<_, anyhow::Error>(AdaptationFieldExtensionSeamlessSplice {
                     splice_type,
                     dts_next_au,
-                }
+                })
             })
+            .transpose()?
             .inspect(|_| offset += 5);
 
         Ok(Self {
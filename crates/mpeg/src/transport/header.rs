@@ -1,4 +1,4 @@
-use anyhow::{Result, bail};
+use anyhow::{Result, bail, ensure};
 use bit::Bit;
 
 use crate::transport::adaptation_field_control::AdaptationFieldControl;
@@ -20,15 +20,16 @@ impl Header {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        ensure!(raw.len() >= 4, "Header too short: {} bytes", raw.len());
         if raw[0] != 0x47 {
-            bail!("Missing sync byte: {raw:?}");
+            bail!("Missing sync byte: 0x{:02X}", raw[0]);
         }
 
         // Raw numbers
         let transport_error_indicator = raw[1].bit(0);
         let payload_unit_start_indicator = raw[1].bit(1);
         let transport_priority = raw[1].bit(2);
-        let packet_id = u16::from_be_bytes(raw[1..3].try_into()?) & 0b0001_1111_1111_1111;
+        let packet_id = u16::from_be_bytes([raw[1], raw[2]]) & 0b0001_1111_1111_1111;
         let transport_scrambling_control = (raw[3] & 0b1100_0000) >> 6;
         let adaptation_field_control = (raw[3] & 0b0011_0000) >> 4;
         let continuity_counter = raw[3] & 0b0000_1111;
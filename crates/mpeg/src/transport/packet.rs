@@ -1,7 +1,7 @@
-use anyhow::Result;
+use anyhow::{Result, ensure};
 
 use crate::{
-    constants::packet_ids::GROUP_CONTROL,
+    constants::{PACKET_SIZE, packet_ids::GROUP_CONTROL},
     pes::packet::PesPacket,
     psi::packet::ProgramSpecificInformation,
     transport::{adaptation_field::AdaptationField, header::Header},
@@ -42,6 +42,11 @@ impl TransportPacket {
     /// # Errors
     /// Error while parsing raw bytes
     pub fn from_raw(raw: &[u8], pmt_packet_ids: &[u16]) -> Result<Self> {
+        ensure!(
+            raw.len() == PACKET_SIZE,
+            "Wrong packet size: {} bytes",
+            raw.len()
+        );
         let header = Header::from_raw(raw)?;
 
         let adaptation_field = if !header.adaptation_field_control.adaptation_field() {
@@ -56,7 +61,9 @@ impl TransportPacket {
             let payload_body = &raw[(4 + adaptation_field.size())..];
             if header.payload_unit_start {
                 // Contains PES or PSI
-                if GROUP_CONTROL.contains(&header.packet_id) || pmt_packet_ids.contains(&header.packet_id) {
+                if GROUP_CONTROL.contains(&header.packet_id)
+                    || pmt_packet_ids.contains(&header.packet_id)
+                {
                     Some(Payload::PSI(ProgramSpecificInformation::from_raw(
                         payload_body,
                     )?))
@@ -77,3 +84,60 @@ impl TransportPacket {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::psi::packet::Section;
+
+    const PAT: &[u8] = include_bytes!("../../tests/data/pat.ts");
+    const PMT: &[u8] = include_bytes!("../../tests/data/pmt.ts");
+    const VIDEO: &[u8] = include_bytes!("../../tests/data/video_pes.ts");
+    const AUDIO: &[u8] = include_bytes!("../../tests/data/audio_pes.ts");
+
+    #[test]
+    fn test_parse() {
+        let pack = TransportPacket::from_raw(PAT, &[]).unwrap();
+        let Some(Payload::PSI(psi)) = pack.payload else {
+            panic!("Not a PSI");
+        };
+        let Section::PAS(pat) = psi.section else {
+            panic!("Not a PAT");
+        };
+        assert_eq!(pat.programs[0].program_id, 0x1000);
+
+        let pack = TransportPacket::from_raw(PMT, &[0x1000]).unwrap();
+        let Some(Payload::PSI(psi)) = pack.payload else {
+            panic!("Not a PSI");
+        };
+        let Section::PMS(pmt) = psi.section else {
+            panic!("Not a PMT");
+        };
+        assert_eq!(pmt.pcr_pid, 0x100);
+        assert_eq!(pmt.program_definitions.len(), 2);
+
+        for (raw, pts) in [(VIDEO, 133_200), (AUDIO, 133_200)] {
+            let pack = TransportPacket::from_raw(raw, &[0x1000]).unwrap();
+            let Some(Payload::PES(pes)) = pack.payload else {
+                panic!("Not a PES");
+            };
+            assert_eq!(pes.pes_header.unwrap().pts_dts.unwrap().pts(), pts);
+        }
+    }
+
+    #[test]
+    fn test_corrupted_packets() {
+        for raw in [PAT, PMT, VIDEO, AUDIO] {
+            // Flipping any byte must not panic
+            for i in 0..raw.len() {
+                let mut raw = raw.to_vec();
+                raw[i] ^= 0xFF;
+                _ = TransportPacket::from_raw(&raw, &[0x1000]);
+            }
+
+            for len in 0..raw.len() {
+                assert!(TransportPacket::from_raw(&raw[..len], &[0x1000]).is_err());
+            }
+        }
+    }
+}
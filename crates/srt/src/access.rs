@@ -0,0 +1,334 @@
+//! IP allow/deny rules for callers
+//!
+//! Rules are checked in order, the first one matching the caller decides.
+//! A rule may be limited to stream IDs (a pattern, where `*` matches anything):
+//!
+//! ```text
+//! # Only venue encoders may publish to live/venue*
+//! allow 10.20.0.0/16 live/venue*
+//! deny  any          live/venue*
+//! allow any
+//! ```
+//!
+//! Patterns are matched against the whole stream ID, and against the resource (`r=`)
+//! of an access control stream ID (`#!::r=live/venue1,m=publish`).
+//!
+//! The address is checked on every handshake, before any state is kept.
+//! The stream ID is only known from the Conclusion, so stream rules are checked then,
+//! before the connection is made.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv6Addr},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Error, Result, bail};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Action {
+    #[default]
+    Allow,
+    Deny,
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            _ => bail!("Unknown action: {s}"),
+        }
+    }
+}
+
+/// Address block, e.g. `10.20.0.0/16` (a bare address is a single host)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// All addresses of both families
+    pub const ANY: Self = Self {
+        addr: IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        prefix: 0,
+    };
+
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            bail!("Prefix too long: {addr}/{prefix}");
+        }
+
+        Ok(Self { addr, prefix })
+    }
+
+    /// IPv4-mapped IPv6 addresses (dual-stack sockets) count as IPv4
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        if *self == Self::ANY {
+            return true;
+        }
+
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "any" {
+            return Ok(Self::ANY);
+        }
+
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>()?, Some(prefix.parse()?)),
+            None => (s.parse::<IpAddr>()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+
+        Self::new(addr, prefix.unwrap_or(max))
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Self::ANY {
+            return f.write_str("any");
+        }
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    pub network: Cidr,
+    /// Stream ID pattern, any stream if `None`
+    pub stream: Option<String>,
+}
+
+impl Rule {
+    fn matches_stream(&self, stream_id: Option<&str>) -> bool {
+        let Some(pattern) = &self.stream else {
+            return true;
+        };
+        let stream_id = stream_id.unwrap_or_default();
+
+        glob(pattern, stream_id) || resource(stream_id).is_some_and(|r| glob(pattern, r))
+    }
+}
+
+/// `<allow|deny> <CIDR|any> [stream pattern]`
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (action, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let rest = rest.trim_start();
+        let (network, stream) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if network.is_empty() {
+            bail!("Rule without an address: {s}");
+        }
+        let stream = stream.trim();
+
+        Ok(Self {
+            action: action.parse()?,
+            network: network.parse()?,
+            stream: (!stream.is_empty()).then(|| stream.to_owned()),
+        })
+    }
+}
+
+/// Ordered rules and the action for callers, that match none
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessRules {
+    pub rules: Vec<Rule>,
+    pub default: Action,
+}
+
+impl AccessRules {
+    /// Whether a caller from `ip` may connect to `stream_id`
+    pub fn allows(&self, ip: IpAddr, stream_id: Option<&str>) -> bool {
+        self.rules
+            .iter()
+            .find(|rule| rule.network.contains(ip) && rule.matches_stream(stream_id))
+            .map_or(self.default, |rule| rule.action)
+            == Action::Allow
+    }
+
+    /// Whether a caller from `ip` may connect to any stream (its stream ID isn't known yet)
+    pub fn allows_address(&self, ip: IpAddr) -> bool {
+        for rule in self.rules.iter().filter(|rule| rule.network.contains(ip)) {
+            match (rule.action, &rule.stream) {
+                (action, None) => return action == Action::Allow,
+                (Action::Allow, Some(_)) => return true,
+                // Other streams may still be allowed
+                (Action::Deny, Some(_)) => {}
+            }
+        }
+
+        self.default == Action::Allow
+    }
+}
+
+/// One rule per line (see [`Rule`]), empty lines and lines starting with `#` are skipped
+///
+/// A line of just `allow` or `deny` sets the default.
+impl FromStr for AccessRules {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut rules = Self::default();
+
+        for (k, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Ok(action) = line.parse() {
+                rules.default = action;
+                continue;
+            }
+            let rule = line
+                .parse()
+                .map_err(|e: Error| e.context(format!("Line {}", k + 1)))?;
+            rules.rules.push(rule);
+        }
+
+        Ok(rules)
+    }
+}
+
+/// Rules shared with a running listener
+///
+/// Clones share the same rules, so a clone kept aside can [`AccessControl::reload`] them.
+/// New rules only apply to callers, that connect afterwards.
+#[derive(Clone, Debug, Default)]
+pub struct AccessControl(Arc<RwLock<AccessRules>>);
+
+impl AccessControl {
+    pub fn new(rules: AccessRules) -> Self {
+        Self(Arc::new(RwLock::new(rules)))
+    }
+
+    pub fn reload(&self, rules: AccessRules) {
+        *self.0.write().unwrap() = rules;
+    }
+
+    pub fn rules(&self) -> AccessRules {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn allows(&self, ip: IpAddr, stream_id: Option<&str>) -> bool {
+        self.0.read().unwrap().allows(ip, stream_id)
+    }
+
+    pub fn allows_address(&self, ip: IpAddr) -> bool {
+        self.0.read().unwrap().allows_address(ip)
+    }
+}
+
+/// `*` matches any (possibly empty) run of characters
+fn glob(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = s.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*`
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        let Some(index) = rest.find(part) else {
+            return false;
+        };
+        rest = &rest[index + part.len()..];
+    }
+
+    rest.ends_with(last)
+}
+
+/// `r` of `#!::r=...,m=...`
+pub(crate) fn resource(stream_id: &str) -> Option<&str> {
+    stream_id
+        .strip_prefix("#!::")?
+        .split(',')
+        .find_map(|pair| pair.strip_prefix("r="))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        let rules: AccessRules = "
+            # Venue encoders only
+            allow 10.20.0.0/16 live/venue*
+            deny any live/venue*
+            deny 192.0.2.0/24
+            allow
+        "
+        .parse()
+        .unwrap();
+        assert_eq!(rules.rules.len(), 3);
+
+        let venue = "10.20.3.4".parse().unwrap();
+        let other = "10.30.0.1".parse().unwrap();
+        let banned = "192.0.2.9".parse().unwrap();
+        let mapped = "::ffff:10.20.0.1".parse().unwrap();
+
+        assert!(rules.allows(venue, Some("live/venue1")));
+        assert!(rules.allows(venue, Some("#!::r=live/venue1,m=publish")));
+        assert!(rules.allows(mapped, Some("live/venue1")));
+        assert!(!rules.allows(other, Some("live/venue1")));
+        assert!(!rules.allows(other, Some("#!::m=publish,r=live/venue2")));
+        assert!(rules.allows(other, Some("live/other")));
+        assert!(rules.allows(other, None));
+
+        assert!(rules.allows_address(other));
+        assert!(!rules.allows_address(banned));
+        assert!(!rules.allows(banned, Some("live/other")));
+
+        assert!("allow 10.0.0.0/33".parse::<Rule>().is_err());
+        assert!("permit any".parse::<Rule>().is_err());
+        assert!("allow".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("live/*", "live/"));
+        assert!(glob("*/venue*", "live/venue1"));
+        assert!(glob("a*b*c", "abbc"));
+        assert!(!glob("a*b*c", "acb"));
+        assert!(!glob("ab*ba", "aba"));
+        assert!(glob("exact", "exact"));
+        assert!(!glob("exact", "exactly"));
+    }
+}
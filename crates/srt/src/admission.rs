@@ -0,0 +1,263 @@
+//! Admission of new callers (handshake flood protection)
+//!
+//! - Callers are checked against [`AccessControl`] rules.
+//! - Induction keeps no state: its cookie is a keyed hash of the caller address and a time window,
+//!   so a half-open handshake expires along with its cookie.
+//! - Handshakes are rate-limited per IP address, in a table of bounded size.
+//! - The # of concurrent connections is capped.
+//!
+//! Both are set in [`ListenerOptions`].
+
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Instant, SystemTime},
+};
+
+use crate::{
+    access::AccessControl,
+    clock::Clock,
+    options::{Limits, ListenerOptions},
+    packet::{
+        Packet, PacketContent,
+        control::{ControlPacketInfo, handshake::HandshakeType},
+    },
+};
+
+/// Shared by the workers of one listener
+pub struct Admission {
+    limits: Limits,
+    access: AccessControl,
+    /// Random key of the cookie hash
+    key: RandomState,
+    check_cookies: bool,
+    rates: Mutex<Rates>,
+    connections: AtomicUsize,
+}
+
+struct Rates {
+    buckets: HashMap<IpAddr, Bucket>,
+    /// Last time full buckets were dropped
+    purged: Instant,
+}
+
+/// Token bucket of one IP address
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Returns the tokens available now
+    fn refill(&mut self, now: Instant, rate: u32, burst: f64) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * f64::from(rate)).min(burst);
+        self.updated = now;
+        self.tokens
+    }
+}
+
+/// Handshake, that may be passed to [`crate::connection::Connection::accept`]
+#[derive(Clone, Copy, Debug)]
+pub struct Admitted {
+    /// For the Induction response
+    pub cookie: u32,
+    /// A connection slot was taken (Conclusion), give it back with [`Admission::release`]
+    /// unless a connection was made
+    pub reserved: bool,
+}
+
+impl Admission {
+    pub fn new(options: &ListenerOptions) -> Self {
+        Self {
+            limits: options.limits.clone(),
+            access: options.access.clone(),
+            key: RandomState::new(),
+            check_cookies: true,
+            rates: Mutex::new(Rates {
+                buckets: HashMap::new(),
+                purged: Instant::now(),
+            }),
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    /// Accept any cookie (for replaying a capture, as the key isn't in it)
+    pub fn ignore_cookies(&mut self) {
+        self.check_cookies = false;
+    }
+
+    /// Check a packet from an unknown address, `None` if it should be dropped
+    pub fn admit(&self, pack: &Packet, addr: SocketAddr, clock: &dyn Clock) -> Option<Admitted> {
+        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = &pack.content else {
+            return None;
+        };
+
+        if !self.access.allows_address(addr.ip()) {
+            tracing::debug!("Denied by access rules: {addr}");
+            return None;
+        }
+        if !self.within_rate(addr.ip(), clock.now()) {
+            tracing::debug!("Handshake rate exceeded: {addr}");
+            return None;
+        }
+
+        let now = clock.system_now();
+        let mut admitted = Admitted {
+            cookie: self.cookie(addr, self.window(now)),
+            reserved: false,
+        };
+
+        if handshake.handshake_type == HandshakeType::Conclusion {
+            if self.check_cookies && !self.is_valid_cookie(addr, handshake.syn_cookie, now) {
+                tracing::debug!("Bad or expired cookie: {addr}");
+                return None;
+            }
+            let stream_id = handshake
+                .stream_id_extension()
+                .map(|x| x.stream_id.as_str());
+            if !self.access.allows(addr.ip(), stream_id) {
+                tracing::info!("Denied by access rules: {addr} ({stream_id:?})");
+                return None;
+            }
+            if !self.try_reserve() {
+                tracing::warn!("Connection limit reached, dropped Conclusion from {addr}");
+                return None;
+            }
+            admitted.reserved = true;
+        }
+
+        Some(admitted)
+    }
+
+    /// Give back the slot of a connection, that was closed (or never made)
+    pub fn release(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// # of connections holding a slot
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    fn try_reserve(&self) -> bool {
+        let max = self.limits.max_connections.unwrap_or(usize::MAX);
+        self.connections
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .is_ok()
+    }
+
+    fn window(&self, now: SystemTime) -> u64 {
+        let secs = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        secs / self.limits.cookie_lifetime.as_secs()
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn cookie(&self, addr: SocketAddr, window: u64) -> u32 {
+        self.key.hash_one((addr, window)) as u32
+    }
+
+    /// Issued in this window or the one before
+    fn is_valid_cookie(&self, addr: SocketAddr, cookie: u32, now: SystemTime) -> bool {
+        let window = self.window(now);
+        [window, window.saturating_sub(1)]
+            .into_iter()
+            .any(|window| self.cookie(addr, window) == cookie)
+    }
+
+    fn within_rate(&self, ip: IpAddr, now: Instant) -> bool {
+        let Some(rate) = self.limits.handshake_rate else {
+            return true;
+        };
+        let burst = f64::from(self.limits.handshake_burst);
+        let mut rates = self.rates.lock().unwrap();
+
+        if !rates.buckets.contains_key(&ip)
+            && rates.buckets.len() >= self.limits.max_tracked_addresses
+        {
+            // Forget addresses, that are back to a full bucket
+            // (at most once per refill, so a flood of new addresses doesn't scan every time)
+            let refill = burst / f64::from(rate);
+            if now.saturating_duration_since(rates.purged).as_secs_f64() < refill {
+                return false;
+            }
+            rates.purged = now;
+            rates
+                .buckets
+                .retain(|_, bucket| bucket.refill(now, rate, burst) < burst);
+
+            if rates.buckets.len() >= self.limits.max_tracked_addresses {
+                return false;
+            }
+        }
+
+        let bucket = rates.buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        if bucket.refill(now, rate, burst) < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_cookies() {
+        let admission = Admission::new(&ListenerOptions::default());
+        let addr = "192.0.2.1:5000".parse().unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_005);
+
+        let cookie = admission.cookie(addr, admission.window(now));
+        assert!(admission.is_valid_cookie(addr, cookie, now));
+        assert!(admission.is_valid_cookie(addr, cookie, now + Duration::from_secs(10)));
+        assert!(!admission.is_valid_cookie(addr, cookie, now + Duration::from_secs(20)));
+
+        let other = "192.0.2.1:5001".parse().unwrap();
+        assert!(!admission.is_valid_cookie(other, cookie, now));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let admission = Admission::new(&ListenerOptions {
+            limits: Limits {
+                handshake_rate: Some(10),
+                handshake_burst: 2,
+                max_tracked_addresses: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let ip = |k: u8| IpAddr::from([192, 0, 2, k]);
+        let now = Instant::now();
+
+        assert!(admission.within_rate(ip(1), now));
+        assert!(admission.within_rate(ip(1), now));
+        assert!(!admission.within_rate(ip(1), now));
+        // 1 token per 100 ms
+        assert!(admission.within_rate(ip(1), now + Duration::from_millis(100)));
+
+        // The table is full until the first address is back to a full bucket
+        assert!(admission.within_rate(ip(2), now));
+        assert!(!admission.within_rate(ip(3), now + Duration::from_millis(100)));
+        assert!(admission.within_rate(ip(3), now + Duration::from_millis(300)));
+    }
+}
@@ -0,0 +1,301 @@
+//! Batched datagram I/O
+//!
+//! Uses `recvmmsg`/`sendmmsg` on Linux and falls back to one syscall per datagram elsewhere.
+
+use std::{io, net::SocketAddr};
+
+use crate::constants::MAX_PACKET_SIZE;
+
+/// Max # of datagrams per syscall
+pub const BATCH_SIZE: usize = 32;
+
+pub type Datagram = (Vec<u8>, SocketAddr);
+
+/// Receive buffers for [`recv_batch`]
+pub struct RecvBatch {
+    bufs: Vec<[u8; MAX_PACKET_SIZE]>,
+    received: Vec<(usize, SocketAddr)>,
+}
+
+impl RecvBatch {
+    pub fn new() -> Self {
+        Self {
+            bufs: vec![[0; MAX_PACKET_SIZE]; BATCH_SIZE],
+            received: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    /// Receive a single datagram into the first buffer
+    pub fn recv_with(
+        &mut self,
+        recv: impl FnOnce(&mut [u8]) -> io::Result<(usize, SocketAddr)>,
+    ) -> io::Result<usize> {
+        self.received.clear();
+
+        let (n, addr) = recv(&mut self.bufs[0])?;
+        self.received.push((n, addr));
+
+        Ok(1)
+    }
+
+    /// Datagrams from the last [`recv_batch`] call
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        self.received
+            .iter()
+            .zip(&self.bufs)
+            .map(|(&(n, addr), buf)| (&buf[..n], addr))
+    }
+}
+
+impl Default for RecvBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub use fallback::{recv_batch, send_batch};
+
+#[cfg(target_os = "linux")]
+pub use linux::{recv_batch, send_batch};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        io, mem,
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket},
+        os::fd::AsRawFd,
+        ptr,
+    };
+
+    use super::{BATCH_SIZE, Datagram, RecvBatch};
+
+    #[allow(clippy::cast_possible_truncation)]
+    const STORAGE_LEN: libc::socklen_t = mem::size_of::<libc::sockaddr_storage>() as _;
+
+    fn to_socket_addr(storage: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+        match i32::from(storage.ss_family) {
+            libc::AF_INET => {
+                // SAFETY: `ss_family` says the storage holds `sockaddr_in`
+                let addr = unsafe { &*ptr::from_ref(storage).cast::<libc::sockaddr_in>() };
+                Ok(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                    u16::from_be(addr.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                // SAFETY: `ss_family` says the storage holds `sockaddr_in6`
+                let addr = unsafe { &*ptr::from_ref(storage).cast::<libc::sockaddr_in6>() };
+                Ok(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                )))
+            }
+            family => Err(io::Error::other(format!(
+                "Unsupported address family: {family}"
+            ))),
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn from_socket_addr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        // SAFETY: all-zero is a valid `sockaddr_storage`
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+        let len = match addr {
+            SocketAddr::V4(addr) => {
+                // SAFETY: `sockaddr_storage` is large enough for any address type
+                let raw = unsafe { &mut *ptr::from_mut(&mut storage).cast::<libc::sockaddr_in>() };
+                raw.sin_family = libc::AF_INET as libc::sa_family_t;
+                raw.sin_port = addr.port().to_be();
+                raw.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                // SAFETY: `sockaddr_storage` is large enough for any address type
+                let raw = unsafe { &mut *ptr::from_mut(&mut storage).cast::<libc::sockaddr_in6>() };
+                raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                raw.sin6_port = addr.port().to_be();
+                raw.sin6_flowinfo = addr.flowinfo();
+                raw.sin6_addr.s6_addr = addr.ip().octets();
+                raw.sin6_scope_id = addr.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+
+        (storage, len as libc::socklen_t)
+    }
+
+    /// Receive up to [`BATCH_SIZE`] datagrams
+    ///
+    /// Blocks (respecting the socket read timeout) until at least one datagram arrives.
+    pub fn recv_batch(socket: &UdpSocket, batch: &mut RecvBatch) -> io::Result<usize> {
+        batch.received.clear();
+
+        // SAFETY: all-zero is a valid `sockaddr_storage`
+        let mut addrs: [libc::sockaddr_storage; BATCH_SIZE] = unsafe { mem::zeroed() };
+        let mut iovecs: Vec<libc::iovec> = batch
+            .bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr().cast(),
+                iov_len: buf.len(),
+            })
+            .collect();
+
+        let mut msgs: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iovec, addr)| {
+                // SAFETY: all-zero is a valid `mmsghdr`
+                let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                msg.msg_hdr.msg_name = ptr::from_mut(addr).cast();
+                msg.msg_hdr.msg_namelen = STORAGE_LEN;
+                msg.msg_hdr.msg_iov = ptr::from_mut(iovec);
+                msg.msg_hdr.msg_iovlen = 1;
+                msg
+            })
+            .collect();
+
+        // SAFETY: every header points to a live buffer and address storage
+        #[allow(clippy::cast_possible_truncation)]
+        let res = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                msgs.as_mut_ptr(),
+                msgs.len() as libc::c_uint,
+                libc::MSG_WAITFORONE,
+                ptr::null_mut(),
+            )
+        };
+        let Ok(n) = usize::try_from(res) else {
+            return Err(io::Error::last_os_error());
+        };
+
+        for (msg, addr) in msgs.iter().zip(&addrs).take(n) {
+            batch
+                .received
+                .push((msg.msg_len as usize, to_socket_addr(addr)?));
+        }
+
+        Ok(n)
+    }
+
+    /// Send all datagrams
+    ///
+    /// A datagram, that fails (e.g. unreachable), is skipped, the first error is returned.
+    pub fn send_batch(socket: &UdpSocket, datagrams: &[Datagram]) -> io::Result<()> {
+        let mut res = Ok(());
+
+        for chunk in datagrams.chunks(BATCH_SIZE) {
+            let mut addrs: Vec<_> = chunk
+                .iter()
+                .map(|(_, addr)| from_socket_addr(addr))
+                .collect();
+            let mut iovecs: Vec<libc::iovec> = chunk
+                .iter()
+                .map(|(data, _)| libc::iovec {
+                    iov_base: data.as_ptr().cast_mut().cast(),
+                    iov_len: data.len(),
+                })
+                .collect();
+
+            let mut msgs: Vec<libc::mmsghdr> = iovecs
+                .iter_mut()
+                .zip(addrs.iter_mut())
+                .map(|(iovec, (addr, len))| {
+                    // SAFETY: all-zero is a valid `mmsghdr`
+                    let mut msg: libc::mmsghdr = unsafe { mem::zeroed() };
+                    msg.msg_hdr.msg_name = ptr::from_mut(addr).cast();
+                    msg.msg_hdr.msg_namelen = *len;
+                    msg.msg_hdr.msg_iov = ptr::from_mut(iovec);
+                    msg.msg_hdr.msg_iovlen = 1;
+                    msg
+                })
+                .collect();
+
+            let mut sent = 0;
+            while sent < msgs.len() {
+                // SAFETY: every header points to a live buffer and address
+                #[allow(clippy::cast_possible_truncation)]
+                let ret = unsafe {
+                    libc::sendmmsg(
+                        socket.as_raw_fd(),
+                        msgs[sent..].as_mut_ptr(),
+                        (msgs.len() - sent) as libc::c_uint,
+                        0,
+                    )
+                };
+                match usize::try_from(ret) {
+                    Ok(n) => sent += n,
+                    Err(_) => {
+                        let e = io::Error::last_os_error();
+                        if e.kind() != io::ErrorKind::Interrupted {
+                            // The one at `sent` failed
+                            sent += 1;
+                            res = res.and(Err(e));
+                        }
+                    }
+                }
+            }
+        }
+
+        res
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod fallback {
+    use std::{io, net::UdpSocket};
+
+    use super::{Datagram, RecvBatch};
+
+    /// Receive a single datagram
+    pub fn recv_batch(socket: &UdpSocket, batch: &mut RecvBatch) -> io::Result<usize> {
+        batch.recv_with(|buf| socket.recv_from(buf))
+    }
+
+    /// Send all datagrams (the first error is returned after trying the rest)
+    pub fn send_batch(socket: &UdpSocket, datagrams: &[Datagram]) -> io::Result<()> {
+        let mut res = Ok(());
+        for (data, addr) in datagrams {
+            if let Err(e) = socket.send_to(data, addr) {
+                res = res.and(Err(e));
+            }
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use super::*;
+
+    #[test]
+    fn test_batch_roundtrip() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b_addr = b.local_addr().unwrap();
+
+        let datagrams: Vec<Datagram> = (0..3u8).map(|i| (vec![i; 10], b_addr)).collect();
+        send_batch(&a, &datagrams).unwrap();
+
+        let mut batch = RecvBatch::new();
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            recv_batch(&b, &mut batch).unwrap();
+            received.extend(batch.iter().map(|(data, addr)| (data.to_vec(), addr)));
+        }
+
+        let a_addr = a.local_addr().unwrap();
+        assert_eq!(
+            received,
+            (0..3u8).map(|i| (vec![i; 10], a_addr)).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,611 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    packet::data::{DataPacketInfo, EncryptionFlag, PacketPosition},
+    seq::{MsgNo, SeqNo},
+};
+
+/// Reorders inbound data packets and assembles them into messages
+///
+/// Packets are released strictly in sequence order, with TSBPD once they are due
+/// (see [`ReceiveBuffer::pop_due`]). A gap is skipped once the packet after it is due
+/// (too late, TLPKTDROP), or when it's not filled before the buffer holds `capacity` packets.
+#[derive(Debug)]
+pub struct ReceiveBuffer {
+    /// Sequence number of the next packet to be released
+    next: SeqNo,
+    capacity: usize,
+    /// By key: distance from the initial sequence number, so the order survives wraparounds
+    packets: BTreeMap<u64, Received>,
+    /// Key of `next`
+    next_key: u64,
+    /// Packets, that were given up on (too late)
+    dropped: u64,
+}
+
+#[derive(Debug)]
+struct Received {
+    position: PacketPosition,
+    /// Peer timestamp, TSBPD time is based on it
+    timestamp: u32,
+    content: Vec<u8>,
+}
+
+impl ReceiveBuffer {
+    pub fn new(initial_sequence_number: SeqNo, capacity: usize) -> Self {
+        Self {
+            next: initial_sequence_number,
+            capacity,
+            packets: BTreeMap::new(),
+            next_key: 0,
+            dropped: 0,
+        }
+    }
+
+    /// `timestamp` of the packet, that carried `data`
+    pub fn insert(&mut self, timestamp: u32, data: &DataPacketInfo) {
+        // Already released (duplicate or late retransmission)
+        let Ok(offset) = u64::try_from(self.next.distance(data.packet_sequence_number)) else {
+            return;
+        };
+
+        self.packets
+            .entry(self.next_key + offset)
+            .or_insert_with(|| Received {
+                position: data.position,
+                timestamp,
+                content: data.content.clone(),
+            });
+    }
+
+    /// Get next complete message, if there is one
+    pub fn pop_message(&mut self) -> Option<Vec<u8>> {
+        self.pop(|_| true, false)
+    }
+
+    /// Get next complete message, once it's due at `now` (TSBPD)
+    ///
+    /// `due` maps the timestamp of its first packet to the time it should be played.
+    /// Missing packets are given up on, once the packet after them is due.
+    pub fn pop_due(
+        &mut self,
+        now: Instant,
+        mut due: impl FnMut(u32) -> Instant,
+    ) -> Option<Vec<u8>> {
+        self.pop(|timestamp| due(timestamp) <= now, true)
+    }
+
+    fn pop(&mut self, mut is_due: impl FnMut(u32) -> bool, drop_late: bool) -> Option<Vec<u8>> {
+        loop {
+            self.drop_orphans();
+            if let Some(head) = self.packets.get(&self.next_key)
+                && !is_due(head.timestamp)
+            {
+                return None;
+            }
+
+            if let Some(message) = self.try_pop_message() {
+                return Some(message);
+            }
+
+            if drop_late && self.skip_late(&mut is_due) {
+                continue;
+            }
+
+            if self.packets.len() < self.capacity {
+                return None;
+            }
+
+            // Give up on the gap (or on the incomplete message at the head)
+            let (&first, _) = self.packets.first_key_value()?;
+            if first == self.next_key {
+                self.packets.remove(&first);
+                self.advance_to(first + 1);
+            } else {
+                tracing::warn!("Dropping packets {}..{}", self.next, self.number(first));
+                self.advance_to(first);
+            }
+        }
+    }
+
+    fn try_pop_message(&mut self) -> Option<Vec<u8>> {
+        self.drop_orphans();
+
+        let head = self.packets.get(&self.next_key)?;
+
+        let end = match head.position {
+            PacketPosition::Single => self.next_key,
+            _ => (self.next_key + 1..)
+                .take_while(|key| self.packets.contains_key(key))
+                .find(|key| matches!(self.packets[key].position, PacketPosition::Last))?,
+        };
+
+        let mut message = Vec::new();
+        for key in self.next_key..=end {
+            message.extend(self.packets.remove(&key)?.content);
+        }
+        self.advance_to(end + 1);
+
+        Some(message)
+    }
+
+    /// # of packets, that can still be buffered
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn available(&self) -> u32 {
+        self.capacity.saturating_sub(self.packets.len()) as u32
+    }
+
+    /// Ranges (inclusive) of missing packets
+    pub fn loss_list(&self) -> Vec<(SeqNo, SeqNo)> {
+        let mut res = Vec::new();
+        let mut expected = self.next_key;
+
+        for &key in self.packets.keys() {
+            if key > expected {
+                res.push((self.number(expected), self.number(key - 1)));
+            }
+            expected = key + 1;
+        }
+
+        res
+    }
+
+    /// First packet, that wasn't received, if there is a gap
+    pub fn first_missing(&self) -> Option<SeqNo> {
+        self.first_missing_key().map(|key| self.number(key))
+    }
+
+    fn first_missing_key(&self) -> Option<u64> {
+        let mut expected = self.next_key;
+
+        for &key in self.packets.keys() {
+            if key > expected {
+                return Some(expected);
+            }
+            expected = key + 1;
+        }
+
+        None
+    }
+
+    /// Skip the first gap (and the incomplete message before it), once the packet after it is due
+    fn skip_late(&mut self, mut is_due: impl FnMut(u32) -> bool) -> bool {
+        let Some(missing) = self.first_missing_key() else {
+            return false;
+        };
+        let Some((&key, next)) = self.packets.range(missing..).next() else {
+            return false;
+        };
+        if !is_due(next.timestamp) {
+            return false;
+        }
+
+        tracing::debug!(
+            "Dropping packets {}..{} (too late)",
+            self.number(missing),
+            self.number(key)
+        );
+        self.dropped += key - missing;
+        self.packets = self.packets.split_off(&key);
+        self.advance_to(key);
+
+        true
+    }
+
+    /// # of packets, that were given up on, as they didn't arrive in time
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Stop waiting for the packets `first..=last` (the sender dropped them)
+    ///
+    /// Only the gap at the head is skipped, later ones may still be filled.
+    pub fn skip(&mut self, first: SeqNo, last: SeqNo) {
+        if first > self.next || last < self.next {
+            return;
+        }
+
+        #[allow(clippy::cast_sign_loss)]
+        let end = self.next_key + self.next.distance(last) as u64 + 1;
+        let key = self.packets.keys().next().map_or(end, |&key| key.min(end));
+        if key > self.next_key {
+            tracing::debug!("Skipping packets {}..{}", self.next, self.number(key));
+            self.advance_to(key);
+        }
+    }
+
+    /// Remove packets at the head, that can't start a message
+    fn drop_orphans(&mut self) {
+        while let Some(Received {
+            position: PacketPosition::Middle | PacketPosition::Last,
+            ..
+        }) = self.packets.get(&self.next_key)
+        {
+            self.packets.remove(&self.next_key);
+            self.advance_to(self.next_key + 1);
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn number(&self, key: u64) -> SeqNo {
+        self.next + (key - self.next_key) as u32
+    }
+
+    fn advance_to(&mut self, key: u64) {
+        self.next = self.number(key);
+        self.next_key = key;
+    }
+}
+
+/// Data packet and the timestamp it was first sent with
+pub type Timestamped = (u32, DataPacketInfo);
+
+/// Data packets sent to the peer, kept for retransmission until they are acknowledged
+///
+/// Live mode: packets, that are too late to be played (see [`SendBuffer::drop_late`])
+/// or beyond `capacity`, are dropped without being acknowledged.
+#[derive(Debug)]
+pub struct SendBuffer {
+    /// Sequence number of the next new packet
+    next: SeqNo,
+    next_message: MsgNo,
+    capacity: usize,
+    /// Consecutive, from the oldest unacknowledged packet
+    packets: VecDeque<Sent>,
+}
+
+#[derive(Debug)]
+struct Sent {
+    /// Not encrypted
+    data: DataPacketInfo,
+    timestamp: u32,
+    /// First sent
+    queued: Instant,
+    /// Last sent again
+    retransmitted: Option<Instant>,
+}
+
+impl SendBuffer {
+    pub fn new(initial_sequence_number: SeqNo, capacity: usize) -> Self {
+        Self {
+            next: initial_sequence_number,
+            next_message: MsgNo::new(1),
+            capacity,
+            packets: VecDeque::new(),
+        }
+    }
+
+    /// Split `message` into packets of at most `payload_size` bytes and keep them
+    ///
+    /// Returns the packets to send. Drops the oldest packets beyond capacity.
+    pub fn push(
+        &mut self,
+        message: &[u8],
+        payload_size: usize,
+        timestamp: u32,
+        now: Instant,
+    ) -> Vec<DataPacketInfo> {
+        let chunks: Vec<_> = match message.is_empty() {
+            true => vec![message],
+            false => message.chunks(payload_size.max(1)).collect(),
+        };
+        let last = chunks.len() - 1;
+        let message_number = self.next_message;
+        self.next_message = message_number.next();
+
+        let mut res = Vec::new();
+        for (k, chunk) in chunks.into_iter().enumerate() {
+            let data = DataPacketInfo {
+                packet_sequence_number: self.next,
+                position: match (k == 0, k == last) {
+                    (true, true) => PacketPosition::Single,
+                    (true, false) => PacketPosition::First,
+                    (false, true) => PacketPosition::Last,
+                    (false, false) => PacketPosition::Middle,
+                },
+                order: false,
+                encryption: EncryptionFlag::NoEncryption,
+                retransmitted: false,
+                message_number,
+                content: chunk.to_vec(),
+            };
+            self.next = self.next.next();
+
+            self.packets.push_back(Sent {
+                data: data.clone(),
+                timestamp,
+                queued: now,
+                retransmitted: None,
+            });
+            res.push(data);
+        }
+
+        let excess = self.packets.len().saturating_sub(self.capacity);
+        if excess > 0 {
+            tracing::warn!("Send buffer full, dropping {excess} packets");
+            self.packets.drain(..excess);
+        }
+
+        res
+    }
+
+    /// Forget packets before `next` (the peer has them)
+    pub fn acknowledge(&mut self, next: SeqNo) {
+        while self
+            .packets
+            .front()
+            .is_some_and(|sent| sent.data.packet_sequence_number < next)
+        {
+            self.packets.pop_front();
+        }
+    }
+
+    /// Packets of a loss report `from..=to` (with timestamps), that weren't sent again
+    /// in the last `interval`
+    ///
+    /// Also returns the part of the range, that is no longer buffered (if any).
+    pub fn retransmit(
+        &mut self,
+        from: SeqNo,
+        to: SeqNo,
+        now: Instant,
+        interval: Duration,
+    ) -> (Vec<Timestamped>, Option<(SeqNo, SeqNo)>) {
+        let first = self
+            .packets
+            .front()
+            .map_or(self.next, |sent| sent.data.packet_sequence_number);
+        // Never sent
+        let to = if to < self.next { to } else { self.next - 1 };
+
+        let gone = (from < first && from <= to).then(|| {
+            let last = if to < first { to } else { first - 1 };
+            (from, last)
+        });
+        let from = if from < first { first } else { from };
+
+        let mut res = Vec::new();
+        for number in from.range_inclusive(to) {
+            let Ok(index) = usize::try_from(first.distance(number)) else {
+                continue;
+            };
+            let Some(sent) = self.packets.get_mut(index) else {
+                break;
+            };
+            if sent
+                .retransmitted
+                .is_some_and(|at| now.saturating_duration_since(at) < interval)
+            {
+                continue;
+            }
+            sent.retransmitted = Some(now);
+
+            let mut data = sent.data.clone();
+            data.retransmitted = true;
+            res.push((sent.timestamp, data));
+        }
+
+        (res, gone)
+    }
+
+    /// Drop packets first sent before `deadline`
+    ///
+    /// Returns the # of dropped packets.
+    pub fn drop_late(&mut self, deadline: Instant) -> usize {
+        let count = self
+            .packets
+            .iter()
+            .take_while(|sent| sent.queued < deadline)
+            .count();
+        self.packets.drain(..count);
+
+        count
+    }
+
+    /// # of unacknowledged packets
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{packet::data::EncryptionFlag, seq::MsgNo};
+
+    fn data(number: u32, position: PacketPosition, content: &[u8]) -> DataPacketInfo {
+        DataPacketInfo {
+            packet_sequence_number: SeqNo::new(number),
+            position,
+            order: false,
+            encryption: EncryptionFlag::NoEncryption,
+            retransmitted: false,
+            message_number: MsgNo::new(1),
+            content: content.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut buf = ReceiveBuffer::new(SeqNo::new(10), 8);
+
+        buf.insert(0, &data(11, PacketPosition::Single, b"b"));
+        assert_eq!(buf.pop_message(), None);
+
+        buf.insert(0, &data(10, PacketPosition::Single, b"a"));
+        assert_eq!(buf.pop_message().as_deref(), Some(&b"a"[..]));
+        assert_eq!(buf.pop_message().as_deref(), Some(&b"b"[..]));
+        assert_eq!(buf.pop_message(), None);
+    }
+
+    #[test]
+    fn test_message_assembly() {
+        let mut buf = ReceiveBuffer::new(SeqNo::new(0), 8);
+
+        buf.insert(0, &data(0, PacketPosition::First, b"a"));
+        buf.insert(0, &data(2, PacketPosition::Last, b"c"));
+        assert_eq!(buf.pop_message(), None);
+
+        buf.insert(0, &data(1, PacketPosition::Middle, b"b"));
+        assert_eq!(buf.pop_message().as_deref(), Some(&b"abc"[..]));
+    }
+
+    #[test]
+    fn test_tsbpd() {
+        let start = Instant::now();
+        let due = |timestamp: u32| start + Duration::from_micros(timestamp.into());
+        let mut buf = ReceiveBuffer::new(SeqNo::new(0), 8);
+
+        buf.insert(1000, &data(0, PacketPosition::First, b"a"));
+        buf.insert(1000, &data(1, PacketPosition::Last, b"b"));
+        buf.insert(2000, &data(2, PacketPosition::Single, b"c"));
+
+        assert_eq!(buf.pop_due(start, due), None);
+        let now = start + Duration::from_micros(1000);
+        assert_eq!(buf.pop_due(now, due).as_deref(), Some(&b"ab"[..]));
+        assert_eq!(buf.pop_due(now, due), None);
+        let now = start + Duration::from_micros(2000);
+        assert_eq!(buf.pop_due(now, due).as_deref(), Some(&b"c"[..]));
+    }
+
+    #[test]
+    fn test_drop_late() {
+        let start = Instant::now();
+        let due = |timestamp: u32| start + Duration::from_micros(timestamp.into());
+        let mut buf = ReceiveBuffer::new(SeqNo::new(0), 8);
+
+        // Incomplete message, then a gap
+        buf.insert(1000, &data(0, PacketPosition::First, b"a"));
+        buf.insert(3000, &data(3, PacketPosition::Single, b"d"));
+        buf.insert(4000, &data(5, PacketPosition::Single, b"f"));
+
+        let now = start + Duration::from_micros(2000);
+        assert_eq!(buf.pop_due(now, due), None);
+        assert_eq!(buf.dropped(), 0);
+
+        let now = start + Duration::from_micros(3000);
+        assert_eq!(buf.pop_due(now, due).as_deref(), Some(&b"d"[..]));
+        assert_eq!(buf.pop_due(now, due), None);
+        assert_eq!(buf.dropped(), 2);
+
+        // Late packet is ignored
+        buf.insert(2000, &data(2, PacketPosition::Single, b"c"));
+        let now = start + Duration::from_micros(4000);
+        assert_eq!(buf.pop_due(now, due).as_deref(), Some(&b"f"[..]));
+        assert_eq!(buf.dropped(), 3);
+    }
+
+    #[test]
+    fn test_skip_gap_when_full() {
+        let mut buf = ReceiveBuffer::new(SeqNo::new(0), 2);
+
+        buf.insert(0, &data(1, PacketPosition::Single, b"b"));
+        assert_eq!(buf.pop_message(), None);
+
+        buf.insert(0, &data(2, PacketPosition::Single, b"c"));
+        assert_eq!(buf.pop_message().as_deref(), Some(&b"b"[..]));
+        assert_eq!(buf.pop_message().as_deref(), Some(&b"c"[..]));
+
+        // Late packet is ignored
+        buf.insert(0, &data(0, PacketPosition::Single, b"a"));
+        assert_eq!(buf.pop_message(), None);
+    }
+
+    #[test]
+    fn test_loss_list() {
+        let mut buf = ReceiveBuffer::new(SeqNo::new(0), 8);
+
+        buf.insert(0, &data(2, PacketPosition::Single, b""));
+        buf.insert(0, &data(3, PacketPosition::Single, b""));
+        buf.insert(0, &data(6, PacketPosition::Single, b""));
+
+        let loss_list: Vec<_> = buf
+            .loss_list()
+            .into_iter()
+            .map(|(from, to)| (from.get(), to.get()))
+            .collect();
+        assert_eq!(loss_list, vec![(0, 1), (4, 5)]);
+        assert_eq!(buf.first_missing(), Some(SeqNo::new(0)));
+
+        // Dropped by the sender
+        buf.skip(SeqNo::new(4), SeqNo::new(5));
+        assert_eq!(buf.first_missing(), Some(SeqNo::new(0)));
+        buf.skip(SeqNo::new(0), SeqNo::new(5));
+        assert_eq!(buf.first_missing(), Some(SeqNo::new(4)));
+        assert!(buf.pop_message().is_some());
+    }
+
+    #[test]
+    fn test_wraparound() {
+        let max = SeqNo::MAX.get();
+        let mut buf = ReceiveBuffer::new(SeqNo::new(max - 1), 8);
+
+        buf.insert(0, &data(1, PacketPosition::Last, b"d"));
+        buf.insert(0, &data(max - 1, PacketPosition::First, b"a"));
+        assert_eq!(buf.loss_list(), vec![(SeqNo::MAX, SeqNo::new(0))]);
+
+        buf.insert(0, &data(0, PacketPosition::Middle, b"c"));
+        buf.insert(0, &data(max, PacketPosition::Middle, b"b"));
+        assert_eq!(buf.pop_message().as_deref(), Some(&b"abcd"[..]));
+
+        // Late packet from before the wrap is ignored
+        buf.insert(0, &data(max, PacketPosition::Single, b"b"));
+        buf.insert(0, &data(2, PacketPosition::Single, b"e"));
+        assert_eq!(buf.pop_message().as_deref(), Some(&b"e"[..]));
+    }
+
+    #[test]
+    fn test_send_buffer() {
+        let now = Instant::now();
+        let rtt = Duration::from_millis(100);
+        let mut buf = SendBuffer::new(SeqNo::new(10), 4);
+
+        let sent = buf.push(b"abcde", 2, 0, now);
+        let numbers: Vec<_> = sent
+            .iter()
+            .map(|data| (data.packet_sequence_number.get(), data.position))
+            .collect();
+        assert_eq!(
+            numbers,
+            vec![
+                (10, PacketPosition::First),
+                (11, PacketPosition::Middle),
+                (12, PacketPosition::Last)
+            ]
+        );
+        assert!(sent.iter().all(|data| data.message_number == MsgNo::new(1)));
+        buf.push(b"f", 2, 1000, now + Duration::from_millis(1));
+
+        buf.acknowledge(SeqNo::new(11));
+        let (again, gone) = buf.retransmit(SeqNo::new(9), SeqNo::new(20), now, rtt);
+        let numbers: Vec<_> = again
+            .iter()
+            .map(|(_, data)| data.packet_sequence_number.get())
+            .collect();
+        assert_eq!(numbers, vec![11, 12, 13]);
+        assert!(again.iter().all(|(_, data)| data.retransmitted));
+        assert_eq!(again[2].0, 1000);
+        assert_eq!(gone, Some((SeqNo::new(9), SeqNo::new(10))));
+
+        // Not again within the interval
+        let (again, _) = buf.retransmit(SeqNo::new(11), SeqNo::new(11), now + rtt / 2, rtt);
+        assert!(again.is_empty());
+        let (again, _) = buf.retransmit(SeqNo::new(11), SeqNo::new(11), now + rtt, rtt);
+        assert_eq!(again.len(), 1);
+
+        assert_eq!(buf.drop_late(now + Duration::from_millis(1)), 2);
+        assert_eq!(buf.len(), 1);
+
+        for _ in 0..4 {
+            buf.push(b"g", 2, 0, now);
+        }
+        assert_eq!(buf.len(), 4);
+    }
+}
@@ -0,0 +1,92 @@
+//! Datagram capture (pcapng) and offline replay
+//!
+//! [`crate::server::Server::record`] writes every datagram the listener receives and sends,
+//! framed as UDP over IP, so captures open in Wireshark.
+//! [`replay::Replayer`] feeds a capture back through [`crate::connection::Connection`].
+//! [`read`] takes pcapng as well as classic pcap files (e.g. from tcpdump).
+
+use std::{
+    io::{self, Read, Write},
+    net::SocketAddr,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::{Result, bail};
+
+pub mod pcap;
+pub mod pcapng;
+pub mod replay;
+
+/// Larger packets (or blocks) are taken as corruption, instead of being allocated (bytes)
+const MAX_RECORD_LENGTH: usize = 0x0400_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Received by the listener
+    Inbound,
+    /// Sent by the listener
+    Outbound,
+}
+
+/// Captured datagram
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub time: SystemTime,
+    /// Unknown if the capture didn't say
+    pub direction: Option<Direction>,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    /// UDP payload
+    pub data: Vec<u8>,
+}
+
+/// Writes datagrams of one listener to a capture
+///
+/// Every datagram is written right away (one `write_all` each),
+/// so the capture is usable even if the process dies.
+pub struct Recorder {
+    local: SocketAddr,
+    writer: Mutex<pcapng::Writer<Box<dyn Write + Send>>>,
+}
+
+impl Recorder {
+    pub fn new(local: SocketAddr, writer: impl Write + Send + 'static) -> Result<Self> {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+
+        Ok(Self {
+            local,
+            writer: Mutex::new(pcapng::Writer::new(writer)?),
+        })
+    }
+
+    /// Failures are logged, capturing must not stop the listener
+    pub fn record(&self, time: SystemTime, direction: Direction, peer: SocketAddr, data: &[u8]) {
+        let (src, dst) = match direction {
+            Direction::Inbound => (peer, self.local),
+            Direction::Outbound => (self.local, peer),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writer.write_datagram(time, Some(direction), src, dst, data) {
+            tracing::warn!("Capture failed: {e}");
+        }
+    }
+}
+
+/// Datagrams of a pcap or pcapng file, by the magic number at its start
+pub fn read(mut inner: impl Read + 'static) -> Result<Box<dyn Iterator<Item = Result<Record>>>> {
+    let mut magic = [0; 4];
+    inner.read_exact(&mut magic)?;
+    let inner = io::Cursor::new(magic).chain(inner);
+
+    Ok(match u32::from_le_bytes(magic) {
+        pcapng::block_types::SECTION_HEADER => Box::new(pcapng::Reader::new(inner)),
+        m if [m, m.swap_bytes()]
+            .iter()
+            .any(|m| [pcap::MAGIC, pcap::MAGIC_NANOS].contains(m)) =>
+        {
+            Box::new(pcap::Reader::new(inner)?)
+        }
+        m => bail!("Not a pcap or pcapng file (magic 0x{m:08X})"),
+    })
+}
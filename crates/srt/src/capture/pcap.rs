@@ -0,0 +1,153 @@
+//! Classic pcap files of UDP datagrams (read only)
+//!
+//! Same link types as [`super::pcapng::Reader`]; there are no directions in this format.
+//!
+//! <https://www.ietf.org/archive/id/draft-ietf-opsawg-pcap-04.html>
+
+use std::{
+    io::{self, Read},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, bail};
+
+use super::{MAX_RECORD_LENGTH, Record, pcapng::unframe};
+
+/// Microsecond timestamps
+pub(super) const MAGIC: u32 = 0xA1B2_C3D4;
+/// Nanosecond timestamps
+pub(super) const MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+
+/// Reads UDP datagrams from a pcap file
+pub struct Reader<R: Read> {
+    inner: R,
+    big_endian: bool,
+    nanos: bool,
+    link_type: u16,
+}
+
+impl<R: Read> Reader<R> {
+    /// Reads the file header
+    pub fn new(mut inner: R) -> Result<Self> {
+        let mut header = [0; 24];
+        inner.read_exact(&mut header)?;
+
+        let magic = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let (big_endian, magic) = match magic {
+            MAGIC | MAGIC_NANOS => (false, magic),
+            _ if matches!(magic.swap_bytes(), MAGIC | MAGIC_NANOS) => (true, magic.swap_bytes()),
+            _ => bail!("Bad pcap magic: 0x{magic:08X}"),
+        };
+
+        let mut reader = Self {
+            inner,
+            big_endian,
+            nanos: magic == MAGIC_NANOS,
+            link_type: 0,
+        };
+        // Upper bits carry the FCS length
+        reader.link_type = (reader.u32(&header[20..24]) & 0xFFFF) as u16;
+
+        Ok(reader)
+    }
+
+    /// Next UDP datagram, `None` at the end of the file
+    pub fn next_record(&mut self) -> Result<Option<Record>> {
+        loop {
+            let mut header = [0; 16];
+            match self.inner.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+
+            let seconds = self.u32(&header[..4]);
+            let fraction = self.u32(&header[4..8]);
+            let captured = self.u32(&header[8..12]) as usize;
+            if captured > MAX_RECORD_LENGTH {
+                bail!("Bad packet length: {captured}");
+            }
+            let mut frame = vec![0; captured];
+            self.inner.read_exact(&mut frame)?;
+
+            let Some((src, dst, data)) = unframe(self.link_type, &frame) else {
+                continue;
+            };
+
+            let nanos = if self.nanos {
+                fraction
+            } else {
+                fraction.saturating_mul(1000)
+            };
+            if nanos >= 1_000_000_000 {
+                bail!("Bad timestamp fraction: {fraction}");
+            }
+
+            return Ok(Some(Record {
+                time: SystemTime::UNIX_EPOCH + Duration::new(seconds.into(), nanos),
+                direction: None,
+                src,
+                dst,
+                data: data.to_vec(),
+            }));
+        }
+    }
+
+    fn u32(&self, raw: &[u8]) -> u32 {
+        let raw = [raw[0], raw[1], raw[2], raw[3]];
+        if self.big_endian {
+            u32::from_be_bytes(raw)
+        } else {
+            u32::from_le_bytes(raw)
+        }
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::pcapng;
+
+    #[test]
+    fn test_read() {
+        let record = Record {
+            time: SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456),
+            direction: None,
+            src: "198.51.100.7:5000".parse().unwrap(),
+            dst: "203.0.113.1:9000".parse().unwrap(),
+            data: b"srt".to_vec(),
+        };
+
+        // Borrow the IPv4 framing from a pcapng capture (EPB data starts 28 bytes into the block)
+        let mut writer = pcapng::Writer::new(Vec::new()).unwrap();
+        writer.write(&record).unwrap();
+        let pcapng = writer.into_inner();
+        let frame = &pcapng[28 + 20 + 28..][..20 + 8 + 3];
+
+        // Big-endian, microseconds, LINKTYPE_RAW
+        let mut raw = Vec::new();
+        raw.extend(MAGIC.to_be_bytes());
+        raw.extend([0, 2, 0, 4]);
+        raw.extend([0; 8]);
+        raw.extend(65535u32.to_be_bytes());
+        raw.extend(101u32.to_be_bytes());
+        raw.extend(1_700_000_000u32.to_be_bytes());
+        raw.extend(123_456u32.to_be_bytes());
+        raw.extend([(frame.len() as u32).to_be_bytes(); 2].concat());
+        raw.extend(frame);
+
+        let read: Vec<_> = Reader::new(&raw[..])
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read, [record]);
+    }
+}
@@ -0,0 +1,540 @@
+//! pcapng files of UDP datagrams
+//!
+//! Written as raw IP (`LINKTYPE_RAW`), with the direction in `epb_flags`.
+//! Reading also accepts Ethernet and Linux cooked captures (e.g. from tcpdump),
+//! other blocks and non-UDP packets are skipped.
+//!
+//! <https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-02.html>
+
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, bail};
+
+use super::{Direction, MAX_RECORD_LENGTH, Record};
+
+pub(super) mod block_types {
+    pub const INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+    pub const ENHANCED_PACKET: u32 = 0x0000_0006;
+    pub const SECTION_HEADER: u32 = 0x0A0D_0D0A;
+}
+
+pub(super) mod link_types {
+    pub const ETHERNET: u16 = 1;
+    pub const RAW: u16 = 101;
+    pub const LINUX_SLL: u16 = 113;
+    pub const IPV4: u16 = 228;
+    pub const IPV6: u16 = 229;
+}
+
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+const OPT_END: u16 = 0;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_EPB_FLAGS: u16 = 2;
+
+const IPPROTO_UDP: u8 = 17;
+
+/// Writes one section with one raw IP interface (microsecond timestamps)
+pub struct Writer<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Writer<W> {
+    /// Writes the section header and interface description
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        let mut shb = Vec::new();
+        shb.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend(1u16.to_le_bytes()); // Major version
+        shb.extend(0u16.to_le_bytes()); // Minor version
+        shb.extend((-1i64).to_le_bytes()); // Section length (unknown)
+        inner.write_all(&block(block_types::SECTION_HEADER, &shb))?;
+
+        let mut idb = Vec::new();
+        idb.extend(link_types::RAW.to_le_bytes());
+        idb.extend(0u16.to_le_bytes()); // Reserved
+        idb.extend(0u32.to_le_bytes()); // Snap length (unlimited)
+        inner.write_all(&block(block_types::INTERFACE_DESCRIPTION, &idb))?;
+
+        Ok(Self { inner })
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        self.write_datagram(
+            record.time,
+            record.direction,
+            record.src,
+            record.dst,
+            &record.data,
+        )
+    }
+
+    pub fn write_datagram(
+        &mut self,
+        time: SystemTime,
+        direction: Option<Direction>,
+        src: SocketAddr,
+        dst: SocketAddr,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let frame = frame(src, dst, data);
+        let micros = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        #[allow(clippy::cast_possible_truncation)]
+        let (high, low) = ((micros >> 32) as u32, micros as u32);
+        let len = u32::try_from(frame.len()).map_err(io::Error::other)?;
+
+        let mut epb = Vec::new();
+        epb.extend(0u32.to_le_bytes()); // Interface ID
+        epb.extend(high.to_le_bytes());
+        epb.extend(low.to_le_bytes());
+        epb.extend(len.to_le_bytes()); // Captured length
+        epb.extend(len.to_le_bytes()); // Original length
+        epb.extend(&frame);
+        epb.resize(epb.len().next_multiple_of(4), 0);
+
+        if let Some(direction) = direction {
+            let flags: u32 = match direction {
+                Direction::Inbound => 1,
+                Direction::Outbound => 2,
+            };
+            epb.extend(OPT_EPB_FLAGS.to_le_bytes());
+            epb.extend(4u16.to_le_bytes());
+            epb.extend(flags.to_le_bytes());
+            epb.extend(OPT_END.to_le_bytes());
+            epb.extend(0u16.to_le_bytes());
+        }
+
+        self.inner
+            .write_all(&block(block_types::ENHANCED_PACKET, &epb))
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Block with type and total length around `body`
+fn block(r#type: u32, body: &[u8]) -> Vec<u8> {
+    #[allow(clippy::cast_possible_truncation)]
+    let total = (12 + body.len()) as u32;
+
+    let mut res = Vec::with_capacity(total as usize);
+    res.extend(r#type.to_le_bytes());
+    res.extend(total.to_le_bytes());
+    res.extend(body);
+    res.extend(total.to_le_bytes());
+    res
+}
+
+/// IP and UDP headers around `data`
+///
+/// Addresses of different families are both written as IPv6 (IPv4-mapped).
+#[allow(clippy::cast_possible_truncation)]
+fn frame(src: SocketAddr, dst: SocketAddr, data: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + data.len()) as u16;
+    let mut udp = Vec::with_capacity(udp_len.into());
+    udp.extend(src.port().to_be_bytes());
+    udp.extend(dst.port().to_be_bytes());
+    udp.extend(udp_len.to_be_bytes());
+    udp.extend(0u16.to_be_bytes()); // Checksum, set below
+    udp.extend(data);
+
+    let mut res = Vec::new();
+    match (src.ip().to_canonical(), dst.ip().to_canonical()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let pseudo = [&src.octets()[..], &dst.octets(), &[0, IPPROTO_UDP]].concat();
+            set_udp_checksum(&mut udp, &pseudo);
+
+            let mut ip = Vec::with_capacity(20);
+            ip.push(0x45); // Version, IHL
+            ip.push(0); // DSCP, ECN
+            ip.extend((20 + udp_len).to_be_bytes());
+            ip.extend(0u16.to_be_bytes()); // Identification
+            ip.extend(0x4000u16.to_be_bytes()); // Don't fragment
+            ip.push(64); // TTL
+            ip.push(IPPROTO_UDP);
+            ip.extend(0u16.to_be_bytes()); // Checksum
+            ip.extend(src.octets());
+            ip.extend(dst.octets());
+            let checksum = !checksum(&ip);
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+            res.extend(ip);
+        }
+        (src, dst) => {
+            let (src, dst) = (to_ipv6(src), to_ipv6(dst));
+            let pseudo = [&src.octets()[..], &dst.octets(), &[0, IPPROTO_UDP]].concat();
+            set_udp_checksum(&mut udp, &pseudo);
+
+            res.extend(0x6000_0000u32.to_be_bytes()); // Version, traffic class, flow label
+            res.extend(udp_len.to_be_bytes());
+            res.push(IPPROTO_UDP);
+            res.push(64); // Hop limit
+            res.extend(src.octets());
+            res.extend(dst.octets());
+        }
+    }
+
+    res.extend(udp);
+    res
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// `pseudo` is the pseudo-header without the UDP length
+fn set_udp_checksum(udp: &mut [u8], pseudo: &[u8]) {
+    let sum = [pseudo, &udp[4..6], udp].concat();
+    let checksum = match !checksum(&sum) {
+        // 0 means "no checksum"
+        0 => 0xFFFF,
+        checksum => checksum,
+    };
+    udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// One's complement sum of 16-bit words
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)])))
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    let sum = sum as u16;
+    sum
+}
+
+struct Interface {
+    link_type: u16,
+    /// Timestamp units per second
+    units: u64,
+}
+
+/// Reads UDP datagrams from a pcapng file
+pub struct Reader<R: Read> {
+    inner: R,
+    big_endian: bool,
+    interfaces: Vec<Interface>,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            big_endian: false,
+            interfaces: Vec::new(),
+        }
+    }
+
+    /// Next UDP datagram, `None` at the end of the file
+    pub fn next_record(&mut self) -> Result<Option<Record>> {
+        loop {
+            let mut header = [0; 8];
+            match self.inner.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+
+            let r#type = u32::from_le_bytes(header[..4].try_into().unwrap());
+            if r#type == block_types::SECTION_HEADER {
+                // Byte order of the section follows the type (which reads the same either way)
+                let mut magic = [0; 4];
+                self.inner.read_exact(&mut magic)?;
+                self.big_endian = match u32::from_le_bytes(magic) {
+                    BYTE_ORDER_MAGIC => false,
+                    magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                    magic => bail!("Bad byte-order magic: 0x{magic:08X}"),
+                };
+                self.interfaces.clear();
+
+                let total = self.u32(&header[4..8]) as usize;
+                let Some(rest) = total.checked_sub(12) else {
+                    bail!("Bad section header length: {total}");
+                };
+                self.skip(rest)?;
+                continue;
+            }
+
+            let total = self.u32(&header[4..8]) as usize;
+            if !(12..=MAX_RECORD_LENGTH).contains(&total) || !total.is_multiple_of(4) {
+                bail!("Bad block length: {total}");
+            }
+            let mut body = vec![0; total - 8];
+            self.inner.read_exact(&mut body)?;
+            body.truncate(total - 12);
+
+            match self.u32(&header[..4]) {
+                block_types::INTERFACE_DESCRIPTION => self.read_interface(&body)?,
+                block_types::ENHANCED_PACKET => {
+                    if let Some(record) = self.read_packet(&body)? {
+                        return Ok(Some(record));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn read_interface(&mut self, body: &[u8]) -> Result<()> {
+        if body.len() < 8 {
+            bail!("Interface description truncated");
+        }
+
+        let mut interface = Interface {
+            link_type: self.u16(&body[..2]),
+            units: 1_000_000,
+        };
+        for (code, value) in self.options(&body[8..]) {
+            if code == OPT_IF_TSRESOL && value.len() == 1 {
+                let exponent = u32::from(value[0] & 0x7F);
+                let units = if value[0] & 0x80 == 0 {
+                    10u64.checked_pow(exponent)
+                } else {
+                    1u64.checked_shl(exponent)
+                };
+                let Some(units) = units.filter(|&units| units <= 1_000_000_000) else {
+                    bail!("Unsupported timestamp resolution: 0x{:02X}", value[0]);
+                };
+                interface.units = units;
+            }
+        }
+
+        self.interfaces.push(interface);
+
+        Ok(())
+    }
+
+    fn read_packet(&self, body: &[u8]) -> Result<Option<Record>> {
+        if body.len() < 20 {
+            bail!("Enhanced packet block truncated");
+        }
+
+        let interface_id = self.u32(&body[..4]) as usize;
+        let Some(interface) = self.interfaces.get(interface_id) else {
+            bail!("Unknown interface: {interface_id}");
+        };
+        let ticks = u64::from(self.u32(&body[4..8])) << 32 | u64::from(self.u32(&body[8..12]));
+        let captured = self.u32(&body[12..16]) as usize;
+        let Some(frame) = body.get(20..20 + captured) else {
+            bail!("Packet data truncated");
+        };
+
+        let direction = self
+            .options(&body[(20 + captured).next_multiple_of(4).min(body.len())..])
+            .find(|(code, value)| *code == OPT_EPB_FLAGS && value.len() == 4)
+            .and_then(|(_, value)| match self.u32(value) & 0b11 {
+                1 => Some(Direction::Inbound),
+                2 => Some(Direction::Outbound),
+                _ => None,
+            });
+
+        let Some((src, dst, data)) = unframe(interface.link_type, frame) else {
+            return Ok(None);
+        };
+
+        let fraction =
+            u128::from(ticks % interface.units) * 1_000_000_000 / u128::from(interface.units);
+        #[allow(clippy::cast_possible_truncation)]
+        let offset = Duration::new(ticks / interface.units, fraction as u32);
+
+        Ok(Some(Record {
+            time: SystemTime::UNIX_EPOCH + offset,
+            direction,
+            src,
+            dst,
+            data: data.to_vec(),
+        }))
+    }
+
+    /// (code, value) pairs
+    fn options<'a>(&self, mut raw: &'a [u8]) -> impl Iterator<Item = (u16, &'a [u8])> {
+        let big_endian = self.big_endian;
+        let read = move |raw: &[u8]| {
+            let raw = [raw[0], raw[1]];
+            if big_endian {
+                u16::from_be_bytes(raw)
+            } else {
+                u16::from_le_bytes(raw)
+            }
+        };
+
+        std::iter::from_fn(move || {
+            if raw.len() < 4 {
+                return None;
+            }
+            let (code, len) = (read(&raw[..2]), usize::from(read(&raw[2..4])));
+            if code == OPT_END {
+                return None;
+            }
+
+            let value = raw.get(4..4 + len)?;
+            raw = raw.get((4 + len).next_multiple_of(4)..).unwrap_or_default();
+            Some((code, value))
+        })
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        io::copy(&mut (&mut self.inner).take(n as u64), &mut io::sink())?;
+        Ok(())
+    }
+
+    fn u16(&self, raw: &[u8]) -> u16 {
+        let raw = [raw[0], raw[1]];
+        if self.big_endian {
+            u16::from_be_bytes(raw)
+        } else {
+            u16::from_le_bytes(raw)
+        }
+    }
+
+    fn u32(&self, raw: &[u8]) -> u32 {
+        let raw = [raw[0], raw[1], raw[2], raw[3]];
+        if self.big_endian {
+            u32::from_be_bytes(raw)
+        } else {
+            u32::from_le_bytes(raw)
+        }
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// UDP addresses and payload of a link-layer frame (`None` if it's something else)
+pub(super) fn unframe(link_type: u16, frame: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let ip = match link_type {
+        link_types::RAW | link_types::IPV4 | link_types::IPV6 => frame,
+        link_types::ETHERNET => {
+            let mut ether_type = u16::from_be_bytes(frame.get(12..14)?.try_into().ok()?);
+            let mut offset = 14;
+            // 802.1Q tags
+            while ether_type == 0x8100 || ether_type == 0x88A8 {
+                ether_type =
+                    u16::from_be_bytes(frame.get(offset + 2..offset + 4)?.try_into().ok()?);
+                offset += 4;
+            }
+            frame.get(offset..)?
+        }
+        link_types::LINUX_SLL => frame.get(16..)?,
+        _ => return None,
+    };
+
+    let (src, dst, udp) = match ip.first()? >> 4 {
+        4 => {
+            let ihl = usize::from(ip[0] & 0x0F) * 4;
+            let total = usize::from(u16::from_be_bytes(ip.get(2..4)?.try_into().ok()?));
+            let fragmented = u16::from_be_bytes(ip.get(6..8)?.try_into().ok()?) & 0x3FFF != 0;
+            if *ip.get(9)? != IPPROTO_UDP || fragmented {
+                return None;
+            }
+            let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv4Addr::from(src)),
+                IpAddr::from(Ipv4Addr::from(dst)),
+                ip.get(ihl..total.min(ip.len()))?,
+            )
+        }
+        // Extension headers aren't followed
+        6 if *ip.get(6)? == IPPROTO_UDP => {
+            let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::from(Ipv6Addr::from(src)),
+                IpAddr::from(Ipv6Addr::from(dst)),
+                ip.get(40..)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let src_port = u16::from_be_bytes(udp.get(..2)?.try_into().ok()?);
+    let dst_port = u16::from_be_bytes(udp.get(2..4)?.try_into().ok()?);
+    let len = usize::from(u16::from_be_bytes(udp.get(4..6)?.try_into().ok()?));
+    let data = udp.get(8..len.max(8))?;
+
+    Some((
+        SocketAddr::new(src, src_port),
+        SocketAddr::new(dst, dst_port),
+        data,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+        let records = [
+            Record {
+                time,
+                direction: Some(Direction::Inbound),
+                src: "198.51.100.7:5000".parse().unwrap(),
+                dst: "203.0.113.1:9000".parse().unwrap(),
+                data: b"odd".to_vec(),
+            },
+            Record {
+                time: time + Duration::from_millis(5),
+                direction: Some(Direction::Outbound),
+                src: "[::]:9000".parse().unwrap(),
+                dst: "[::ffff:198.51.100.7]:5000".parse().unwrap(),
+                data: vec![7; 1316],
+            },
+            Record {
+                time: time + Duration::from_millis(6),
+                direction: None,
+                src: "[2001:db8::1]:9000".parse().unwrap(),
+                dst: "[2001:db8::2]:5000".parse().unwrap(),
+                data: Vec::new(),
+            },
+        ];
+
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        for record in &records {
+            writer.write(record).unwrap();
+        }
+        let raw = writer.into_inner();
+
+        // IPv4 header and UDP checksums verify
+        let ip = &raw[28 + 20 + 28..];
+        assert_eq!(checksum(&ip[..20]), 0xFFFF);
+        let pseudo = [&ip[12..20], &[0, IPPROTO_UDP], &ip[24..26]].concat();
+        assert_eq!(checksum(&[&pseudo[..], &ip[20..31]].concat()), 0xFFFF);
+
+        // Mixed families (dual-stack socket) are written as IPv6
+        let read: Vec<_> = Reader::new(&raw[..]).collect::<Result<_>>().unwrap();
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn test_block_too_long() {
+        let mut raw = Writer::new(Vec::new()).unwrap().into_inner();
+        raw.extend(block_types::ENHANCED_PACKET.to_le_bytes());
+        raw.extend(0xFFFF_FFF0u32.to_le_bytes());
+
+        let err = Reader::new(&raw[..]).next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("Bad block length"));
+    }
+}
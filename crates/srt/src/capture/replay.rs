@@ -0,0 +1,183 @@
+//! Offline replay of captured datagrams
+//!
+//! Inbound datagrams go through the same handling as in [`crate::server::Server`],
+//! on a [`ManualClock`] that follows the capture timestamps.
+//! Cookies aren't checked, as the key of the captured listener is unknown.
+
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::SystemTime};
+
+use anyhow::Result;
+
+use super::{Direction, Record};
+use crate::{
+    admission::Admission,
+    clock::{Clock, ManualClock},
+    connection::Connection,
+    group::Groups,
+    options::ListenerOptions,
+    packet::Packet,
+};
+
+/// Replays the datagrams received by one listener
+pub struct Replayer {
+    local: SocketAddr,
+    options: ListenerOptions,
+    groups: Groups,
+    admission: Admission,
+    clock: ManualClock,
+    connections: HashMap<SocketAddr, Connection>,
+    /// Capture time of the last replayed datagram
+    last: Option<SystemTime>,
+    bad_packets: u64,
+}
+
+impl Replayer {
+    /// `local` is the listener address (used if the capture has no directions)
+    pub fn new(local: SocketAddr) -> Self {
+        let options = ListenerOptions::default();
+
+        Self {
+            local,
+            admission: admission(&options),
+            options,
+            groups: Groups::default(),
+            clock: ManualClock::new(),
+            connections: HashMap::new(),
+            last: None,
+            bad_packets: 0,
+        }
+    }
+
+    /// Options of the listener, that was captured
+    pub fn options(&mut self, options: ListenerOptions) -> Result<()> {
+        options.validate()?;
+        self.admission = admission(&options);
+        self.options = options;
+
+        Ok(())
+    }
+
+    /// Handle a captured datagram, if the listener received it
+    ///
+    /// Timers run as the capture time moves on. Messages, that become ready, are passed to `on_data`.
+    /// Whatever the connections would send is discarded.
+    pub fn replay(
+        &mut self,
+        record: &Record,
+        mut on_data: impl FnMut(&Connection, &[u8]),
+    ) -> Result<()> {
+        let inbound = match record.direction {
+            Some(direction) => direction == Direction::Inbound,
+            None => same_addr(record.dst, self.local),
+        };
+        if !inbound {
+            return Ok(());
+        }
+
+        if let Some(last) = self.last
+            && let Ok(elapsed) = record.time.duration_since(last)
+        {
+            self.clock.advance(elapsed);
+        }
+        self.last = Some(record.time);
+
+        for conn in self.connections.values() {
+            conn.update()?;
+            while conn.poll_transmit().is_some() {}
+        }
+        self.remove_where(Connection::is_idle);
+
+        match Packet::from_raw(&record.data) {
+            Ok(pack) => self.dispatch(record.src, &pack)?,
+            Err(e) => {
+                self.bad_packets += 1;
+                tracing::debug!("Bad packet from {}: {e}", record.src);
+            }
+        }
+
+        for conn in self.connections.values() {
+            conn.deliver(|message| on_data(conn, message));
+            while conn.poll_extended().is_some() {}
+            while conn.poll_transmit().is_some() {}
+        }
+
+        self.remove_where(|conn| conn.disconnect_reason().is_some());
+
+        Ok(())
+    }
+
+    /// Deliver messages, that would become ready after the end of the capture (TSBPD)
+    pub fn finish(&mut self, mut on_data: impl FnMut(&Connection, &[u8])) {
+        let latency = self
+            .connections
+            .values()
+            .map(|conn| conn.negotiated.latency)
+            .max()
+            .unwrap_or_default();
+        self.clock.advance(latency);
+
+        for conn in self.connections.values() {
+            conn.deliver(|message| on_data(conn, message));
+        }
+    }
+
+    /// Connections, that are still open
+    pub fn connections(&self) -> impl Iterator<Item = &Connection> {
+        self.connections.values()
+    }
+
+    /// # of inbound datagrams, that failed to parse
+    pub fn bad_packets(&self) -> u64 {
+        self.bad_packets
+    }
+
+    fn remove_where(&mut self, f: impl Fn(&Connection) -> bool) {
+        let (groups, admission) = (&self.groups, &self.admission);
+        self.connections.retain(|_, conn| {
+            let remove = f(conn);
+            if remove {
+                admission.release();
+                conn.close(groups);
+            }
+            !remove
+        });
+    }
+
+    fn dispatch(&mut self, addr: SocketAddr, pack: &Packet) -> Result<()> {
+        if let Some(conn) = self.connections.get(&addr) {
+            return conn.handle(pack);
+        }
+
+        let Some(admitted) = self.admission.admit(pack, addr, &self.clock) else {
+            return Ok(());
+        };
+        let clock: Arc<dyn Clock> = Arc::new(self.clock.clone());
+        match Connection::accept(
+            pack,
+            addr,
+            admitted.cookie,
+            &self.groups,
+            &self.options,
+            &clock,
+        ) {
+            Ok((_, Some(conn))) => {
+                self.connections.insert(addr, conn);
+            }
+            _ if admitted.reserved => self.admission.release(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn admission(options: &ListenerOptions) -> Admission {
+    let mut admission = Admission::new(options);
+    admission.ignore_cookies();
+    admission
+}
+
+/// Ignores IPv4-mapped IPv6
+fn same_addr(a: SocketAddr, b: SocketAddr) -> bool {
+    a.ip().to_canonical() == b.ip().to_canonical() && a.port() == b.port()
+}
@@ -0,0 +1,69 @@
+//! Time source for connection timers
+//!
+//! [`SystemClock`] is used by the drivers. [`ManualClock`] only moves when told to,
+//! so timers (ACK, NAK, keep-alive, idle timeout) can be stepped through in tests.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// Monotonic time
+    fn now(&self) -> Instant;
+
+    /// Wall clock time
+    fn system_now(&self) -> SystemTime;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Starts at the current time and stands still until [`ManualClock::advance`] is called
+///
+/// Clones share the same time.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<(Instant, SystemTime)>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            now: Arc::new(Mutex::new((Instant::now(), SystemTime::now()))),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += duration;
+        now.1 += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+}
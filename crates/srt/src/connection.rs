@@ -1,155 +1,640 @@
 use std::{
-    net::{SocketAddr, UdpSocket},
+    collections::VecDeque,
+    net::{IpAddr, SocketAddr},
     sync::{
-        Mutex,
-        atomic::{AtomicU32, Ordering},
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicU32, AtomicU64, Ordering},
     },
-    time::{Instant, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{Result, bail};
 
 use crate::{
-    constants::{FULL_ACK_INTERVAL, HANDSHAKE_MAGIC_CODE, RTT_INIT, RTT_VAR_INIT},
+    buffer::{ReceiveBuffer, SendBuffer},
+    clock::Clock,
+    constants::{
+        ACK_WINDOW, FULL_ACK_INTERVAL, HANDSHAKE_MAGIC_CODE, KEEPALIVE_INTERVAL, MAX_PACKET_SIZE,
+        NAK_INTERVAL_MIN, PACKET_OVERHEAD, RTT_INIT, RTT_MAX, RTT_VAR_INIT, SEND_DROP_MIN,
+        SRT_VERSION,
+    },
+    crypto::Cipher,
+    filter::{
+        ArqLevel, FecConfig,
+        fec::{FEC_MESSAGE_NUMBER, FecDecoder},
+    },
+    group::{Group, Groups},
+    options::{Congestion, ListenerOptions, Options},
     packet::{
         Packet, PacketContent,
-        control::{ControlPacketInfo, ack::Ack, handshake::Handshake, nak::Nak},
-        data::DataPacketInfo,
+        control::{
+            ControlPacketInfo,
+            ack::Ack,
+            ack_ack::AckAck,
+            drop_req::DropReq,
+            extended::Extended,
+            handshake::{
+                Handshake, HandshakeType,
+                extension::{
+                    Extension, extension_types,
+                    handshake::{HandshakeExtension, handshake_extension_message_flags as flags},
+                    packet_filter::PacketFilterExtension,
+                },
+            },
+            nak::Nak,
+            peer_error::PeerError,
+        },
+        data::{DataPacketInfo, EncryptionFlag},
     },
-    server::OnDataHandler,
+    seq::{MsgNo, SeqNo},
+    subscription::{self, Mode},
+    timeline::PeerClock,
 };
 
-pub struct Connection<'c> {
-    socket: &'c UdpSocket,
-    on_data: Option<&'c OnDataHandler>,
+pub mod caller;
+
+/// Identifies a delivered stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StreamKey {
+    Peer(SocketAddr),
+    /// Group ID of the peer
+    Group(u32),
+}
+
+/// Why a connection was closed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// Peer sent `UMSG_SHUTDOWN`
+    Shutdown,
+    /// Nothing was received for [`Options::peer_idle_timeout`]
+    Timeout,
+    /// Peer sent `UMSG_PEERERROR` with this code
+    PeerError(u32),
+    /// `UMSG_PEERERROR` with this code was sent to the peer (see [`Connection::fail`])
+    Failed(u32),
+    /// Handling the peer's packets failed (see [`Connection::abort`])
+    Error,
+}
+
+/// Counters of a connection
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    /// `UMSG_CGWARNING` received from the peer
+    pub congestion_warnings: u32,
+    /// Wraparounds of the peer's timestamps (every ~71.6 minutes)
+    pub timestamp_wraps: u32,
+    /// Clock drift of the peer (micros)
+    pub drift: i64,
+    /// Sum of TSBPD base corrections for drift (micros)
+    pub drift_correction: i64,
+    /// Data packets sent again on the peer's request
+    pub retransmitted: u64,
+    /// Data packets dropped before the peer acknowledged them (too late or over the flow window)
+    pub send_dropped: u64,
+    /// Data packets from the peer, that didn't arrive before they were due (TSBPD)
+    pub receive_dropped: u64,
+}
+
+/// Values agreed on during the handshake
+#[derive(Clone, Debug)]
+pub struct Negotiated {
+    /// SRT version of the peer (0 if it didn't send HSREQ)
+    pub peer_srt_version: u32,
+    /// Refer to [`flags`]
+    pub peer_srt_flags: u32,
+    /// TSBPD delay of packets received from the peer
+    pub latency: Duration,
+    /// TSBPD delay of packets sent to the peer
+    pub peer_latency: Duration,
+    /// (bytes)
+    pub mtu: u32,
+    /// (packets)
+    pub flow_window: u32,
+}
+
+impl Negotiated {
+    /// Agree on `handshake` (Conclusion request)
+    ///
+    /// Each direction uses the larger of both sides' latencies.
+    fn new(handshake: &Handshake, options: &Options) -> Self {
+        let (peer_srt_version, peer_srt_flags, receiver_delay, sender_delay) =
+            match handshake.handshake_extension() {
+                Some(ext) => (
+                    ext.srt_version,
+                    ext.srt_flags,
+                    ext.receiver_delay,
+                    ext.sender_delay,
+                ),
+                None => (0, 0, 0, 0),
+            };
+
+        let latency = u16::try_from(options.latency.as_millis()).unwrap_or(u16::MAX);
+        let max_mtu = (options.payload_size + PACKET_OVERHEAD).min(MAX_PACKET_SIZE);
+
+        #[allow(clippy::cast_possible_truncation)]
+        Self {
+            peer_srt_version,
+            peer_srt_flags,
+            latency: Duration::from_millis(latency.max(sender_delay).into()),
+            peer_latency: Duration::from_millis(latency.max(receiver_delay).into()),
+            mtu: handshake.maximum_transmission_unit_size.min(max_mtu as u32),
+            flow_window: handshake.maximum_flow_window_size.min(options.flow_window),
+        }
+    }
+
+    /// HSRSP for the peer
+    #[allow(clippy::cast_possible_truncation)]
+    fn response(&self, packet_filter: bool, crypt: bool) -> HandshakeExtension {
+        let mut srt_flags = flags::TSBPDSND
+            | flags::TSBPDRCV
+            | flags::TLPKTDROP
+            | flags::PERIODICNAK
+            | flags::REXMITFLG;
+        if packet_filter {
+            srt_flags |= flags::PACKET_FILTER;
+        }
+        if crypt {
+            srt_flags |= flags::CRYPT;
+        }
+
+        HandshakeExtension {
+            r#type: extension_types::HSRSP,
+            srt_version: SRT_VERSION,
+            srt_flags,
+            receiver_delay: self.latency.as_millis() as u16,
+            sender_delay: self.peer_latency.as_millis() as u16,
+        }
+    }
+}
+
+/// Socket IDs of accepted connections count up from a random start, like libsrt's
+static NEXT_SOCKET_ID: LazyLock<AtomicU32> =
+    LazyLock::new(|| AtomicU32::new(fastrand::u32(1..1 << 30)));
 
+/// 30 bits, 0 is reserved (e.g. Induction requests)
+fn next_socket_id() -> u32 {
+    loop {
+        let id = NEXT_SOCKET_ID.fetch_add(1, Ordering::Relaxed) & ((1 << 30) - 1);
+        if id != 0 {
+            return id;
+        }
+    }
+}
+
+pub struct Connection {
     // Srt info
     pub stream_id: Option<String>,
     pub established: SystemTime,
     pub addr: SocketAddr,
+    /// Address of this side, as reported by the peer
+    pub reported_ip: IpAddr,
+    /// Ours, the peer sends to it
+    pub socket_id: u32,
     pub peer_srt_socket_id: u32,
+    pub options: Options,
+    pub negotiated: Negotiated,
+
+    /// Origin of outbound timestamps
+    start: Instant,
+    /// Origin and drift of inbound timestamps
+    peer_clock: Mutex<PeerClock>,
+    /// Time source of all timers
+    clock: Arc<dyn Clock>,
+
+    /// Group this link is a member of
+    group: Option<Arc<Group>>,
+    /// First link of the delivered stream
+    new_stream: bool,
 
     // /// # of packets received since last ack was sent
     // received_since_ack: AtomicU32,
     /// Ack sequence number
     ack_counter: AtomicU32,
 
-    /// Timestamp of the last **sent** Ack
-    /// (used to caltulate RTT)
+    /// Timestamp of the last periodic Ack
     last_ack_timestamp: Mutex<Instant>,
 
+    /// Recent Acks and when they were sent
+    /// (used to calculate RTT from the matching AckAck)
+    acks_sent: Mutex<VecDeque<(u32, Instant)>>,
+
+    /// Timestamp of the last periodic Nak
+    last_nak_timestamp: Mutex<Instant>,
+
+    /// Timestamp of the last sent packet
+    /// (used for keep-alive)
+    last_sent_timestamp: Mutex<Instant>,
+
+    /// Timestamp of the last received packet
+    /// (used for idle timeout)
+    last_received_timestamp: Mutex<Instant>,
+
+    /// Set once the connection should be dropped
+    closed: Mutex<Option<DisconnectReason>>,
+
+    /// Our answer to the caller's Conclusion, sent again if the caller repeats it
+    conclusion_response: Option<Handshake>,
+
+    /// # of congestion warnings received
+    congestion_warnings: AtomicU32,
+    /// Share of the send rate left after congestion warnings (per mille), recovers with ACKs
+    send_backoff: AtomicU32,
+    /// Receiving rate, reported in the peer's last full ACK (bytes/s)
+    peer_receiving_rate: AtomicU32,
+
     /// Package sequence number of last received data packet
     last_received: AtomicU32,
 
+    /// Packets waiting to be delivered in order
+    receive_buffer: Mutex<ReceiveBuffer>,
+
+    /// Packets waiting to be sent to the peer
+    outbound: Mutex<VecDeque<Packet>>,
+    /// Data packets waiting to be sent, after control packets and paced by max bandwidth
+    data_outbound: Mutex<VecDeque<Packet>>,
+    /// When the next data packet may be sent (max bandwidth)
+    next_data: Mutex<Instant>,
+
+    /// Data sent to the peer, until it's acknowledged
+    send_buffer: Mutex<SendBuffer>,
+    retransmitted: AtomicU64,
+    send_dropped: AtomicU64,
+
+    /// Application-defined control messages (subtype, data) received from the peer
+    extended: Mutex<VecDeque<(u16, Vec<u8>)>>,
+
+    /// Rebuilds lost packets (if the `fec` filter was agreed on)
+    fec: Option<Mutex<FecDecoder>>,
+    /// When lost packets are requested
+    arq: ArqLevel,
+
+    /// Encrypts and decrypts payloads (if the caller enabled encryption)
+    cipher: Option<Cipher>,
+
     /// <add link>
     rtt: AtomicU32,
     /// <add link>
     rtt_var: AtomicU32,
 }
 
-impl<'c> Connection<'c> {
-    pub fn establish_v5(socket: &'c UdpSocket, on_data: Option<&'c OnDataHandler>) -> Result<Self> {
-        let mut buf = [0; 200];
-
-        tracing::debug!("Waiting for a handshake...");
+impl Connection {
+    /// Listener side of the handshake (v5)
+    ///
+    /// Handles a handshake packet from an unknown peer and returns the response.
+    /// Connection is returned after the Conclusion
+    /// (group members are added to their group in `groups`).
+    /// Callers, that don't meet `options` of their stream ID, are rejected.
+    ///
+    /// `cookie` is sent in the Induction response, the one in the Conclusion
+    /// is checked by the driver (see [`crate::admission::Admission`]).
+    ///
+    /// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.1.1>
+    pub fn accept(
+        in_packet: &Packet,
+        addr: SocketAddr,
+        cookie: u32,
+        groups: &Groups,
+        options: &ListenerOptions,
+        clock: &Arc<dyn Clock>,
+    ) -> Result<(Packet, Option<Self>)> {
+        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = &in_packet.content
+        else {
+            bail!("Failed to unwrap handshake");
+        };
 
-        //
-        // Induction
-        //
+        match handshake.handshake_type {
+            HandshakeType::Induction => {
+                tracing::debug!("Connection: {addr}");
 
-        let (n, addr) = socket.recv_from(&mut buf)?;
-        let data = &buf[..n];
+                let out_packet_v5 = Packet {
+                    timestamp: in_packet.timestamp.wrapping_add(1),
+                    dest_socket_id: handshake.srt_socket_id,
+                    content: PacketContent::Control(ControlPacketInfo::Handshake(Handshake {
+                        version: 5,
+                        extension_field: HANDSHAKE_MAGIC_CODE,
+                        // The socket is only created with the Conclusion
+                        srt_socket_id: 0,
+                        syn_cookie: cookie,
+                        peer_ip_address: addr.ip(),
+                        ..handshake.clone()
+                    })),
+                };
 
-        tracing::debug!("Connection: {addr}");
+                tracing::debug!("Completed Induction");
 
-        let in_packet = Packet::from_raw(data)?;
-        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = in_packet.content
-        else {
-            bail!("Failed to unwrap handshake");
-        };
+                Ok((out_packet_v5, None))
+            }
+            HandshakeType::Conclusion => {
+                let stream_id = handshake
+                    .stream_id_extension()
+                    .map(|x| x.stream_id.as_str());
+                let options = options.get(stream_id).clone();
 
-        let out_packet_v5 = Packet {
-            timestamp: in_packet.timestamp + 1,
-            dest_socket_id: handshake.srt_socket_id,
-            content: PacketContent::Control(ControlPacketInfo::Handshake(Handshake {
-                version: 5,
-                extension_field: HANDSHAKE_MAGIC_CODE,
-                srt_socket_id: 42,
-                syn_cookie: 42,
-                ..handshake
-            })),
-        };
-        socket.send_to(&out_packet_v5.to_raw(), addr)?;
+                let congestion = handshake
+                    .congestion_extension()
+                    .map_or(Ok(Congestion::Live), |ext| ext.congestion.parse())?;
+                if congestion != options.congestion {
+                    bail!(
+                        "Congestion control mismatch: {congestion} != {}",
+                        options.congestion
+                    );
+                }
 
-        tracing::debug!("Completed Induction");
+                let cipher = match (&options.passphrase, handshake.key_material_extension()) {
+                    (None, None) => None,
+                    (Some(_), None) => bail!("Caller must enable encryption"),
+                    (None, Some(_)) => bail!("Caller enabled encryption, but no passphrase is set"),
+                    (Some(passphrase), Some(km)) => {
+                        if let Some(key_length) = options.key_length
+                            && key_length != km.key_length()
+                        {
+                            bail!("Key length mismatch: {} != {key_length}", km.key_length());
+                        }
+                        Some(Cipher::from_key_material(passphrase, km)?)
+                    }
+                };
 
-        //
-        // Conclusion
-        //
+                let filter = handshake
+                    .packet_filter_extension()
+                    .map(|ext| ext.config.parse::<FecConfig>())
+                    .transpose()?;
+                // Only the decoder is implemented
+                if filter.is_some() && Mode::from_stream_id(stream_id) == Mode::Request {
+                    bail!("Packet filter is only supported for publishers");
+                }
 
-        let (n, addr) = socket.recv_from(&mut buf)?;
-        let data = &buf[..n];
+                let (group, new_stream) = match handshake.group_membership_extension() {
+                    Some(ext) => {
+                        let (group, created) = groups.join(
+                            ext,
+                            stream_id,
+                            SeqNo::new(handshake.initial_packet_sequence_number),
+                            options.receive_buffer_size,
+                        )?;
+                        (Some(group), created)
+                    }
+                    None => (None, true),
+                };
 
-        let in_packet = Packet::from_raw(data)?;
-        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = in_packet.content
-        else {
-            bail!("Failed to unwrap handshake");
-        };
+                let negotiated = Negotiated::new(handshake, &options);
+                tracing::debug!("Negotiated: {negotiated:?}");
 
-        let peer_srt_socket_id = handshake.srt_socket_id;
-        let stream_id = handshake
-            .stream_id_extension
-            .as_ref()
-            .map(|x| x.stream_id.clone());
+                let socket_id = next_socket_id();
+                let mut response = Handshake {
+                    srt_socket_id: socket_id,
+                    maximum_transmission_unit_size: negotiated.mtu,
+                    maximum_flow_window_size: negotiated.flow_window,
+                    peer_ip_address: addr.ip(),
+                    ..handshake.clone()
+                };
+                response.extensions.retain_mut(|ext| match ext {
+                    Extension::Handshake(ext) => {
+                        *ext = negotiated.response(filter.is_some(), cipher.is_some());
+                        true
+                    }
+                    Extension::KeyMaterial(ext) => {
+                        ext.r#type = extension_types::KMRSP;
+                        true
+                    }
+                    // Not echoed back
+                    Extension::StreamId(_) | Extension::Unknown { .. } => false,
+                    Extension::PacketFilter(ext) => {
+                        if let Some(config) = &filter {
+                            *ext = PacketFilterExtension::new(config.to_string());
+                        }
+                        true
+                    }
+                    Extension::GroupMembership(ext) => {
+                        if let Some(group) = &group {
+                            tracing::debug!("Joined group 0x{:X}", group.peer_group_id);
+                            ext.group_id = group.group_id;
+                        }
+                        true
+                    }
+                    _ => true,
+                });
 
-        let out_packet_v5 = Packet {
-            timestamp: in_packet.timestamp + 1,
-            dest_socket_id: handshake.srt_socket_id,
-            content: PacketContent::Control(ControlPacketInfo::Handshake(handshake)),
-        };
-        socket.send_to(&out_packet_v5.to_raw(), addr)?;
+                let out_packet_v5 = Packet {
+                    timestamp: in_packet.timestamp.wrapping_add(1),
+                    dest_socket_id: handshake.srt_socket_id,
+                    content: PacketContent::Control(ControlPacketInfo::Handshake(response.clone())),
+                };
 
-        tracing::debug!("Completed Conclusion");
-        tracing::debug!("Done!");
+                tracing::debug!("Completed Conclusion");
+                tracing::debug!("Done!");
 
-        let established = SystemTime::now();
+                let mut conn = Self::new(
+                    handshake,
+                    socket_id,
+                    in_packet.timestamp,
+                    addr,
+                    options,
+                    negotiated,
+                    group,
+                    new_stream,
+                    Arc::clone(clock),
+                );
+                conn.cipher = cipher;
+                conn.conclusion_response = Some(response);
+                if let Some(config) = filter {
+                    tracing::debug!("Packet filter: {config}");
+                    conn.arq = config.arq;
+                    conn.fec = Some(Mutex::new(FecDecoder::new(config)));
+                }
 
-        Ok(Self::new(
-            socket,
-            on_data,
-            stream_id,
-            established,
-            addr,
-            peer_srt_socket_id,
-        ))
+                Ok((out_packet_v5, Some(conn)))
+            }
+            other => bail!("Unexpected handshake: {other:?}"),
+        }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn new(
-        socket: &'c UdpSocket,
-        on_data: Option<&'c OnDataHandler>,
-        stream_id: Option<String>,
-        established: SystemTime,
+        handshake: &Handshake,
+        socket_id: u32,
+        timestamp: u32,
         addr: SocketAddr,
-        peer_srt_socket_id: u32,
+        options: Options,
+        negotiated: Negotiated,
+        group: Option<Arc<Group>>,
+        new_stream: bool,
+        clock: Arc<dyn Clock>,
     ) -> Self {
+        let now = clock.now();
+        // Both directions start at the caller's initial sequence number
+        let send_buffer = SendBuffer::new(
+            SeqNo::new(handshake.initial_packet_sequence_number),
+            negotiated.flow_window as usize,
+        );
+
         Self {
-            on_data,
-            socket,
-            stream_id,
-            established,
+            stream_id: handshake.stream_id_extension().map(|x| x.stream_id.clone()),
+            established: clock.system_now(),
+            start: now,
+            peer_clock: Mutex::new(PeerClock::new(timestamp, now)),
+            clock,
             addr,
-            peer_srt_socket_id,
+            reported_ip: handshake.peer_ip_address,
+            socket_id,
+            peer_srt_socket_id: handshake.srt_socket_id,
+            negotiated,
+            group,
+            new_stream,
 
             ack_counter: AtomicU32::new(1),
-            last_ack_timestamp: Mutex::new(Instant::now()),
+            last_ack_timestamp: Mutex::new(now),
+            acks_sent: Mutex::new(VecDeque::new()),
+            last_nak_timestamp: Mutex::new(now),
+            last_sent_timestamp: Mutex::new(now),
+            last_received_timestamp: Mutex::new(now),
             // received_since_ack: AtomicU32::new(0),
-            last_received: AtomicU32::new(0),
+            closed: Mutex::new(None),
+            conclusion_response: None,
+            congestion_warnings: AtomicU32::new(0),
+            send_backoff: AtomicU32::new(1000),
+            peer_receiving_rate: AtomicU32::new(0),
+            last_received: AtomicU32::new(
+                (SeqNo::new(handshake.initial_packet_sequence_number) - 1).get(),
+            ),
+            receive_buffer: Mutex::new(ReceiveBuffer::new(
+                SeqNo::new(handshake.initial_packet_sequence_number),
+                options.receive_buffer_size,
+            )),
+            outbound: Mutex::new(VecDeque::new()),
+            data_outbound: Mutex::new(VecDeque::new()),
+            next_data: Mutex::new(now),
+            send_buffer: Mutex::new(send_buffer),
+            retransmitted: AtomicU64::new(0),
+            send_dropped: AtomicU64::new(0),
+            extended: Mutex::new(VecDeque::new()),
+
+            fec: None,
+            arq: ArqLevel::default(),
+
+            cipher: None,
 
             rtt: AtomicU32::new(RTT_INIT),
             rtt_var: AtomicU32::new(RTT_VAR_INIT),
+
+            options,
+        }
+    }
+
+    /// Identifies the delivered stream (members of a group share one)
+    pub fn stream_key(&self) -> StreamKey {
+        match &self.group {
+            Some(group) => StreamKey::Group(group.peer_group_id),
+            None => StreamKey::Peer(self.addr),
+        }
+    }
+
+    pub fn group(&self) -> Option<&Arc<Group>> {
+        self.group.as_ref()
+    }
+
+    /// Whether this link started the delivered stream
+    /// (`false` for links, that joined an existing group)
+    pub fn is_new_stream(&self) -> bool {
+        self.new_stream
+    }
+
+    /// Whether the peer sends or receives the stream (see [`crate::subscription`])
+    pub fn mode(&self) -> Mode {
+        Mode::from_stream_id(self.stream_id.as_deref())
+    }
+
+    /// Resource the peer publishes to or requests (see [`crate::subscription`])
+    pub fn resource(&self) -> &str {
+        subscription::resource(self.stream_id.as_deref())
+    }
+
+    /// Peer reached this listener on another address than `local` (e.g. through NAT)
+    ///
+    /// Unspecified addresses (e.g. bound to `[::]`) never mismatch.
+    pub fn is_nat_mismatch(&self, local: IpAddr) -> bool {
+        let (reported, local) = (self.reported_ip.to_canonical(), local.to_canonical());
+        !reported.is_unspecified() && !local.is_unspecified() && reported != local
+    }
+
+    /// Nothing was received for [`Options::peer_idle_timeout`]
+    pub fn is_idle(&self) -> bool {
+        let idle = self.elapsed(*self.last_received_timestamp.lock().unwrap());
+        idle > self.options.peer_idle_timeout
+    }
+
+    /// Set once the peer ended the connection or [`Connection::fail`] was called
+    ///
+    /// Idle connections are checked separately (see [`Connection::is_idle`]).
+    pub fn disconnect_reason(&self) -> Option<DisconnectReason> {
+        *self.closed.lock().unwrap()
+    }
+
+    /// Send `UMSG_PEERERROR` and close the connection
+    ///
+    /// Meant for when received data can't be written (see [`peer_error::error_codes`]).
+    ///
+    /// [`peer_error::error_codes`]: crate::packet::control::peer_error::error_codes
+    pub fn fail(&self, error_code: u32) -> Result<()> {
+        let peer_error = ControlPacketInfo::PeerError(PeerError { error_code });
+        tracing::trace!("srt | outbound | control | {peer_error:?}");
+        self.send(PacketContent::Control(peer_error))?;
+
+        self.closed
+            .lock()
+            .unwrap()
+            .get_or_insert(DisconnectReason::Failed(error_code));
+
+        Ok(())
+    }
+
+    /// Send `UMSG_SHUTDOWN` and close the connection
+    ///
+    /// Meant for drivers, when [`Connection::handle`] or [`Connection::update`] fails,
+    /// so one bad peer only ends its own connection.
+    pub fn abort(&self) {
+        let shutdown = PacketContent::Control(ControlPacketInfo::Shutdown);
+        tracing::trace!("srt | outbound | control | {shutdown:?}");
+        self.outbound.lock().unwrap().push_back(self.pack(shutdown));
+
+        self.closed
+            .lock()
+            .unwrap()
+            .get_or_insert(DisconnectReason::Error);
+    }
+
+    pub fn stats(&self) -> Stats {
+        let clock = self.peer_clock.lock().unwrap();
+
+        Stats {
+            congestion_warnings: self.congestion_warnings.load(Ordering::Relaxed),
+            timestamp_wraps: clock.wraps(),
+            drift: clock.drift(),
+            drift_correction: clock.correction(),
+            retransmitted: self.retransmitted.load(Ordering::Relaxed),
+            send_dropped: self.send_dropped.load(Ordering::Relaxed),
+            receive_dropped: self.receive_buffer().lock().unwrap().dropped(),
         }
     }
 
+    /// Leave the group (if any)
+    ///
+    /// Returns `true` if the delivered stream has ended.
+    pub fn close(&self, groups: &Groups) -> bool {
+        self.group.as_ref().is_none_or(|group| groups.leave(group))
+    }
+
+    /// Time since `since`, according to the connection clock
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.clock.now().saturating_duration_since(since)
+    }
+
+    /// Receive buffer of the group or of this connection
+    fn receive_buffer(&self) -> &Mutex<ReceiveBuffer> {
+        self.group
+            .as_ref()
+            .map_or(&self.receive_buffer, |group| &group.receive_buffer)
+    }
+
     pub(crate) fn inc_ack(&self) -> u32 {
         self.ack_counter.fetch_add(1, Ordering::Relaxed)
     }
@@ -160,25 +645,195 @@ impl<'c> Connection<'c> {
     //         == Ok(0)
     // }
 
+    /// Timestamps wrap around every ~71.6 minutes (see [`crate::timeline`])
     #[allow(clippy::cast_possible_truncation)]
-    pub(crate) fn pack(&self, content: PacketContent) -> Result<Packet> {
-        Ok(Packet {
-            timestamp: SystemTime::now()
-                .duration_since(self.established)?
-                .as_micros() as u32,
+    fn timestamp(&self) -> u32 {
+        self.elapsed(self.start).as_micros() as u32
+    }
+
+    pub(crate) fn pack(&self, content: PacketContent) -> Packet {
+        Packet {
+            timestamp: self.timestamp(),
             dest_socket_id: self.peer_srt_socket_id,
             content,
-        })
+        }
     }
 
+    /// Queue a packet for the peer (see [`Connection::poll_transmit`])
     pub fn send(&self, content: PacketContent) -> Result<()> {
-        self.socket
-            .send_to(&self.pack(content)?.to_raw(), self.addr)?;
+        let pack = self.pack(content);
+        self.outbound.lock().unwrap().push_back(pack);
+
+        Ok(())
+    }
+
+    /// Get next packet, that should be sent to [`Connection::addr`]
+    pub fn poll_transmit(&self) -> Option<Packet> {
+        let pack = self
+            .outbound
+            .lock()
+            .unwrap()
+            .pop_front()
+            .or_else(|| self.poll_data())?;
+        *self.last_sent_timestamp.lock().unwrap() = self.clock.now();
+
+        Some(pack)
+    }
+
+    /// Next data packet, unless sending it now would exceed [`Options::max_bandwidth`]
+    ///
+    /// Packets, that waited longer than the peer's latency, are dropped instead.
+    fn poll_data(&self) -> Option<Packet> {
+        let mut data_outbound = self.data_outbound.lock().unwrap();
+        while let Some(pack) = data_outbound.front()
+            && self.is_too_late(pack.timestamp)
+        {
+            tracing::debug!(
+                "Dropping packet, that's too late to be played: {}",
+                self.addr
+            );
+            data_outbound.pop_front();
+            self.send_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        let pack = data_outbound.front()?;
+
+        if let Some(send_rate) = self.send_rate() {
+            let now = self.clock.now();
+            let mut next_data = self.next_data.lock().unwrap();
+            if *next_data > now {
+                return None;
+            }
+
+            let PacketContent::Data(data) = &pack.content else {
+                unreachable!("Only data packets are paced");
+            };
+            let bytes = (data.content.len() + PACKET_OVERHEAD) as u64;
+            // Drivers poll about every timer interval, time left unused within it isn't lost
+            let earliest = now
+                .checked_sub(Duration::from_micros(FULL_ACK_INTERVAL.into()))
+                .unwrap_or(now);
+            *next_data = (*next_data).max(earliest)
+                + Duration::from_nanos(bytes * 1_000_000_000 / send_rate);
+        }
+
+        data_outbound.pop_front()
+    }
+
+    /// Data packets are paced at this rate (bytes/s)
+    ///
+    /// It's [`Options::max_bandwidth`], backed off after congestion warnings.
+    /// Without a limit, the peer's receiving rate is backed off instead, until it has recovered.
+    fn send_rate(&self) -> Option<u64> {
+        let backoff = self.send_backoff.load(Ordering::Relaxed);
+        if backoff >= 1000 {
+            return self.options.max_bandwidth;
+        }
+
+        let rate = self
+            .options
+            .max_bandwidth
+            .unwrap_or_else(|| self.peer_receiving_rate.load(Ordering::Relaxed).into());
+        (rate > 0).then(|| (rate * u64::from(backoff) / 1000).max(1))
+    }
+
+    /// Queue a message for the peer, as data packets of up to the negotiated MTU
+    ///
+    /// Packets are kept until the peer acknowledges them, and sent again if it reports them lost.
+    /// The timestamp (now) tells the peer when to play the message (plus its latency).
+    pub fn send_message(&self, message: &[u8]) -> Result<()> {
+        let payload_size = (self.negotiated.mtu as usize)
+            .saturating_sub(PACKET_OVERHEAD)
+            .min(self.options.payload_size);
+        let timestamp = self.timestamp();
+
+        let packets = self.send_buffer.lock().unwrap().push(
+            message,
+            payload_size,
+            timestamp,
+            self.clock.now(),
+        );
+        let mut data_outbound = self.data_outbound.lock().unwrap();
+        for data in packets {
+            data_outbound.push_back(Packet {
+                timestamp,
+                dest_socket_id: self.peer_srt_socket_id,
+                content: PacketContent::Data(self.encrypt(data)?),
+            });
+        }
 
         Ok(())
     }
 
-    fn handle_control(&self, control: &ControlPacketInfo) -> Result<()> {
+    fn encrypt(&self, mut data: DataPacketInfo) -> Result<DataPacketInfo> {
+        if let Some(cipher) = &self.cipher {
+            cipher.encrypt(&mut data)?;
+        }
+
+        Ok(data)
+    }
+
+    /// Queue an application-defined control message (`UMSG_EXT`) for the peer
+    ///
+    /// `subtype` must not be one of the `SRT_CMD_*` values (see [`Extended::is_srt_subtype`]).
+    pub fn send_extended(&self, subtype: u16, data: Vec<u8>) -> Result<()> {
+        if Extended::is_srt_subtype(subtype) {
+            bail!("subtype {subtype} is reserved by SRT");
+        }
+
+        let extended = Extended::User { subtype, data };
+        self.send(PacketContent::Control(ControlPacketInfo::Extended(
+            extended,
+        )))
+    }
+
+    /// Get next application-defined control message (subtype, data) received from the peer
+    pub fn poll_extended(&self) -> Option<(u16, Vec<u8>)> {
+        self.extended.lock().unwrap().pop_front()
+    }
+
+    /// Pass all messages, that are ready, to `f` (in order)
+    ///
+    /// With TSBPD (if the peer sends with it), a message is ready at the peer's timestamp
+    /// plus the negotiated latency, otherwise as soon as it's complete.
+    ///
+    /// The receive buffer stays locked, so links of one group can't interleave deliveries.
+    pub fn deliver(&self, mut f: impl FnMut(&[u8])) {
+        let mut receive_buffer = self.receive_buffer().lock().unwrap();
+
+        if !self.is_tsbpd() {
+            while let Some(message) = receive_buffer.pop_message() {
+                f(&message);
+            }
+            return;
+        }
+
+        let now = self.clock.now();
+        let latency = self.negotiated.latency;
+        loop {
+            // Not locked while `f` runs (it may ask for stats)
+            let message = {
+                let mut peer_clock = self.peer_clock.lock().unwrap();
+                receive_buffer.pop_due(now, |timestamp| peer_clock.tsbpd_time(timestamp, latency))
+            };
+            let Some(message) = message else {
+                break;
+            };
+            f(&message);
+        }
+    }
+
+    /// Peer sends with TSBPD (agreed on in HSREQ/HSRSP)
+    fn is_tsbpd(&self) -> bool {
+        self.negotiated.peer_srt_flags & flags::TSBPDSND != 0
+    }
+
+    /// Peer receives with TSBPD, and would play a packet with our `timestamp` already
+    fn is_too_late(&self, timestamp: u32) -> bool {
+        let age = Duration::from_micros(self.timestamp().wrapping_sub(timestamp).into());
+        self.negotiated.peer_srt_flags & flags::TSBPDRCV != 0 && age > self.negotiated.peer_latency
+    }
+
+    fn handle_control(&self, timestamp: u32, control: &ControlPacketInfo) -> Result<()> {
         tracing::trace!("srt | inbound | control | {control:?}");
 
         match control {
@@ -187,26 +842,101 @@ impl<'c> Connection<'c> {
                 tracing::trace!("srt | outbound | control | {keep_alive:?}");
                 self.send(keep_alive)?;
             }
-            ControlPacketInfo::AckAck(_) => {
+            ControlPacketInfo::AckAck(ack_ack) => {
+                let arrival = self.clock.now();
+
+                let sent = {
+                    let mut acks_sent = self.acks_sent.lock().unwrap();
+                    let Some(i) = acks_sent
+                        .iter()
+                        .position(|&(number, _)| number == ack_ack.ack_number)
+                    else {
+                        return Ok(());
+                    };
+                    let (_, sent) = acks_sent[i];
+                    acks_sent.drain(..=i);
+                    sent
+                };
+
                 // Calculate RTT
                 // RTT = 7/8 * RTT + 1/8 * rtt
                 // RTTVar = 3/4 * RTTVar + 1/4 * abs(RTT - rtt)
 
-                let sent = self.last_ack_timestamp.lock().unwrap();
-                let rtt_new: u32 = sent.elapsed().as_micros().try_into().unwrap();
+                let rtt_new = u32::try_from(arrival.saturating_duration_since(sent).as_micros())
+                    .unwrap_or(u32::MAX)
+                    .min(RTT_MAX);
 
                 let rtt_old = self
                     .rtt
                     .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| {
-                        Some(x * 7 / 8 + rtt_new / 8)
+                        Some(x.saturating_mul(7) / 8 + rtt_new / 8)
                     })
                     .unwrap();
 
                 self.rtt_var
                     .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |rtt_var| {
-                        Some(rtt_var * 3 / 4 + (rtt_old).abs_diff(rtt_new) / 4)
+                        Some(rtt_var.saturating_mul(3) / 4 + (rtt_old).abs_diff(rtt_new) / 4)
                     })
                     .unwrap();
+
+                // Sent right after our ACK, so it isn't delayed by the peer's buffers
+                self.peer_clock.lock().unwrap().sample_drift(
+                    timestamp,
+                    arrival,
+                    Duration::from_micros(rtt_new.into()),
+                );
+            }
+            ControlPacketInfo::Ack(ack) => self.handle_ack(ack)?,
+            ControlPacketInfo::Nak(loss_list) => self.handle_nak(loss_list)?,
+            ControlPacketInfo::DropReq(drop_req) => {
+                self.receive_buffer().lock().unwrap().skip(
+                    drop_req.first_packet_sequence_number,
+                    drop_req.last_packet_sequence_number,
+                );
+            }
+            ControlPacketInfo::Shutdown => {
+                self.closed
+                    .lock()
+                    .unwrap()
+                    .get_or_insert(DisconnectReason::Shutdown);
+            }
+            ControlPacketInfo::PeerError(PeerError { error_code }) => {
+                tracing::warn!("Peer error {error_code}: {}", self.addr);
+                self.closed
+                    .lock()
+                    .unwrap()
+                    .get_or_insert(DisconnectReason::PeerError(*error_code));
+            }
+            ControlPacketInfo::CongestionWarning => {
+                tracing::debug!("Congestion warning: {}", self.addr);
+                self.congestion_warnings.fetch_add(1, Ordering::Relaxed);
+                // Slow down by 1/9, like UDT's increase of the packet sending period
+                _ = self.send_backoff.fetch_update(
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                    |backoff| Some(backoff.min(1000) * 8 / 9),
+                );
+            }
+            ControlPacketInfo::Extended(Extended::User { subtype, data }) => {
+                self.extended
+                    .lock()
+                    .unwrap()
+                    .push_back((*subtype, data.clone()));
+            }
+            // The response got lost, the caller waits for it
+            ControlPacketInfo::Handshake(handshake)
+                if handshake.handshake_type == HandshakeType::Conclusion =>
+            {
+                if let Some(response) = &self.conclusion_response {
+                    tracing::debug!("Repeating Conclusion response: {}", self.addr);
+                    self.outbound.lock().unwrap().push_back(Packet {
+                        timestamp: timestamp.wrapping_add(1),
+                        dest_socket_id: self.peer_srt_socket_id,
+                        content: PacketContent::Control(ControlPacketInfo::Handshake(
+                            response.clone(),
+                        )),
+                    });
+                }
             }
             _ => (),
         }
@@ -214,17 +944,146 @@ impl<'c> Connection<'c> {
         Ok(())
     }
 
-    fn handle_data(&self, data: &DataPacketInfo) -> Result<()> {
+    /// Release acknowledged data, a Full ACK is confirmed with an ACKACK
+    fn handle_ack(&self, ack: &Ack) -> Result<()> {
+        let (Ack::Full {
+            last_ackd_packet_sequence_number,
+            ..
+        }
+        | Ack::Light {
+            last_ackd_packet_sequence_number,
+        }
+        | Ack::Small {
+            last_ackd_packet_sequence_number,
+            ..
+        }) = ack;
+        self.send_buffer
+            .lock()
+            .unwrap()
+            .acknowledge(*last_ackd_packet_sequence_number);
+
+        if let Ack::Full {
+            ack_number,
+            rtt,
+            rtt_variance,
+            receiving_rate,
+            ..
+        } = ack
+        {
+            self.peer_receiving_rate
+                .store(*receiving_rate, Ordering::Relaxed);
+            // Back off less with every ACK, in about a second after a warning
+            _ = self
+                .send_backoff
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |backoff| {
+                    (backoff < 1000).then_some(backoff + 1)
+                });
+
+            // Measured by the peer, from our ACKACK (capped, it's not trusted)
+            if *rtt > 0 {
+                self.rtt.store((*rtt).min(RTT_MAX), Ordering::Relaxed);
+                self.rtt_var
+                    .store((*rtt_variance).min(RTT_MAX), Ordering::Relaxed);
+            }
+
+            let ack_ack = PacketContent::Control(ControlPacketInfo::AckAck(AckAck {
+                ack_number: *ack_number,
+            }));
+            tracing::trace!("srt | outbound | control | {ack_ack:?}");
+            self.send(ack_ack)?;
+        }
+
+        Ok(())
+    }
+
+    /// Send lost packets again (at most once per RTT),
+    /// or tell the peer to stop waiting for packets, that were dropped
+    fn handle_nak(&self, loss_list: &[Nak]) -> Result<()> {
+        let now = self.clock.now();
+        let interval = Duration::from_micros(self.rtt.load(Ordering::Relaxed).into());
+
+        for nak in loss_list {
+            let (from, to) = match *nak {
+                Nak::Single { lost_packet } => (lost_packet, lost_packet),
+                Nak::Range {
+                    lost_packets_from,
+                    lost_packets_to,
+                } => (lost_packets_from, lost_packets_to),
+            };
+            let (packets, gone) = self
+                .send_buffer
+                .lock()
+                .unwrap()
+                .retransmit(from, to, now, interval);
+
+            self.retransmitted
+                .fetch_add(packets.len() as u64, Ordering::Relaxed);
+            for (timestamp, data) in packets {
+                let pack = Packet {
+                    timestamp,
+                    dest_socket_id: self.peer_srt_socket_id,
+                    content: PacketContent::Data(self.encrypt(data)?),
+                };
+                self.data_outbound.lock().unwrap().push_back(pack);
+            }
+
+            if let Some((first, last)) = gone {
+                let drop_req = PacketContent::Control(ControlPacketInfo::DropReq(DropReq {
+                    // Unknown
+                    message_number: MsgNo::new(0),
+                    first_packet_sequence_number: first,
+                    last_packet_sequence_number: last,
+                }));
+                tracing::trace!("srt | outbound | control | {drop_req:?}");
+                self.send(drop_req)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_data(&self, timestamp: u32, data: &DataPacketInfo) -> Result<()> {
+        let rebuilt = match &self.fec {
+            Some(fec) => fec.lock().unwrap().push(timestamp, data),
+            None => Vec::new(),
+        };
+
+        if self.fec.is_some() && data.message_number == FEC_MESSAGE_NUMBER {
+            tracing::trace!(
+                "srt | inbound | data | FEC {{ packet_sequence_number: {:?} }}",
+                data.packet_sequence_number
+            );
+
+            let mut receive_buffer = self.receive_buffer().lock().unwrap();
+            for (timestamp, pack) in &rebuilt {
+                self.store(&mut receive_buffer, *timestamp, pack);
+            }
+
+            return Ok(());
+        }
+
         let packet_number = data.packet_sequence_number;
-        let prev_packet_number = self.last_received.swap(packet_number, Ordering::Relaxed);
-        if prev_packet_number + 1 != packet_number && data.message_number != 1 {
-            tracing::warn!("Missed {} packets", packet_number - prev_packet_number - 1);
+        // Only moves forward (reordered and retransmitted packets are older)
+        let prev_packet_number = SeqNo::new(
+            self.last_received
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |prev| {
+                    (SeqNo::new(prev) < packet_number).then_some(packet_number.get())
+                })
+                .unwrap_or_else(|prev| prev),
+        );
+        let missed = prev_packet_number.distance(packet_number) - 1;
+        if missed > 0 && self.arq == ArqLevel::Always {
+            tracing::warn!("Missed {missed} packets");
 
-            self.send(PacketContent::Control(ControlPacketInfo::Nak(
-                Nak::Single {
-                    lost_packet: packet_number - 1,
+            let (from, to) = (prev_packet_number.next(), packet_number - 1);
+            let nak = match from == to {
+                true => Nak::Single { lost_packet: from },
+                false => Nak::Range {
+                    lost_packets_from: from,
+                    lost_packets_to: to,
                 },
-            )))?;
+            };
+            self.send(PacketContent::Control(ControlPacketInfo::Nak(vec![nak])))?;
         }
 
         tracing::trace!(
@@ -238,8 +1097,6 @@ impl<'c> Connection<'c> {
             data.content.len()
         );
 
-        self.send_full_ack()?;
-
         // if self.check_ack() {
         //     let ack = PacketContent::Control(ControlPacketInfo::Ack(Ack::Light {
         //         last_ackd_packet_sequence_number: data.packet_sequence_number + 1,
@@ -248,33 +1105,76 @@ impl<'c> Connection<'c> {
         //     self.send(ack)?;
         // }
 
-        let mpeg_packet = &data.content[..];
+        if let Some(group) = &self.group {
+            group.mark_active(self.addr);
+        }
 
-        if let Some(callback) = &self.on_data {
-            callback(self, mpeg_packet);
+        let mut receive_buffer = self.receive_buffer().lock().unwrap();
+        self.store(&mut receive_buffer, timestamp, data);
+        for (timestamp, pack) in &rebuilt {
+            self.store(&mut receive_buffer, *timestamp, pack);
         }
+        drop(receive_buffer);
 
-        Ok(())
+        // After storing, so the packet doesn't hide a gap
+        self.send_full_ack()
+    }
+
+    /// Decrypt (if needed) and buffer a data packet
+    fn store(&self, receive_buffer: &mut ReceiveBuffer, timestamp: u32, data: &DataPacketInfo) {
+        if data.encryption == EncryptionFlag::NoEncryption {
+            receive_buffer.insert(timestamp, data);
+            return;
+        }
+
+        let Some(cipher) = &self.cipher else {
+            tracing::warn!("Dropping encrypted packet {}", data.packet_sequence_number);
+            return;
+        };
+
+        let mut data = data.clone();
+        match cipher.decrypt(&mut data) {
+            Ok(()) => receive_buffer.insert(timestamp, &data),
+            Err(e) => tracing::warn!("Dropping packet {}: {e}", data.packet_sequence_number),
+        }
     }
 
-    pub(crate) fn handle(&self, pack: &Packet) -> Result<()> {
+    pub fn handle(&self, pack: &Packet) -> Result<()> {
+        *self.last_received_timestamp.lock().unwrap() = self.clock.now();
+        self.peer_clock.lock().unwrap().observe(pack.timestamp);
         self.update()?;
 
         match &pack.content {
-            PacketContent::Control(control) => self.handle_control(control)?,
-            PacketContent::Data(data) => self.handle_data(data)?,
+            PacketContent::Control(control) => self.handle_control(pack.timestamp, control)?,
+            PacketContent::Data(data) => self.handle_data(pack.timestamp, data)?,
         }
 
         Ok(())
     }
 
+    /// Acknowledges everything up to the first gap
     fn send_full_ack(&self) -> Result<()> {
+        let receive_buffer = self.receive_buffer().lock().unwrap();
+        let last_ackd_packet_sequence_number = receive_buffer
+            .first_missing()
+            .unwrap_or_else(|| SeqNo::new(self.last_received.load(Ordering::Relaxed)).next());
+        let available_buffer_size = receive_buffer.available();
+        drop(receive_buffer);
+
+        let ack_number = self.inc_ack();
+        let mut acks_sent = self.acks_sent.lock().unwrap();
+        if acks_sent.len() == ACK_WINDOW {
+            acks_sent.pop_front();
+        }
+        acks_sent.push_back((ack_number, self.clock.now()));
+        drop(acks_sent);
+
         let ack = PacketContent::Control(ControlPacketInfo::Ack(Ack::Full {
-            ack_number: self.inc_ack(),
-            last_ackd_packet_sequence_number: self.last_received.load(Ordering::Relaxed) + 1,
+            ack_number,
+            last_ackd_packet_sequence_number,
             rtt: self.rtt.load(Ordering::Relaxed),
             rtt_variance: self.rtt_var.load(Ordering::Relaxed),
-            available_buffer_size: 1,
+            available_buffer_size,
             packets_receiving_rate: 1,
             estimated_link_capacity: 1,
             receiving_rate: 1,
@@ -283,15 +1183,999 @@ impl<'c> Connection<'c> {
         self.send(ack)
     }
 
-    pub(crate) fn update(&self) -> Result<()> {
-        let mut last_ack_timestamp = self.last_ack_timestamp.lock().unwrap();
-        let micros: u32 = last_ack_timestamp.elapsed().as_micros().try_into().unwrap();
+    fn send_periodic_nak(&self) -> Result<()> {
+        let mut loss_list = self.receive_buffer().lock().unwrap().loss_list();
+
+        match (self.arq, &self.fec) {
+            (ArqLevel::Never, _) => return Ok(()),
+            // Leave recent losses to FEC
+            (ArqLevel::OnReq, Some(fec)) => {
+                let Some(horizon) = fec.lock().unwrap().recovery_horizon() else {
+                    return Ok(());
+                };
+                loss_list.retain(|&(from, _)| from < horizon);
+                for (_, to) in &mut loss_list {
+                    if *to >= horizon {
+                        *to = horizon - 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if loss_list.is_empty() {
+            return Ok(());
+        }
+
+        let loss_list = loss_list
+            .into_iter()
+            .map(|(from, to)| {
+                if from == to {
+                    Nak::Single { lost_packet: from }
+                } else {
+                    Nak::Range {
+                        lost_packets_from: from,
+                        lost_packets_to: to,
+                    }
+                }
+            })
+            .collect();
+        let nak = PacketContent::Control(ControlPacketInfo::Nak(loss_list));
+        tracing::trace!("srt | outbound | control | {nak:?}");
+        self.send(nak)
+    }
+
+    /// Run timers (Ack, Nak, keep-alive, dropping late data)
+    ///
+    /// Should be called at least every [`FULL_ACK_INTERVAL`]
+    pub fn update(&self) -> Result<()> {
+        {
+            let mut last_ack_timestamp = self.last_ack_timestamp.lock().unwrap();
+            let micros = self.elapsed(*last_ack_timestamp).as_micros();
 
-        if micros > FULL_ACK_INTERVAL {
-            *last_ack_timestamp = Instant::now();
-            self.send_full_ack()?;
+            if micros > FULL_ACK_INTERVAL.into() {
+                *last_ack_timestamp = self.clock.now();
+                drop(last_ack_timestamp);
+                self.send_full_ack()?;
+            }
+        }
+
+        {
+            // NAKInterval = max(RTT + 4 * RTTVar / 2, 20ms)
+            let nak_interval = self
+                .rtt
+                .load(Ordering::Relaxed)
+                .saturating_add(self.rtt_var.load(Ordering::Relaxed).saturating_mul(4) / 2)
+                .max(NAK_INTERVAL_MIN);
+
+            let mut last_nak_timestamp = self.last_nak_timestamp.lock().unwrap();
+            if self.elapsed(*last_nak_timestamp).as_micros() > nak_interval.into() {
+                *last_nak_timestamp = self.clock.now();
+                drop(last_nak_timestamp);
+                self.send_periodic_nak()?;
+            }
+        }
+
+        // Too late to be played by the peer
+        let threshold = self
+            .negotiated
+            .peer_latency
+            .max(Duration::from_micros(SEND_DROP_MIN.into()))
+            + 2 * Duration::from_micros(FULL_ACK_INTERVAL.into());
+        if let Some(deadline) = self.clock.now().checked_sub(threshold) {
+            let dropped = self.send_buffer.lock().unwrap().drop_late(deadline);
+            if dropped > 0 {
+                tracing::warn!("Dropped {dropped} packets, that weren't acknowledged in time");
+                self.send_dropped
+                    .fetch_add(dropped as u64, Ordering::Relaxed);
+            }
+        }
+
+        let idle = self
+            .elapsed(*self.last_sent_timestamp.lock().unwrap())
+            .as_micros();
+        if idle > KEEPALIVE_INTERVAL.into() {
+            let keep_alive = PacketContent::Control(ControlPacketInfo::KeepAlive);
+            tracing::trace!("srt | outbound | control | {keep_alive:?}");
+            self.send(keep_alive)?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualClock, SystemClock};
+    use crate::connection::caller::Caller;
+    use crate::packet::{
+        control::{
+            ack_ack::AckAck,
+            handshake::{
+                HandshakeEncryption,
+                extension::{
+                    group_membership::{GroupMembershipExtension, group_type},
+                    stream_id::StreamIdExtension,
+                },
+            },
+        },
+        data::PacketPosition,
+    };
+    use crate::transport::{
+        MemoryNetwork, MemoryTransport, Transport,
+        impair::{Impaired, Impairment, Loss},
+    };
+
+    fn system_clock() -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
+
+    /// Accept `request` from 127.0.0.1:9000
+    fn accepted(
+        request: &Packet,
+        options: &ListenerOptions,
+        clock: Arc<dyn Clock>,
+    ) -> Result<(Packet, Connection)> {
+        let (response, conn) = Connection::accept(
+            request,
+            "127.0.0.1:9000".parse().unwrap(),
+            42,
+            &Groups::default(),
+            options,
+            &clock,
+        )?;
+        Ok((response, conn.expect("Conclusion creates a connection")))
+    }
+
+    fn conclusion(extensions: Vec<Extension>) -> Packet {
+        Packet {
+            timestamp: 0,
+            dest_socket_id: 42,
+            content: PacketContent::Control(ControlPacketInfo::Handshake(Handshake {
+                version: 5,
+                encryption: HandshakeEncryption::NoEncryption,
+                extension_field: 0x05,
+                initial_packet_sequence_number: 100,
+                maximum_transmission_unit_size: 9000,
+                maximum_flow_window_size: 25600,
+                handshake_type: HandshakeType::Conclusion,
+                srt_socket_id: 7,
+                syn_cookie: 42,
+                peer_ip_address: "127.0.0.1".parse().unwrap(),
+                extensions,
+            })),
+        }
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let request = conclusion(vec![Extension::Handshake(HandshakeExtension {
+            r#type: extension_types::HSREQ,
+            srt_version: 0x01_04_04,
+            srt_flags: flags::TSBPDSND | flags::TSBPDRCV,
+            receiver_delay: 200,
+            sender_delay: 80,
+        })]);
+
+        let (response, conn) =
+            accepted(&request, &ListenerOptions::default(), system_clock()).unwrap();
+
+        let PacketContent::Control(ControlPacketInfo::Handshake(response)) = response.content
+        else {
+            panic!("Expected a handshake");
+        };
+        assert_eq!(response.maximum_transmission_unit_size, 1500);
+        assert_eq!(response.maximum_flow_window_size, 8192);
+
+        let hsrsp = response.handshake_extension().unwrap();
+        assert_eq!(hsrsp.r#type, extension_types::HSRSP);
+        assert_eq!(hsrsp.srt_version, SRT_VERSION);
+        assert_eq!(hsrsp.receiver_delay, 120);
+        assert_eq!(hsrsp.sender_delay, 200);
+
+        let negotiated = conn.negotiated;
+        assert_eq!(negotiated.peer_srt_version, 0x01_04_04);
+        assert_eq!(negotiated.latency, Duration::from_millis(120));
+        assert_eq!(negotiated.peer_latency, Duration::from_millis(200));
+        assert_eq!(negotiated.mtu, 1500);
+        assert_eq!(negotiated.flow_window, 8192);
+    }
+
+    #[test]
+    fn test_repeated_conclusion() {
+        let (response, conn) = accepted(
+            &conclusion(Vec::new()),
+            &ListenerOptions::default(),
+            system_clock(),
+        )
+        .unwrap();
+
+        let mut repeated = conclusion(Vec::new());
+        repeated.timestamp = 5000;
+        conn.handle(&repeated).unwrap();
+
+        let resent = std::iter::from_fn(|| conn.poll_transmit())
+            .find(|pack| {
+                matches!(
+                    pack.content,
+                    PacketContent::Control(ControlPacketInfo::Handshake(_))
+                )
+            })
+            .unwrap();
+        assert_eq!(resent.timestamp, 5001);
+        assert_eq!(resent.dest_socket_id, 7);
+        // Same handshake (after the timestamp)
+        assert_eq!(resent.to_raw()[12..], response.to_raw()[12..]);
+    }
+
+    #[test]
+    fn test_socket_id() {
+        let accept = || {
+            let (response, conn) = accepted(
+                &conclusion(Vec::new()),
+                &ListenerOptions::default(),
+                system_clock(),
+            )
+            .unwrap();
+            let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = response.content
+            else {
+                panic!("Expected a handshake");
+            };
+            assert_eq!(handshake.srt_socket_id, conn.socket_id);
+            assert_eq!(conn.peer_srt_socket_id, 7);
+            conn.socket_id
+        };
+
+        let (a, b) = (accept(), accept());
+        assert_ne!(a, 0);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_packet_filter() {
+        let filter = || {
+            Extension::PacketFilter(PacketFilterExtension::new(
+                "fec,cols:10,rows:5,layout:even".to_owned(),
+            ))
+        };
+        let accept =
+            |request: &Packet| accepted(request, &ListenerOptions::default(), system_clock());
+
+        let (response, conn) = accept(&conclusion(vec![filter()])).unwrap();
+        assert!(conn.fec.is_some());
+        let PacketContent::Control(ControlPacketInfo::Handshake(response)) = response.content
+        else {
+            panic!("Expected a handshake");
+        };
+        assert!(response.extensions.iter().any(|ext| matches!(
+            ext,
+            Extension::PacketFilter(ext) if ext.config == "fec,cols:10,rows:5,arq:always"
+        )));
+
+        // Nothing would send FEC packets to a player
+        let request = conclusion(vec![
+            Extension::StreamId(StreamIdExtension::new("#!::m=request".to_owned())),
+            filter(),
+        ]);
+        assert!(accept(&request).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_wraps() {
+        let (_, conn) = accepted(
+            &conclusion(Vec::new()),
+            &ListenerOptions::default(),
+            system_clock(),
+        )
+        .unwrap();
+
+        for timestamp in [0x7000_0000, 0xE000_0000, 0x1000_0000, 0x0800_0000] {
+            let keep_alive = Packet {
+                timestamp,
+                dest_socket_id: 42,
+                content: PacketContent::Control(ControlPacketInfo::KeepAlive),
+            };
+            conn.handle(&keep_alive).unwrap();
+        }
+
+        assert_eq!(conn.stats().timestamp_wraps, 1);
+    }
+
+    #[test]
+    fn test_handshake_timestamp_wraps() {
+        let addr = "127.0.0.1:9000".parse().unwrap();
+        let mut request = conclusion(Vec::new());
+        request.timestamp = u32::MAX;
+
+        for handshake_type in [HandshakeType::Induction, HandshakeType::Conclusion] {
+            if let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) =
+                &mut request.content
+            {
+                handshake.handshake_type = handshake_type;
+            }
+            let (response, _) = Connection::accept(
+                &request,
+                addr,
+                42,
+                &Groups::default(),
+                &ListenerOptions::default(),
+                &system_clock(),
+            )
+            .unwrap();
+            assert_eq!(response.timestamp, 0);
+        }
+    }
+
+    #[test]
+    fn test_sequence_wraparound() {
+        let mut request = conclusion(Vec::new());
+        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = &mut request.content
+        else {
+            unreachable!();
+        };
+        handshake.initial_packet_sequence_number = SeqNo::MAX.get() - 1;
+
+        let (_, conn) = accepted(&request, &ListenerOptions::default(), system_clock()).unwrap();
+        while conn.poll_transmit().is_some() {}
+
+        let data = |number: u32| Packet {
+            timestamp: 0,
+            dest_socket_id: 42,
+            content: PacketContent::Data(DataPacketInfo {
+                packet_sequence_number: SeqNo::new(number),
+                position: PacketPosition::Single,
+                order: false,
+                encryption: EncryptionFlag::NoEncryption,
+                retransmitted: false,
+                message_number: MsgNo::new(number + 2),
+                content: number.to_be_bytes().to_vec(),
+            }),
+        };
+        let naks = || {
+            std::iter::from_fn(|| conn.poll_transmit())
+                .filter_map(|pack| match pack.content {
+                    PacketContent::Control(ControlPacketInfo::Nak(naks)) => Some(naks),
+                    _ => None,
+                })
+                .flatten()
+                .collect::<Vec<_>>()
+        };
+
+        conn.handle(&data(SeqNo::MAX.get() - 1)).unwrap();
+        // Also after message numbers wrapped back to 1
+        let mut wrapped = data(1);
+        if let PacketContent::Data(data) = &mut wrapped.content {
+            data.message_number = MsgNo::new(1);
+        }
+        conn.handle(&wrapped).unwrap();
+        assert_eq!(
+            naks(),
+            vec![Nak::Range {
+                lost_packets_from: SeqNo::MAX,
+                lost_packets_to: SeqNo::new(0),
+            }]
+        );
+
+        // Reordered packets don't move back or trigger a Nak
+        conn.handle(&data(0)).unwrap();
+        conn.handle(&data(SeqNo::MAX.get())).unwrap();
+        assert_eq!(naks(), Vec::new());
+
+        let mut delivered = Vec::new();
+        conn.deliver(|message| delivered.push(message.to_vec()));
+        let expected = [SeqNo::MAX.get() - 1, SeqNo::MAX.get(), 0, 1];
+        assert_eq!(delivered, expected.map(|n| n.to_be_bytes().to_vec()));
+    }
+
+    #[test]
+    fn test_peer_ip_address() {
+        // IPv4 caller on a dual-stack socket
+        let addr = "[::ffff:198.51.100.7]:5000".parse().unwrap();
+        let (response, conn) = Connection::accept(
+            &conclusion(Vec::new()),
+            addr,
+            42,
+            &Groups::default(),
+            &ListenerOptions::default(),
+            &system_clock(),
+        )
+        .unwrap();
+
+        let PacketContent::Control(ControlPacketInfo::Handshake(response)) = response.content
+        else {
+            panic!("Expected a handshake");
+        };
+        assert_eq!(response.raw_content()[32..36], [7, 100, 51, 198]);
+
+        let conn = conn.unwrap();
+        assert!(!conn.is_nat_mismatch("127.0.0.1".parse().unwrap()));
+        assert!(!conn.is_nat_mismatch("::".parse().unwrap()));
+        assert!(conn.is_nat_mismatch("203.0.113.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_passphrase() {
+        let cipher = Cipher::new([1; 16], vec![2; 16]).unwrap();
+        let km = cipher.key_material("0123456789").unwrap();
+        let request = conclusion(vec![Extension::KeyMaterial(km)]);
+
+        let options = |passphrase: Option<&str>| ListenerOptions {
+            default: Options {
+                passphrase: passphrase.map(str::to_owned),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let accept = |options| accepted(&request, &options, system_clock());
+
+        assert!(accept(options(None)).is_err());
+        assert!(accept(options(Some("9876543210"))).is_err());
+
+        let (response, conn) = accept(options(Some("0123456789"))).unwrap();
+        assert!(conn.cipher.is_some());
+
+        let PacketContent::Control(ControlPacketInfo::Handshake(response)) = response.content
+        else {
+            panic!("Expected a handshake");
+        };
+        let kmrsp = response.key_material_extension().unwrap();
+        assert_eq!(kmrsp.r#type, extension_types::KMRSP);
+    }
+
+    #[test]
+    fn test_group_intruder() {
+        let cipher = Cipher::new([1; 16], vec![2; 16]).unwrap();
+        let membership = || {
+            Extension::GroupMembership(GroupMembershipExtension {
+                group_id: 0x4000_0001,
+                r#type: group_type::BROADCAST,
+                flags: 0,
+                weight: 0,
+            })
+        };
+        let stream_id = || Extension::StreamId(StreamIdExtension::new("live/cam1".to_owned()));
+        let member = conclusion(vec![
+            Extension::KeyMaterial(cipher.key_material("0123456789").unwrap()),
+            membership(),
+            stream_id(),
+        ]);
+        let intruder = conclusion(vec![membership(), stream_id()]);
+
+        let join = |request, addr: &str, groups, options| {
+            Connection::accept(
+                request,
+                addr.parse().unwrap(),
+                42,
+                groups,
+                options,
+                &system_clock(),
+            )
+            .map(|(_, conn)| conn.unwrap())
+        };
+
+        // Without a passphrase, the group and stream ID are enough to join
+        let groups = Groups::default();
+        let options = ListenerOptions::default();
+        let conn = join(&intruder, "198.51.100.7:5000", &groups, &options).unwrap();
+        join(&intruder, "203.0.113.1:5000", &groups, &options).unwrap();
+        assert_eq!(conn.group.unwrap().members(), 2);
+
+        // Every member has to prove the passphrase
+        let groups = Groups::default();
+        let options = ListenerOptions {
+            default: Options {
+                passphrase: Some("0123456789".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let conn = join(&member, "198.51.100.7:5000", &groups, &options).unwrap();
+        assert!(join(&intruder, "203.0.113.1:5000", &groups, &options).is_err());
+        assert_eq!(conn.group.unwrap().members(), 1);
+    }
+
+    #[test]
+    fn test_extended() {
+        let (_, conn) = accepted(
+            &conclusion(Vec::new()),
+            &ListenerOptions::default(),
+            system_clock(),
+        )
+        .unwrap();
+
+        let tally = Packet {
+            timestamp: 0,
+            dest_socket_id: 42,
+            content: PacketContent::Control(ControlPacketInfo::Extended(Extended::User {
+                subtype: 0x1000,
+                data: b"on air".to_vec(),
+            })),
+        };
+        conn.handle(&tally).unwrap();
+        assert_eq!(conn.poll_extended(), Some((0x1000, b"on air".to_vec())));
+        assert_eq!(conn.poll_extended(), None);
+
+        assert!(
+            conn.send_extended(extension_types::SID, Vec::new())
+                .is_err()
+        );
+        conn.send_extended(0x1001, b"{}".to_vec()).unwrap();
+        let pack = std::iter::from_fn(|| conn.poll_transmit())
+            .find(|pack| {
+                matches!(
+                    pack.content,
+                    PacketContent::Control(ControlPacketInfo::Extended(_))
+                )
+            })
+            .unwrap();
+        let raw = pack.to_raw();
+        assert_eq!(&raw[..4], &[0xFF, 0xFF, 0x10, 0x01]);
+        assert_eq!(&raw[16..], b"{}");
+    }
+
+    #[test]
+    fn test_disconnect_reason() {
+        let accept = || {
+            let (_, conn) = accepted(
+                &conclusion(Vec::new()),
+                &ListenerOptions::default(),
+                system_clock(),
+            )
+            .unwrap();
+            conn
+        };
+        let control = |control| Packet {
+            timestamp: 0,
+            dest_socket_id: 42,
+            content: PacketContent::Control(control),
+        };
+
+        let conn = accept();
+        conn.handle(&control(ControlPacketInfo::CongestionWarning))
+            .unwrap();
+        assert_eq!(conn.stats().congestion_warnings, 1);
+        assert_eq!(conn.disconnect_reason(), None);
+
+        conn.handle(&control(ControlPacketInfo::PeerError(PeerError {
+            error_code: 4000,
+        })))
+        .unwrap();
+        assert_eq!(
+            conn.disconnect_reason(),
+            Some(DisconnectReason::PeerError(4000))
+        );
+
+        let conn = accept();
+        conn.fail(4000).unwrap();
+        assert_eq!(
+            conn.disconnect_reason(),
+            Some(DisconnectReason::Failed(4000))
+        );
+        assert!(
+            std::iter::from_fn(|| conn.poll_transmit()).any(|pack| matches!(
+                pack.content,
+                PacketContent::Control(ControlPacketInfo::PeerError(PeerError {
+                    error_code: 4000
+                }))
+            ))
+        );
+    }
+
+    #[test]
+    fn test_peer_rtt() {
+        let (_, conn) = accepted(
+            &conclusion(Vec::new()),
+            &ListenerOptions::default(),
+            system_clock(),
+        )
+        .unwrap();
+
+        let ack = Packet {
+            timestamp: 0,
+            dest_socket_id: 42,
+            content: PacketContent::Control(ControlPacketInfo::Ack(Ack::Full {
+                ack_number: 1,
+                last_ackd_packet_sequence_number: SeqNo::new(100),
+                rtt: u32::MAX,
+                rtt_variance: u32::MAX,
+                available_buffer_size: 8192,
+                packets_receiving_rate: 1,
+                estimated_link_capacity: 1,
+                receiving_rate: 1,
+            })),
+        };
+        conn.handle(&ack).unwrap();
+        assert_eq!(conn.rtt.load(Ordering::Relaxed), RTT_MAX);
+        assert_eq!(conn.rtt_var.load(Ordering::Relaxed), RTT_MAX);
+
+        // Timers and smoothing keep working
+        conn.handle(&ack).unwrap();
+        conn.handle(&Packet {
+            timestamp: 0,
+            dest_socket_id: 42,
+            content: PacketContent::Control(ControlPacketInfo::AckAck(AckAck { ack_number: 1 })),
+        })
+        .unwrap();
+        conn.update().unwrap();
+    }
+
+    #[test]
+    fn test_tsbpd_release() {
+        let clock = ManualClock::new();
+        let request = conclusion(vec![Extension::Handshake(HandshakeExtension {
+            r#type: extension_types::HSREQ,
+            srt_version: SRT_VERSION,
+            srt_flags: flags::TSBPDSND,
+            receiver_delay: 0,
+            sender_delay: 0,
+        })]);
+        let (_, conn) = accepted(
+            &request,
+            &ListenerOptions::default(),
+            Arc::new(clock.clone()),
+        )
+        .unwrap();
+        let latency = conn.negotiated.latency;
+
+        // Sent 10 ms after the Conclusion, arrives right away
+        clock.advance(Duration::from_millis(10));
+        conn.handle(&Packet {
+            timestamp: 10_000,
+            dest_socket_id: 42,
+            content: PacketContent::Data(DataPacketInfo {
+                packet_sequence_number: SeqNo::new(100),
+                position: PacketPosition::Single,
+                order: false,
+                encryption: EncryptionFlag::NoEncryption,
+                retransmitted: false,
+                message_number: MsgNo::new(1),
+                content: b"hello".to_vec(),
+            }),
+        })
+        .unwrap();
+
+        let delivered = || {
+            let mut messages = Vec::new();
+            conn.deliver(|message| messages.push(message.to_vec()));
+            messages
+        };
+        clock.advance(latency - Duration::from_micros(1));
+        assert!(delivered().is_empty());
+        clock.advance(Duration::from_micros(1));
+        assert_eq!(delivered(), [b"hello"]);
+    }
+
+    #[test]
+    fn test_max_bandwidth() {
+        let clock = ManualClock::new();
+        let options = ListenerOptions {
+            default: Options {
+                // A full packet every 10 ms
+                max_bandwidth: Some(150_000),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (_, conn) =
+            accepted(&conclusion(Vec::new()), &options, Arc::new(clock.clone())).unwrap();
+
+        let payload_size = MAX_PACKET_SIZE - PACKET_OVERHEAD;
+        conn.send_message(&vec![0; 5 * payload_size]).unwrap();
+        conn.send(PacketContent::Control(ControlPacketInfo::KeepAlive))
+            .unwrap();
+
+        // Control packets go first
+        assert!(matches!(
+            conn.poll_transmit().unwrap().content,
+            PacketContent::Control(ControlPacketInfo::KeepAlive)
+        ));
+
+        let sent = || std::iter::from_fn(|| conn.poll_transmit()).count();
+        assert_eq!(sent(), 1);
+        clock.advance(Duration::from_millis(9));
+        assert_eq!(sent(), 0);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(sent(), 1);
+
+        // Time left unused is made up for within one timer interval
+        clock.advance(Duration::from_millis(30));
+        assert_eq!(sent(), 2);
+    }
+
+    #[test]
+    fn test_congestion_warning() {
+        let clock = ManualClock::new();
+        let options = ListenerOptions {
+            default: Options {
+                // A full packet every 10 ms
+                max_bandwidth: Some(150_000),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (_, conn) =
+            accepted(&conclusion(Vec::new()), &options, Arc::new(clock.clone())).unwrap();
+
+        let payload_size = MAX_PACKET_SIZE - PACKET_OVERHEAD;
+        conn.send_message(&vec![0; 100 * payload_size]).unwrap();
+        let sent_in_100ms = || {
+            (0..10)
+                .map(|_| {
+                    clock.advance(Duration::from_millis(10));
+                    std::iter::from_fn(|| conn.poll_transmit()).count()
+                })
+                .sum::<usize>()
+        };
+
+        assert!(conn.poll_transmit().is_some());
+        assert_eq!(sent_in_100ms(), 10);
+
+        let warning = Packet {
+            timestamp: 0,
+            dest_socket_id: 42,
+            content: PacketContent::Control(ControlPacketInfo::CongestionWarning),
+        };
+        for _ in 0..5 {
+            conn.handle(&warning).unwrap();
+        }
+        assert_eq!(conn.stats().congestion_warnings, 5);
+        assert_eq!(sent_in_100ms(), 6);
+    }
+
+    #[test]
+    fn test_send_too_late() {
+        let clock = ManualClock::new();
+        let request = conclusion(vec![Extension::Handshake(HandshakeExtension {
+            r#type: extension_types::HSREQ,
+            srt_version: SRT_VERSION,
+            srt_flags: flags::TSBPDRCV,
+            receiver_delay: 200,
+            sender_delay: 0,
+        })]);
+        let (_, conn) = accepted(
+            &request,
+            &ListenerOptions::default(),
+            Arc::new(clock.clone()),
+        )
+        .unwrap();
+        let is_data = |pack: Packet| matches!(pack.content, PacketContent::Data(_));
+
+        conn.send_message(b"on time").unwrap();
+        clock.advance(Duration::from_millis(200));
+        assert!(std::iter::from_fn(|| conn.poll_transmit()).any(is_data));
+
+        // Not polled, until the peer would have played it
+        conn.send_message(b"late").unwrap();
+        clock.advance(Duration::from_millis(201));
+        assert!(!std::iter::from_fn(|| conn.poll_transmit()).any(is_data));
+        assert_eq!(conn.stats().send_dropped, 1);
+    }
+
+    #[test]
+    fn test_timers() {
+        let clock = ManualClock::new();
+        let (_, conn) = accepted(
+            &conclusion(Vec::new()),
+            &ListenerOptions::default(),
+            Arc::new(clock.clone()),
+        )
+        .unwrap();
+
+        let sent = || std::iter::from_fn(|| conn.poll_transmit()).collect::<Vec<_>>();
+        let step = |micros: u32| {
+            clock.advance(Duration::from_micros(micros.into()));
+            conn.update().unwrap();
+            sent()
+        };
+        let is_ack = |pack: &Packet| {
+            matches!(
+                pack.content,
+                PacketContent::Control(ControlPacketInfo::Ack(Ack::Full { .. }))
+            )
+        };
+        let control = |control| Packet {
+            timestamp: 0,
+            dest_socket_id: 42,
+            content: PacketContent::Control(control),
+        };
+        sent();
+
+        // Full ACK once the interval has passed
+        assert!(step(FULL_ACK_INTERVAL).is_empty());
+        let acks = step(1);
+        assert!(acks.len() == 1 && is_ack(&acks[0]));
+        assert_eq!(acks[0].timestamp, FULL_ACK_INTERVAL + 1);
+
+        // RTT from the ACKACK
+        clock.advance(Duration::from_millis(8));
+        conn.handle(&control(ControlPacketInfo::AckAck(AckAck {
+            ack_number: 1,
+        })))
+        .unwrap();
+        assert_eq!(
+            conn.rtt.load(Ordering::Relaxed),
+            RTT_INIT * 7 / 8 + 8000 / 8
+        );
+
+        // A gap is reported right away, then again every NAK interval
+        let data = |number: u32| Packet {
+            timestamp: 0,
+            dest_socket_id: 42,
+            content: PacketContent::Data(DataPacketInfo {
+                packet_sequence_number: SeqNo::new(number),
+                position: PacketPosition::Single,
+                order: false,
+                encryption: EncryptionFlag::NoEncryption,
+                retransmitted: false,
+                message_number: MsgNo::new(number),
+                content: vec![0; 10],
+            }),
+        };
+        let is_nak = |pack: &Packet| {
+            matches!(
+                pack.content,
+                PacketContent::Control(ControlPacketInfo::Nak(_))
+            )
+        };
+        conn.handle(&data(100)).unwrap();
+        conn.handle(&data(102)).unwrap();
+        assert_eq!(sent().iter().filter(|pack| is_nak(pack)).count(), 1);
+        let nak_interval =
+            conn.rtt.load(Ordering::Relaxed) + 4 * conn.rtt_var.load(Ordering::Relaxed) / 2;
+        let naks = (0..=nak_interval / FULL_ACK_INTERVAL)
+            .flat_map(|_| step(FULL_ACK_INTERVAL))
+            .filter(is_nak)
+            .count();
+        assert_eq!(naks, 1);
+
+        // Keep-alive, once nothing was sent for a while
+        let sent = step(KEEPALIVE_INTERVAL + 1);
+        assert!(sent.iter().any(|pack| matches!(
+            pack.content,
+            PacketContent::Control(ControlPacketInfo::KeepAlive)
+        )));
+
+        // Idle timeout counts from the last received packet
+        conn.handle(&control(ControlPacketInfo::KeepAlive)).unwrap();
+        clock.advance(conn.options.peer_idle_timeout);
+        assert!(!conn.is_idle());
+        clock.advance(Duration::from_micros(1));
+        assert!(conn.is_idle());
+    }
+
+    /// Send a message per ms over 5% loss each way and 200 ms RTT for `millis`
+    ///
+    /// Returns the delivered messages with the time since they were sent, and the receiver.
+    fn lossy_link(latency: Duration, millis: u32) -> (Vec<(usize, Duration)>, Connection) {
+        let clock = ManualClock::new();
+        let shared: Arc<dyn Clock> = Arc::new(clock.clone());
+        let network = MemoryNetwork::default();
+        let link = |seed| {
+            let impairment = Impairment {
+                loss: Loss::Random(0.05),
+                delay: Duration::from_millis(100),
+                seed,
+                ..Default::default()
+            };
+            let transport =
+                Impaired::with_clock(network.bind_any().unwrap(), impairment, clock.clone());
+            transport
+                .set_read_timeout(Some(Duration::from_micros(1)))
+                .unwrap();
+            transport
+        };
+        let (a, b) = (link(1), link(2));
+        let (a_addr, b_addr) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+
+        let recv = |transport: &Impaired<MemoryTransport>| {
+            let mut buf = [0; MAX_PACKET_SIZE];
+            let mut packs = Vec::new();
+            while let Ok((n, _)) = transport.recv_from(&mut buf) {
+                packs.push(Packet::from_raw(&buf[..n]).unwrap());
+            }
+            packs
+        };
+        let step = || {
+            clock.advance(Duration::from_millis(1));
+            a.release();
+            b.release();
+        };
+
+        let options = Options {
+            latency,
+            ..Default::default()
+        };
+        let listener_options = ListenerOptions {
+            default: options.clone(),
+            ..Default::default()
+        };
+        let mut caller = Caller::new(b_addr, None, options, Arc::clone(&shared)).unwrap();
+        let mut receiver = None;
+        let sender = 'handshake: loop {
+            step();
+            if let Some(pack) = caller.poll_transmit() {
+                a.send_to(&pack.to_raw(), b_addr).unwrap();
+            }
+            for pack in recv(&b) {
+                let (response, conn) = Connection::accept(
+                    &pack,
+                    a_addr,
+                    42,
+                    &Groups::default(),
+                    &listener_options,
+                    &shared,
+                )
+                .unwrap();
+                b.send_to(&response.to_raw(), a_addr).unwrap();
+                receiver = receiver.or(conn);
+            }
+            for pack in recv(&a) {
+                if let Some(conn) = caller.handle(&pack).unwrap() {
+                    break 'handshake conn;
+                }
+            }
+        };
+        let receiver = receiver.unwrap();
+
+        let mut sent = Vec::new();
+        let mut delivered = Vec::new();
+        for _ in 0..millis {
+            step();
+            sender.send_message(&sent.len().to_be_bytes()).unwrap();
+            sent.push(clock.now());
+
+            for pack in recv(&a) {
+                sender.handle(&pack).unwrap();
+            }
+            for pack in recv(&b) {
+                receiver.handle(&pack).unwrap();
+            }
+            sender.update().unwrap();
+            receiver.update().unwrap();
+            while let Some(pack) = sender.poll_transmit() {
+                a.send_to(&pack.to_raw(), b_addr).unwrap();
+            }
+            while let Some(pack) = receiver.poll_transmit() {
+                b.send_to(&pack.to_raw(), a_addr).unwrap();
+            }
+
+            receiver.deliver(|message| {
+                let k = usize::from_be_bytes(message.try_into().unwrap());
+                delivered.push((k, clock.now() - sent[k]));
+            });
+        }
+        assert!(a.dropped() > 0 && b.dropped() > 0);
+
+        (delivered, receiver)
+    }
+
+    #[test]
+    fn test_loss_recovery() {
+        // Each message is played exactly one-way delay plus latency after it was sent
+        let on_time = |delay: Duration, latency| {
+            let expected = Duration::from_millis(100) + latency;
+            (expected..=expected + Duration::from_millis(1)).contains(&delay)
+        };
+
+        // Enough latency for a few retransmissions
+        let latency = Duration::from_secs(1);
+        let (delivered, receiver) = lossy_link(latency, 3000);
+        assert!(delivered.len() > 1500);
+        for (i, &(k, delay)) in delivered.iter().enumerate() {
+            assert_eq!(k, i);
+            assert!(on_time(delay, latency), "{k}: {delay:?}");
+        }
+        assert_eq!(receiver.stats().receive_dropped, 0);
+
+        // Less than the RTT: lost packets are too late to be played
+        let latency = Duration::from_millis(120);
+        let (delivered, receiver) = lossy_link(latency, 3000);
+        assert!(delivered.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(delivered.iter().all(|&(_, delay)| on_time(delay, latency)));
+        let (last, _) = delivered.last().unwrap();
+        let dropped = receiver.stats().receive_dropped;
+        assert!(dropped > 0);
+        assert_eq!(dropped, (last + 1 - delivered.len()) as u64);
+    }
+}
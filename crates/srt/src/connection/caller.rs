@@ -0,0 +1,262 @@
+//! Caller side of the handshake (v5)
+//!
+//! Sans-IO, like [`Connection`]: send what [`Caller::poll_transmit`] returns to the listener
+//! and pass its responses to [`Caller::handle`], until the connection comes out.
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.3.1.1>
+
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    clock::Clock,
+    connection::{Connection, Negotiated},
+    constants::{
+        HANDSHAKE_INTERVAL, HANDSHAKE_MAGIC_CODE, MAX_PACKET_SIZE, PACKET_OVERHEAD, SRT_VERSION,
+    },
+    crypto::Cipher,
+    options::Options,
+    packet::{
+        Packet, PacketContent,
+        control::{
+            ControlPacketInfo,
+            handshake::{
+                Handshake, HandshakeEncryption, HandshakeType,
+                extension::{
+                    Extension, extension_flags, extension_types,
+                    handshake::{HandshakeExtension, handshake_extension_message_flags as flags},
+                    key_material::KeyMaterialExtension,
+                    stream_id::StreamIdExtension,
+                },
+            },
+        },
+    },
+    seq::SeqNo,
+};
+
+/// `UDT_DGRAM`, in the Induction request
+const SOCKET_TYPE_DGRAM: u16 = 2;
+
+/// Connects to a listener
+pub struct Caller {
+    addr: SocketAddr,
+    stream_id: Option<String>,
+    options: Options,
+    socket_id: u32,
+    initial_sequence_number: SeqNo,
+    /// Keys and their KMREQ (if a passphrase is set)
+    cipher: Option<(Cipher, KeyMaterialExtension)>,
+    clock: Arc<dyn Clock>,
+    start: Instant,
+    /// From the Induction response, once there is one
+    cookie: Option<u32>,
+    /// When the current step was last sent
+    last_sent: Option<Instant>,
+}
+
+impl Caller {
+    pub fn new(
+        addr: SocketAddr,
+        stream_id: Option<String>,
+        options: Options,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self> {
+        options.validate()?;
+
+        let cipher = match &options.passphrase {
+            Some(passphrase) => {
+                let cipher = Cipher::generate(options.key_length.unwrap_or(16))?;
+                let km = cipher.key_material(passphrase)?;
+                Some((cipher, km))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            addr,
+            stream_id,
+            options,
+            socket_id: fastrand::u32(1..1 << 30),
+            initial_sequence_number: SeqNo::new(fastrand::u32(..)),
+            cipher,
+            start: clock.now(),
+            clock,
+            cookie: None,
+            last_sent: None,
+        })
+    }
+
+    /// Our socket ID (the listener sends to it)
+    pub fn socket_id(&self) -> u32 {
+        self.socket_id
+    }
+
+    /// The listener didn't answer within [`Options::connect_timeout`]
+    pub fn is_timed_out(&self) -> bool {
+        self.clock.now().saturating_duration_since(self.start) > self.options.connect_timeout
+    }
+
+    /// Get the handshake, that should be sent to the listener now
+    ///
+    /// Each step is repeated every [`HANDSHAKE_INTERVAL`] until it's answered.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn poll_transmit(&mut self) -> Option<Packet> {
+        let now = self.clock.now();
+        let interval = Duration::from_micros(HANDSHAKE_INTERVAL.into());
+        if self
+            .last_sent
+            .is_some_and(|sent| now.saturating_duration_since(sent) < interval)
+        {
+            return None;
+        }
+        self.last_sent = Some(now);
+
+        let handshake = match self.cookie {
+            None => self.induction(),
+            Some(cookie) => self.conclusion(cookie),
+        };
+
+        Some(Packet {
+            timestamp: now.saturating_duration_since(self.start).as_micros() as u32,
+            dest_socket_id: 0,
+            content: PacketContent::Control(ControlPacketInfo::Handshake(handshake)),
+        })
+    }
+
+    /// Handle a response of the listener
+    ///
+    /// Returns the connection once the Conclusion is answered.
+    pub fn handle(&mut self, pack: &Packet) -> Result<Option<Connection>> {
+        let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) = &pack.content else {
+            return Ok(None);
+        };
+        if pack.dest_socket_id != self.socket_id {
+            return Ok(None);
+        }
+
+        match (handshake.handshake_type, self.cookie) {
+            (HandshakeType::Induction, None) => {
+                if handshake.version < 5 || handshake.extension_field != HANDSHAKE_MAGIC_CODE {
+                    bail!("Listener doesn't support handshake v5");
+                }
+
+                tracing::debug!("Completed Induction: {}", self.addr);
+                self.cookie = Some(handshake.syn_cookie);
+                // Conclusion right away
+                self.last_sent = None;
+
+                Ok(None)
+            }
+            (HandshakeType::Conclusion, Some(_)) => self.connect(handshake).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn connect(&mut self, response: &Handshake) -> Result<Connection> {
+        let kmrsp = response
+            .key_material_extension()
+            .is_some_and(|km| km.r#type == extension_types::KMRSP);
+        if self.cipher.is_some() && !kmrsp {
+            bail!("Listener didn't accept the passphrase");
+        }
+
+        let negotiated = Negotiated::new(response, &self.options);
+        tracing::debug!("Negotiated: {negotiated:?}");
+
+        // The listener's timestamps start with its connection, about now
+        let mut conn = Connection::new(
+            response,
+            self.socket_id,
+            0,
+            self.addr,
+            self.options.clone(),
+            negotiated,
+            None,
+            true,
+            Arc::clone(&self.clock),
+        );
+        conn.stream_id = self.stream_id.clone();
+        conn.cipher = self.cipher.take().map(|(cipher, _)| cipher);
+        // Our timestamps go on from the handshake, the listener's TSBPD base is set by it
+        conn.start = self.start;
+
+        tracing::debug!("Completed Conclusion: {}", self.addr);
+
+        Ok(conn)
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn handshake(&self, handshake_type: HandshakeType) -> Handshake {
+        Handshake {
+            version: 4,
+            encryption: HandshakeEncryption::NoEncryption,
+            extension_field: SOCKET_TYPE_DGRAM,
+            initial_packet_sequence_number: self.initial_sequence_number.get(),
+            maximum_transmission_unit_size: (self.options.payload_size + PACKET_OVERHEAD)
+                .min(MAX_PACKET_SIZE) as u32,
+            maximum_flow_window_size: self.options.flow_window,
+            handshake_type,
+            srt_socket_id: self.socket_id,
+            syn_cookie: 0,
+            peer_ip_address: self.addr.ip(),
+            extensions: Vec::new(),
+        }
+    }
+
+    fn induction(&self) -> Handshake {
+        self.handshake(HandshakeType::Induction)
+    }
+
+    /// With HSREQ (latency of both directions), KMREQ and the stream ID
+    #[allow(clippy::cast_possible_truncation)]
+    fn conclusion(&self, cookie: u32) -> Handshake {
+        let latency = u16::try_from(self.options.latency.as_millis()).unwrap_or(u16::MAX);
+        let mut srt_flags = flags::TSBPDSND
+            | flags::TSBPDRCV
+            | flags::TLPKTDROP
+            | flags::PERIODICNAK
+            | flags::REXMITFLG;
+        if self.cipher.is_some() {
+            srt_flags |= flags::CRYPT;
+        }
+
+        let mut extension_field = extension_flags::HSREQ;
+        let mut extensions = vec![Extension::Handshake(HandshakeExtension {
+            r#type: extension_types::HSREQ,
+            srt_version: SRT_VERSION,
+            srt_flags,
+            receiver_delay: latency,
+            sender_delay: latency,
+        })];
+        let mut encryption = HandshakeEncryption::NoEncryption;
+        if let Some((cipher, km)) = &self.cipher {
+            extension_field |= extension_flags::KMREQ;
+            extensions.push(Extension::KeyMaterial(km.clone()));
+            encryption = match cipher.key_length() {
+                16 => HandshakeEncryption::AES128,
+                24 => HandshakeEncryption::AES192,
+                _ => HandshakeEncryption::AES256,
+            };
+        }
+        if let Some(stream_id) = &self.stream_id {
+            extension_field |= extension_flags::CONFIG;
+            extensions.push(Extension::StreamId(StreamIdExtension::new(
+                stream_id.clone(),
+            )));
+        }
+
+        Handshake {
+            version: 5,
+            encryption,
+            extension_field,
+            syn_cookie: cookie,
+            extensions,
+            ..self.handshake(HandshakeType::Conclusion)
+        }
+    }
+}
@@ -1,12 +1,56 @@
 /// (bytes)
 pub const MAX_PACKET_SIZE: usize = 1500;
 
+/// (packets)
+pub const RECEIVE_BUFFER_SIZE: usize = 8192;
+
 pub const HANDSHAKE_MAGIC_CODE: u16 = 0x4A17;
 
 /// (micros)
 pub const RTT_INIT: u32 = 100_000;
 /// (micros)
 pub const RTT_VAR_INIT: u32 = 50_000;
+/// RTT and its variance are capped at this, e.g. when reported by the peer (micros)
+pub const RTT_MAX: u32 = 1_000_000;
 
 /// (micros)
 pub const FULL_ACK_INTERVAL: u32 = 10_000;
+
+/// Sent ACKs remembered to match ACKACKs with
+pub const ACK_WINDOW: usize = 1024;
+
+/// (micros)
+pub const NAK_INTERVAL_MIN: u32 = 20_000;
+
+/// (micros)
+pub const KEEPALIVE_INTERVAL: u32 = 1_000_000;
+
+/// (micros)
+pub const PEER_IDLE_TIMEOUT: u32 = 5_000_000;
+
+/// Reported in HSRSP (1.5.0)
+pub const SRT_VERSION: u32 = 0x00_01_05_00;
+
+/// TSBPD delay, unless configured otherwise (millis)
+pub const DEFAULT_LATENCY: u16 = 120;
+
+/// SRT (16), UDP (8) and IPv4 (20) headers (bytes)
+pub const PACKET_OVERHEAD: usize = 44;
+
+/// # of drift samples averaged before the TSBPD base is corrected
+pub const DRIFT_MAX_SPAN: u32 = 1000;
+/// Drift tolerated before the TSBPD base is moved (micros)
+pub const DRIFT_MAX: i64 = 5000;
+
+/// Caller repeats a handshake step until answered, this often (micros)
+pub const HANDSHAKE_INTERVAL: u32 = 250_000;
+
+/// (micros)
+pub const CONNECT_TIMEOUT: u32 = 3_000_000;
+
+/// Unacknowledged data packets are dropped once they are older than
+/// the peer's latency (at least this) plus 2 ACK intervals (micros)
+pub const SEND_DROP_MIN: u32 = 1_000_000;
+
+/// Messages waiting to be read from each stream of a listener (more are dropped)
+pub const STREAM_QUEUE_SIZE: usize = 8192;
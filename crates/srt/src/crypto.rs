@@ -0,0 +1,248 @@
+//! Payload encryption (AES-CTR)
+//!
+//! Stream encrypting keys (SEK) are wrapped with a key (KEK),
+//! that is derived from the passphrase, and exchanged in Key Material.
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-6>
+
+use aes::{
+    Aes128, Aes192, Aes256,
+    cipher::{KeyIvInit, StreamCipher},
+};
+use aes_kw::Kek;
+use anyhow::{Result, anyhow, bail};
+use sha1::Sha1;
+
+use crate::packet::{
+    control::handshake::extension::{
+        extension_types,
+        key_material::{KeyBasedEncryption, KeyMaterialExtension},
+    },
+    data::{DataPacketInfo, EncryptionFlag},
+};
+
+/// (bytes)
+pub const SALT_LENGTH: usize = 16;
+
+const PBKDF2_ITERATIONS: u32 = 2048;
+/// Only the end of the salt is used to derive KEK (bytes)
+const PBKDF2_SALT_LENGTH: usize = 8;
+
+/// Key Material: cipher (AES-CTR)
+const CIPHER_AES_CTR: u8 = 2;
+/// Key Material: stream encapsulation (SRT)
+const STREAM_ENCAPSULATION_SRT: u8 = 2;
+/// Key Material: packet type (KM message)
+const PACKET_TYPE_KM: u8 = 2;
+/// Key Material: signature ("HAI" in PnP Vendor ID)
+const SIGN: u16 = 0x2029;
+
+/// Stream encrypting keys of a connection
+pub struct Cipher {
+    salt: [u8; SALT_LENGTH],
+    even_key: Option<Vec<u8>>,
+    odd_key: Option<Vec<u8>>,
+}
+
+impl Cipher {
+    pub fn new(salt: [u8; SALT_LENGTH], even_key: Vec<u8>) -> Result<Self> {
+        if !matches!(even_key.len(), 16 | 24 | 32) {
+            bail!("Unsupported key length: {}", even_key.len());
+        }
+
+        Ok(Self {
+            salt,
+            even_key: Some(even_key),
+            odd_key: None,
+        })
+    }
+
+    /// Random salt and key (caller side)
+    pub fn generate(key_length: usize) -> Result<Self> {
+        let mut salt = [0; SALT_LENGTH];
+        let mut key = vec![0; key_length];
+        getrandom::fill(&mut salt).map_err(|e| anyhow!("No random salt: {e}"))?;
+        getrandom::fill(&mut key).map_err(|e| anyhow!("No random key: {e}"))?;
+
+        Self::new(salt, key)
+    }
+
+    /// Unwrap keys with the passphrase
+    ///
+    /// Fails if the passphrase doesn't match.
+    pub fn from_key_material(passphrase: &str, km: &KeyMaterialExtension) -> Result<Self> {
+        let key_length = km.key_length();
+        if !matches!(key_length, 16 | 24 | 32) {
+            bail!("Unsupported key length: {key_length}");
+        }
+        if km.cipher != CIPHER_AES_CTR {
+            bail!("Unsupported cipher: {}", km.cipher);
+        }
+        let Ok(salt) = <[u8; SALT_LENGTH]>::try_from(&km.salt[..]) else {
+            bail!("Unsupported salt length: {}", km.salt.len());
+        };
+
+        let kek = derive_kek(passphrase, &salt, key_length);
+        let mut keys = vec![0; km.wrapped_key.len().saturating_sub(8)];
+        match key_length {
+            16 => Kek::<Aes128>::try_from(&kek[..])?.unwrap(&km.wrapped_key, &mut keys),
+            24 => Kek::<Aes192>::try_from(&kek[..])?.unwrap(&km.wrapped_key, &mut keys),
+            _ => Kek::<Aes256>::try_from(&kek[..])?.unwrap(&km.wrapped_key, &mut keys),
+        }
+        .map_err(|_| anyhow!("Wrong passphrase"))?;
+
+        let (even_key, odd_key) = match km.key_based_encryption {
+            KeyBasedEncryption::EvenKey => (Some(keys), None),
+            KeyBasedEncryption::OddKey => (None, Some(keys)),
+            KeyBasedEncryption::Both => {
+                let odd_key = keys.split_off(key_length);
+                (Some(keys), Some(odd_key))
+            }
+        };
+
+        Ok(Self {
+            salt,
+            even_key,
+            odd_key,
+        })
+    }
+
+    /// Wrap keys with the passphrase (KMREQ)
+    pub fn key_material(&self, passphrase: &str) -> Result<KeyMaterialExtension> {
+        let (key_based_encryption, keys) = match (&self.even_key, &self.odd_key) {
+            (Some(even), Some(odd)) => (KeyBasedEncryption::Both, [&even[..], odd].concat()),
+            (Some(even), None) => (KeyBasedEncryption::EvenKey, even.clone()),
+            (None, Some(odd)) => (KeyBasedEncryption::OddKey, odd.clone()),
+            (None, None) => bail!("No keys"),
+        };
+        let key_length = self.key_length();
+
+        let kek = derive_kek(passphrase, &self.salt, key_length);
+        let mut wrapped_key = vec![0; keys.len() + 8];
+        match key_length {
+            16 => Kek::<Aes128>::try_from(&kek[..])?.wrap(&keys, &mut wrapped_key),
+            24 => Kek::<Aes192>::try_from(&kek[..])?.wrap(&keys, &mut wrapped_key),
+            _ => Kek::<Aes256>::try_from(&kek[..])?.wrap(&keys, &mut wrapped_key),
+        }
+        .map_err(|e| anyhow!("Failed to wrap keys: {e}"))?;
+
+        Ok(KeyMaterialExtension {
+            r#type: extension_types::KMREQ,
+            version: 1,
+            packet_type: PACKET_TYPE_KM,
+            sign: SIGN,
+            key_based_encryption,
+            keki: 0,
+            cipher: CIPHER_AES_CTR,
+            auth: 0,
+            stream_encapsulation: STREAM_ENCAPSULATION_SRT,
+            salt: self.salt.to_vec(),
+            wrapped_key,
+        })
+    }
+
+    /// (bytes)
+    pub fn key_length(&self) -> usize {
+        self.even_key
+            .as_ref()
+            .or(self.odd_key.as_ref())
+            .map_or(0, Vec::len)
+    }
+
+    /// Decrypt the payload in place
+    pub fn decrypt(&self, data: &mut DataPacketInfo) -> Result<()> {
+        let key = match data.encryption {
+            EncryptionFlag::NoEncryption => return Ok(()),
+            EncryptionFlag::EvenKey => &self.even_key,
+            EncryptionFlag::OddKey => &self.odd_key,
+        };
+        let Some(key) = key else {
+            bail!("No {:?} to decrypt packet", data.encryption);
+        };
+
+        self.apply_keystream(key, data)?;
+        data.encryption = EncryptionFlag::NoEncryption;
+
+        Ok(())
+    }
+
+    /// Encrypt the payload in place (with the even key, if there is one)
+    pub fn encrypt(&self, data: &mut DataPacketInfo) -> Result<()> {
+        let (flag, key) = match (&self.even_key, &self.odd_key) {
+            (Some(key), _) => (EncryptionFlag::EvenKey, key),
+            (None, Some(key)) => (EncryptionFlag::OddKey, key),
+            (None, None) => bail!("No keys"),
+        };
+
+        self.apply_keystream(key, data)?;
+        data.encryption = flag;
+
+        Ok(())
+    }
+
+    fn apply_keystream(&self, key: &[u8], data: &mut DataPacketInfo) -> Result<()> {
+        // IV = salt ^ (packet index << 16)
+        let mut iv = [0; 16];
+        iv[10..14].copy_from_slice(&data.packet_sequence_number.get().to_be_bytes());
+        for (byte, salt) in iv[..14].iter_mut().zip(&self.salt) {
+            *byte ^= salt;
+        }
+
+        let content = &mut data.content;
+        match key.len() {
+            16 => ctr::Ctr128BE::<Aes128>::new_from_slices(key, &iv)?.apply_keystream(content),
+            24 => ctr::Ctr128BE::<Aes192>::new_from_slices(key, &iv)?.apply_keystream(content),
+            _ => ctr::Ctr128BE::<Aes256>::new_from_slices(key, &iv)?.apply_keystream(content),
+        }
+
+        Ok(())
+    }
+}
+
+fn derive_kek(passphrase: &str, salt: &[u8; SALT_LENGTH], key_length: usize) -> Vec<u8> {
+    let mut kek = vec![0; key_length];
+    pbkdf2::pbkdf2_hmac::<Sha1>(
+        passphrase.as_bytes(),
+        &salt[SALT_LENGTH - PBKDF2_SALT_LENGTH..],
+        PBKDF2_ITERATIONS,
+        &mut kek,
+    );
+
+    kek
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        packet::data::PacketPosition,
+        seq::{MsgNo, SeqNo},
+    };
+
+    #[test]
+    fn test_key_material_roundtrip() {
+        let cipher = Cipher::new([7; SALT_LENGTH], vec![3; 16]).unwrap();
+        let km = cipher.key_material("passphrase").unwrap();
+
+        assert!(Cipher::from_key_material("wrong passphrase", &km).is_err());
+        let peer = Cipher::from_key_material("passphrase", &km).unwrap();
+
+        let mut data = DataPacketInfo {
+            packet_sequence_number: SeqNo::new(12345),
+            position: PacketPosition::Single,
+            order: false,
+            encryption: EncryptionFlag::NoEncryption,
+            retransmitted: false,
+            message_number: MsgNo::new(1),
+            content: b"Hello, SRT!".to_vec(),
+        };
+
+        cipher.encrypt(&mut data).unwrap();
+        assert_eq!(data.encryption, EncryptionFlag::EvenKey);
+        assert_ne!(data.content, b"Hello, SRT!");
+
+        peer.decrypt(&mut data).unwrap();
+        assert_eq!(data.encryption, EncryptionFlag::NoEncryption);
+        assert_eq!(data.content, b"Hello, SRT!");
+    }
+}
@@ -0,0 +1,93 @@
+//! Packet parsing errors
+//!
+//! Offsets are relative to the start of the datagram.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Datagram ends before a field (`length` bytes at `offset`)
+    Truncated {
+        offset: usize,
+        length: usize,
+    },
+    UnknownControlType {
+        offset: usize,
+        r#type: u16,
+    },
+    BadValue {
+        offset: usize,
+        field: &'static str,
+        value: u64,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::Truncated { offset, .. }
+            | Self::UnknownControlType { offset, .. }
+            | Self::BadValue { offset, .. } => *offset,
+        }
+    }
+
+    /// Make the offset relative to an outer buffer, that starts `base` bytes earlier
+    pub(crate) fn offset_by(mut self, base: usize) -> Self {
+        match &mut self {
+            Self::Truncated { offset, .. }
+            | Self::UnknownControlType { offset, .. }
+            | Self::BadValue { offset, .. } => *offset += base,
+        }
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated { offset, length } => {
+                write!(f, "Packet truncated: {length} bytes expected at {offset}")
+            }
+            Self::UnknownControlType { offset, r#type } => {
+                write!(f, "Unknown control type 0x{type:X} at {offset}")
+            }
+            Self::BadValue {
+                offset,
+                field,
+                value,
+            } => write!(f, "Bad value of {field} at {offset}: 0x{value:X}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// `length` bytes at `offset`
+pub(crate) fn bytes_at(raw: &[u8], offset: usize, length: usize) -> Result<&[u8]> {
+    raw.get(offset..offset + length)
+        .ok_or(Error::Truncated { offset, length })
+}
+
+/// All bytes from `offset` on (may be empty)
+pub(crate) fn rest_at(raw: &[u8], offset: usize) -> Result<&[u8]> {
+    raw.get(offset..).ok_or_else(|| Error::Truncated {
+        offset: raw.len(),
+        length: offset - raw.len(),
+    })
+}
+
+pub(crate) fn u8_at(raw: &[u8], offset: usize) -> Result<u8> {
+    Ok(bytes_at(raw, offset, 1)?[0])
+}
+
+pub(crate) fn u16_at(raw: &[u8], offset: usize) -> Result<u16> {
+    let bytes = bytes_at(raw, offset, 2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+pub(crate) fn u32_at(raw: &[u8], offset: usize) -> Result<u32> {
+    let bytes = bytes_at(raw, offset, 4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
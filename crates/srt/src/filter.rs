@@ -0,0 +1,122 @@
+//! Packet filters (`SRT_CMD_FILTER`)
+//!
+//! Only the built-in `fec` filter is supported, e.g. `fec,cols:10,rows:5,arq:onreq`.
+//!
+//! <https://github.com/Haivision/srt/blob/master/docs/features/packet-filtering-and-fec.md>
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{Error, Result, bail};
+
+pub mod fec;
+
+/// When lost packets are requested with a Nak
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArqLevel {
+    /// Right away (FEC recovery runs in parallel)
+    #[default]
+    Always,
+    /// Only for packets, that FEC can no longer rebuild
+    OnReq,
+    /// Never (lost packets are left to FEC)
+    Never,
+}
+
+/// Configuration of the built-in `fec` filter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FecConfig {
+    /// # of packets in a row
+    pub cols: u32,
+    /// # of packets in a column (1 means row FEC only)
+    pub rows: u32,
+    pub arq: ArqLevel,
+}
+
+impl FromStr for FecConfig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split(',');
+
+        let name = parts.next().unwrap_or_default();
+        if name != "fec" {
+            bail!("Unsupported packet filter: {name}");
+        }
+
+        let mut cols = None;
+        let mut rows = 1;
+        let mut arq = ArqLevel::default();
+
+        for part in parts {
+            let Some((key, value)) = part.split_once(':') else {
+                bail!("Malformed packet filter option: {part}");
+            };
+
+            match key {
+                "cols" => cols = Some(value.parse()?),
+                "rows" => rows = value.parse()?,
+                // Columns are spaced by `cols` either way, only where they start differs
+                "layout" => {
+                    if !matches!(value, "even" | "staircase") {
+                        bail!("Unsupported FEC layout: {value}");
+                    }
+                }
+                "arq" => {
+                    arq = match value {
+                        "always" => ArqLevel::Always,
+                        "onreq" => ArqLevel::OnReq,
+                        "never" => ArqLevel::Never,
+                        _ => bail!("Unsupported ARQ level: {value}"),
+                    }
+                }
+                _ => bail!("Unsupported packet filter option: {key}"),
+            }
+        }
+
+        let Some(cols) = cols else {
+            bail!("FEC filter requires `cols`");
+        };
+        if cols == 0 || rows == 0 {
+            bail!("FEC matrix can't be empty: {cols}x{rows}");
+        }
+
+        Ok(Self { cols, rows, arq })
+    }
+}
+
+impl fmt::Display for FecConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arq = match self.arq {
+            ArqLevel::Always => "always",
+            ArqLevel::OnReq => "onreq",
+            ArqLevel::Never => "never",
+        };
+
+        write!(f, "fec,cols:{},rows:{},arq:{arq}", self.cols, self.rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: FecConfig = "fec,cols:10,rows:5,arq:onreq".parse().unwrap();
+        assert_eq!(
+            config,
+            FecConfig {
+                cols: 10,
+                rows: 5,
+                arq: ArqLevel::OnReq,
+            }
+        );
+        assert_eq!(config.to_string().parse::<FecConfig>().unwrap(), config);
+
+        assert!("fec,cols:10,layout:even".parse::<FecConfig>().is_ok());
+        assert!("fec,cols:10,layout:spiral".parse::<FecConfig>().is_err());
+        assert!("fec,rows:5".parse::<FecConfig>().is_err());
+        assert!("fec,cols:10,arq:sometimes".parse::<FecConfig>().is_err());
+        assert!("other,cols:10".parse::<FecConfig>().is_err());
+    }
+}
@@ -0,0 +1,428 @@
+//! Built-in row/column XOR FEC
+//!
+//! Every row of `cols` packets and every column of `rows` packets is protected
+//! by a FEC packet carrying the XOR of its members.
+//! A group, that misses exactly one packet, can rebuild it.
+//!
+//! FEC packets are data packets with message number [`FEC_MESSAGE_NUMBER`]
+//! and the sequence number of the last packet of their group.
+//!
+//! Clips don't carry the message number and position of packets, so a rebuilt packet
+//! takes them from its neighbours (a single packet message, if they aren't known).
+
+use std::collections::HashMap;
+
+use crate::{
+    error::{Result, rest_at, u8_at, u16_at},
+    filter::FecConfig,
+    packet::data::{DataPacketInfo, EncryptionFlag, PacketPosition},
+    seq::{MsgNo, SeqNo},
+};
+
+/// Message number of FEC packets (`SRT_MSGNO_CONTROL`)
+pub const FEC_MESSAGE_NUMBER: MsgNo = MsgNo::new(0);
+
+/// [`FecPacket::index`] of row FEC packets
+pub const ROW_INDEX: i8 = -1;
+
+/// Content of a FEC packet
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FecPacket {
+    /// Column, or [`ROW_INDEX`]
+    pub index: i8,
+    /// XOR of encryption flags
+    pub flag_clip: u8,
+    /// XOR of payload lengths
+    pub length_clip: u16,
+    /// XOR of timestamps (carried in the packet header)
+    pub timestamp_clip: u32,
+    /// XOR of payloads (padded with zeros)
+    pub payload_clip: Vec<u8>,
+}
+
+impl FecPacket {
+    pub fn from_raw(timestamp: u32, raw: &[u8]) -> Result<Self> {
+        Ok(Self {
+            index: i8::from_be_bytes([u8_at(raw, 0)?]),
+            flag_clip: u8_at(raw, 1)?,
+            length_clip: u16_at(raw, 2)?,
+            timestamp_clip: timestamp,
+            payload_clip: Vec::from(rest_at(raw, 4)?),
+        })
+    }
+
+    /// Content only (timestamp goes to the header)
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+
+        res.extend(self.index.to_be_bytes());
+        res.push(self.flag_clip);
+        res.extend(self.length_clip.to_be_bytes());
+        res.extend(&self.payload_clip);
+
+        res
+    }
+
+    /// XOR a packet into the clip
+    pub fn add(&mut self, timestamp: u32, data: &DataPacketInfo) {
+        #[allow(clippy::cast_possible_truncation)]
+        let length = data.content.len() as u16;
+
+        self.flag_clip ^= encryption_bits(data.encryption);
+        self.length_clip ^= length;
+        self.timestamp_clip ^= timestamp;
+
+        if self.payload_clip.len() < data.content.len() {
+            self.payload_clip.resize(data.content.len(), 0);
+        }
+        for (clip, byte) in self.payload_clip.iter_mut().zip(&data.content) {
+            *clip ^= byte;
+        }
+    }
+}
+
+fn encryption_bits(encryption: EncryptionFlag) -> u8 {
+    match encryption {
+        EncryptionFlag::NoEncryption => 0b00,
+        EncryptionFlag::EvenKey => 0b01,
+        EncryptionFlag::OddKey => 0b10,
+    }
+}
+
+/// Receiver side of the filter
+#[derive(Debug)]
+pub struct FecDecoder {
+    config: FecConfig,
+    /// Highest sequence number seen
+    highest: Option<SeqNo>,
+    /// Received and rebuilt packets (`timestamp`, packet)
+    packets: HashMap<SeqNo, (u32, DataPacketInfo)>,
+    /// FEC packets, whose group misses more than one packet (members, FEC)
+    pending: Vec<(Vec<SeqNo>, FecPacket)>,
+}
+
+impl FecDecoder {
+    pub fn new(config: FecConfig) -> Self {
+        Self {
+            config,
+            highest: None,
+            packets: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &FecConfig {
+        &self.config
+    }
+
+    /// # of packets covered by one FEC matrix (plus the staircase offset)
+    fn span(&self) -> u32 {
+        (self.config.rows + 1) * self.config.cols
+    }
+
+    /// Packets from here on may still be rebuilt
+    ///
+    /// Losses below should be requested with a Nak.
+    pub fn recovery_horizon(&self) -> Option<SeqNo> {
+        Some(self.highest? - self.span())
+    }
+
+    /// Feed a data or FEC packet
+    ///
+    /// Returns packets, that were rebuilt thanks to it.
+    pub fn push(&mut self, timestamp: u32, data: &DataPacketInfo) -> Vec<(u32, DataPacketInfo)> {
+        let number = data.packet_sequence_number;
+        if self.highest.is_none_or(|highest| highest < number) {
+            self.highest = Some(number);
+        }
+
+        if data.message_number == FEC_MESSAGE_NUMBER {
+            match FecPacket::from_raw(timestamp, &data.content) {
+                Ok(fec) => {
+                    let members = self.members(number, fec.index);
+                    if members.is_empty() {
+                        tracing::warn!("Unexpected FEC index: {}", fec.index);
+                    } else {
+                        self.pending.push((members, fec));
+                    }
+                }
+                Err(e) => tracing::warn!("{e}"),
+            }
+        } else {
+            self.packets
+                .entry(number)
+                .or_insert_with(|| (timestamp, data.clone()));
+        }
+
+        let rebuilt = self.recover();
+        self.purge();
+
+        rebuilt
+    }
+
+    /// Sequence numbers of the group ending at `last`
+    fn members(&self, last: SeqNo, index: i8) -> Vec<SeqNo> {
+        let (count, step) = match index {
+            ROW_INDEX => (self.config.cols, 1),
+            column if column >= 0 && (column as u32) < self.config.cols => {
+                (self.config.rows, self.config.cols)
+            }
+            _ => return Vec::new(),
+        };
+
+        (0..count).map(|k| last - k * step).collect()
+    }
+
+    /// Rebuild packets until no group misses exactly one
+    fn recover(&mut self) -> Vec<(u32, DataPacketInfo)> {
+        let mut rebuilt = Vec::new();
+
+        loop {
+            let mut progress = false;
+
+            self.pending.retain(|(members, fec)| {
+                let missing: Vec<_> = members
+                    .iter()
+                    .filter(|n| !self.packets.contains_key(n))
+                    .collect();
+
+                match missing[..] {
+                    [] => false,
+                    [&number] => {
+                        if let Some(pack) = Self::rebuild(&self.packets, members, fec, number) {
+                            tracing::debug!("FEC: rebuilt packet {number}");
+                            rebuilt.push(pack.clone());
+                            self.packets.insert(number, pack);
+                            progress = true;
+                        }
+                        false
+                    }
+                    _ => true,
+                }
+            });
+
+            if !progress {
+                return rebuilt;
+            }
+        }
+    }
+
+    fn rebuild(
+        packets: &HashMap<SeqNo, (u32, DataPacketInfo)>,
+        members: &[SeqNo],
+        fec: &FecPacket,
+        number: SeqNo,
+    ) -> Option<(u32, DataPacketInfo)> {
+        let mut clip = fec.clone();
+        for member in members.iter().filter(|&&n| n != number) {
+            let (timestamp, data) = &packets[member];
+            clip.add(*timestamp, data);
+        }
+
+        let length = usize::from(clip.length_clip);
+        if length > clip.payload_clip.len() {
+            tracing::warn!("FEC: corrupted clip for packet {number}");
+            return None;
+        }
+        clip.payload_clip.truncate(length);
+
+        let encryption = match clip.flag_clip {
+            0b01 => EncryptionFlag::EvenKey,
+            0b10 => EncryptionFlag::OddKey,
+            _ => EncryptionFlag::NoEncryption,
+        };
+
+        let mut data = DataPacketInfo {
+            packet_sequence_number: number,
+            position: PacketPosition::Single,
+            order: false,
+            encryption,
+            retransmitted: false,
+            message_number: MsgNo::new(1),
+            content: clip.payload_clip,
+        };
+        Self::restore_message(packets, &mut data);
+
+        Some((clip.timestamp_clip, data))
+    }
+
+    /// Position and message number of a rebuilt packet from the packets around it
+    fn restore_message(packets: &HashMap<SeqNo, (u32, DataPacketInfo)>, data: &mut DataPacketInfo) {
+        let number = data.packet_sequence_number;
+        let prev = packets.get(&(number - 1)).map(|(_, data)| data);
+        let next = packets.get(&(number + 1)).map(|(_, data)| data);
+        // Whether a message goes on after a packet, and whether a packet goes on from one before
+        let goes_on = |data: &DataPacketInfo| {
+            matches!(
+                data.position,
+                PacketPosition::First | PacketPosition::Middle
+            )
+        };
+        let went_on = |data: &DataPacketInfo| {
+            matches!(data.position, PacketPosition::Middle | PacketPosition::Last)
+        };
+
+        let first = prev.is_none_or(|prev| !goes_on(prev));
+        let last = next.is_none_or(|next| !went_on(next));
+        data.position = match (first, last) {
+            (true, true) => PacketPosition::Single,
+            (true, false) => PacketPosition::First,
+            (false, false) => PacketPosition::Middle,
+            (false, true) => PacketPosition::Last,
+        };
+
+        data.message_number = match (prev, next) {
+            (Some(prev), _) if goes_on(prev) => prev.message_number,
+            (Some(prev), _) => prev.message_number.next(),
+            (None, Some(next)) if went_on(next) => next.message_number,
+            (None, Some(next)) => match next.message_number - 1 {
+                // 0 is skipped
+                number if number == FEC_MESSAGE_NUMBER => MsgNo::MAX,
+                number => number,
+            },
+            (None, None) => MsgNo::new(1),
+        };
+        if let Some(neighbour) = prev.or(next) {
+            data.order = neighbour.order;
+        }
+    }
+
+    /// Forget packets, that can't be part of an incomplete group anymore
+    fn purge(&mut self) {
+        let Some(highest) = self.highest else {
+            return;
+        };
+        let horizon = highest - 2 * self.span();
+
+        self.packets.retain(|&n, _| n >= horizon);
+        self.pending
+            .retain(|(members, _)| members.iter().all(|&n| n >= horizon));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::ArqLevel;
+
+    fn data(number: u32, content: &[u8]) -> DataPacketInfo {
+        DataPacketInfo {
+            packet_sequence_number: SeqNo::new(number),
+            position: PacketPosition::Single,
+            order: false,
+            encryption: EncryptionFlag::NoEncryption,
+            retransmitted: false,
+            message_number: MsgNo::new(1),
+            content: content.to_vec(),
+        }
+    }
+
+    /// FEC packet for `members` (the last one gives the sequence number)
+    fn fec(index: i8, members: &[&DataPacketInfo]) -> (u32, DataPacketInfo) {
+        let mut clip = FecPacket {
+            index,
+            ..Default::default()
+        };
+        for member in members {
+            clip.add(member.packet_sequence_number.get() * 10, member);
+        }
+
+        let mut pack = data(
+            members.last().unwrap().packet_sequence_number.get(),
+            &clip.to_raw(),
+        );
+        pack.message_number = FEC_MESSAGE_NUMBER;
+
+        (clip.timestamp_clip, pack)
+    }
+
+    fn decoder() -> FecDecoder {
+        FecDecoder::new(FecConfig {
+            cols: 3,
+            rows: 2,
+            arq: ArqLevel::Never,
+        })
+    }
+
+    #[test]
+    fn test_rebuild_from_row() {
+        let mut decoder = decoder();
+        let packets = [data(0, b"a"), data(1, b"bcd"), data(2, b"ef")];
+
+        decoder.push(0, &packets[0]);
+        decoder.push(20, &packets[2]);
+
+        let (timestamp, row) = fec(ROW_INDEX, &packets.iter().collect::<Vec<_>>());
+        let rebuilt = decoder.push(timestamp, &row);
+
+        assert_eq!(rebuilt.len(), 1);
+        let (timestamp, pack) = &rebuilt[0];
+        assert_eq!(*timestamp, 10);
+        assert_eq!(pack.packet_sequence_number, SeqNo::new(1));
+        assert_eq!(pack.content, b"bcd");
+    }
+
+    #[test]
+    fn test_rebuild_cascade() {
+        let mut decoder = decoder();
+        // 0 1 2
+        // 3 4 5
+        let packets: Vec<_> = (0..6).map(|n| data(n, &[n as u8; 4])).collect();
+
+        // Row 0 misses two packets, column 1 can rebuild one of them
+        for n in [0, 3, 4, 5] {
+            decoder.push(n * 10, &packets[n as usize]);
+        }
+
+        let (timestamp, row) = fec(ROW_INDEX, &[&packets[0], &packets[1], &packets[2]]);
+        assert!(decoder.push(timestamp, &row).is_empty());
+
+        let (timestamp, column) = fec(1, &[&packets[1], &packets[4]]);
+        let rebuilt: Vec<_> = decoder
+            .push(timestamp, &column)
+            .into_iter()
+            .map(|(_, pack)| (pack.packet_sequence_number.get(), pack.content))
+            .collect();
+
+        assert_eq!(rebuilt, vec![(1, vec![1; 4]), (2, vec![2; 4])]);
+    }
+
+    #[test]
+    fn test_restore_message() {
+        let mut decoder = decoder();
+        // Message 5 of 3 packets, then message 6
+        let mut packets = [data(0, b"a"), data(1, b"b"), data(2, b"c"), data(3, b"d")];
+        let positions = [
+            PacketPosition::First,
+            PacketPosition::Middle,
+            PacketPosition::Last,
+            PacketPosition::Single,
+        ];
+        for (pack, position) in packets.iter_mut().zip(positions) {
+            pack.position = position;
+            pack.message_number = MsgNo::new(5);
+        }
+        packets[3].message_number = MsgNo::new(6);
+
+        let row = |decoder: &mut FecDecoder, members: &[&DataPacketInfo]| {
+            let (timestamp, row) = fec(ROW_INDEX, members);
+            decoder.push(timestamp, &row).remove(0).1
+        };
+
+        // Middle of a message
+        decoder.push(0, &packets[0]);
+        decoder.push(20, &packets[2]);
+        let rebuilt = row(&mut decoder, &[&packets[0], &packets[1], &packets[2]]);
+        assert_eq!(rebuilt.position, PacketPosition::Middle);
+        assert_eq!(rebuilt.message_number, MsgNo::new(5));
+
+        // Next message (row 3 4 5)
+        let more = [data(4, b"e"), data(5, b"f")];
+        for pack in &more {
+            decoder.push(0, pack);
+        }
+        let rebuilt = row(&mut decoder, &[&packets[3], &more[0], &more[1]]);
+        assert_eq!(rebuilt.position, PacketPosition::Single);
+        assert_eq!(rebuilt.message_number, MsgNo::new(6));
+    }
+}
@@ -0,0 +1,234 @@
+//! Socket groups (connection bonding)
+//!
+//! Members of a group deliver into one shared [`ReceiveBuffer`],
+//! so packets received over several links are merged by sequence number and deduplicated.
+//! When receiving, this covers both broadcast (every link carries every packet)
+//! and main/backup (a backup link takes over after the main one fails).
+//! Switching is up to the sender: the listener takes data from whichever link carries it
+//! and only logs the switch.
+//!
+//! Main/backup failover isn't supported when sending (request mode):
+//! every member gets every packet, as in a broadcast group.
+//!
+//! Links of a bonded caller usually come from different addresses, so a group is found
+//! by the caller's group ID alone. A member has to ask for the same stream ID as the group,
+//! so a caller can't join a group of a different stream.
+//!
+//! Nothing else ties a member to the first one: without a passphrase, any caller that knows
+//! (or guesses) the group ID and stream ID joins the group and can inject packets into it.
+//! With a passphrase, every member has to prove it before joining, so set one for bonded streams.
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1.4>
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+    },
+};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    buffer::ReceiveBuffer,
+    packet::control::handshake::extension::group_membership::{
+        GroupMembershipExtension, group_type,
+    },
+    seq::SeqNo,
+};
+
+/// Set on every group ID (`SRTGROUP_MASK`)
+const GROUP_ID_MASK: u32 = 1 << 30;
+
+#[derive(Debug)]
+pub struct Group {
+    /// Group ID, assigned by the caller
+    pub peer_group_id: u32,
+    /// Group ID, sent back to the caller
+    pub group_id: u32,
+    /// Refer to [`group_type`]
+    pub r#type: u8,
+    /// Of the first member, every member must use the same
+    pub stream_id: Option<String>,
+
+    members: AtomicUsize,
+
+    /// Link that delivered the last data packet (main/backup only)
+    active_link: Mutex<Option<SocketAddr>>,
+
+    pub(crate) receive_buffer: Mutex<ReceiveBuffer>,
+}
+
+impl Group {
+    /// Track the link that is currently carrying data (main/backup only)
+    pub(crate) fn mark_active(&self, addr: SocketAddr) {
+        if self.r#type != group_type::MAIN_BACKUP {
+            return;
+        }
+
+        let mut active_link = self.active_link.lock().unwrap();
+
+        if *active_link != Some(addr) {
+            if let Some(prev) = *active_link {
+                tracing::info!(
+                    "Group 0x{:X}: switched from {prev} to {addr}",
+                    self.peer_group_id
+                );
+            }
+            *active_link = Some(addr);
+        }
+    }
+
+    pub fn members(&self) -> usize {
+        self.members.load(Ordering::Relaxed)
+    }
+}
+
+/// All groups of a listener (shared between workers)
+#[derive(Debug, Default)]
+pub struct Groups {
+    groups: Mutex<HashMap<u32, Arc<Group>>>,
+    next_id: AtomicU32,
+}
+
+impl Groups {
+    /// Add a member link to the group (creating the group on first member)
+    ///
+    /// Returns the group and whether it was just created.
+    pub fn join(
+        &self,
+        ext: &GroupMembershipExtension,
+        stream_id: Option<&str>,
+        initial_packet_sequence_number: SeqNo,
+        receive_buffer_size: usize,
+    ) -> Result<(Arc<Group>, bool)> {
+        if !matches!(ext.r#type, group_type::BROADCAST | group_type::MAIN_BACKUP) {
+            bail!("Unsupported group type: {}", ext.r#type);
+        }
+
+        let mut groups = self.groups.lock().unwrap();
+
+        if let Some(group) = groups.get(&ext.group_id) {
+            if group.r#type != ext.r#type {
+                bail!("Group type mismatch: {} != {}", group.r#type, ext.r#type);
+            }
+            if group.stream_id.as_deref() != stream_id {
+                bail!(
+                    "Stream ID mismatch in group 0x{:X}: {stream_id:?} != {:?}",
+                    ext.group_id,
+                    group.stream_id
+                );
+            }
+            group.members.fetch_add(1, Ordering::Relaxed);
+
+            return Ok((group.clone(), false));
+        }
+
+        let group = Arc::new(Group {
+            peer_group_id: ext.group_id,
+            group_id: self.next_id.fetch_add(1, Ordering::Relaxed) | GROUP_ID_MASK,
+            r#type: ext.r#type,
+            stream_id: stream_id.map(ToOwned::to_owned),
+            members: AtomicUsize::new(1),
+            active_link: Mutex::new(None),
+            receive_buffer: Mutex::new(ReceiveBuffer::new(
+                initial_packet_sequence_number,
+                receive_buffer_size,
+            )),
+        });
+        groups.insert(ext.group_id, group.clone());
+
+        Ok((group, true))
+    }
+
+    /// Remove a member link
+    ///
+    /// Returns `true` if it was the last one.
+    pub fn leave(&self, group: &Group) -> bool {
+        let mut groups = self.groups.lock().unwrap();
+
+        let last = group.members.fetch_sub(1, Ordering::Relaxed) == 1;
+        if last {
+            groups.remove(&group.peer_group_id);
+        }
+
+        last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ext(group_id: u32, r#type: u8) -> GroupMembershipExtension {
+        GroupMembershipExtension {
+            group_id,
+            r#type,
+            flags: 0,
+            weight: 0,
+        }
+    }
+
+    #[test]
+    fn test_join_leave() {
+        let groups = Groups::default();
+
+        let (a, created) = groups
+            .join(&ext(7, group_type::BROADCAST), None, SeqNo::new(0), 8)
+            .unwrap();
+        assert!(created);
+        let (b, created) = groups
+            .join(&ext(7, group_type::BROADCAST), None, SeqNo::new(0), 8)
+            .unwrap();
+        assert!(!created);
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(a.members(), 2);
+
+        assert!(!groups.leave(&a));
+        assert!(groups.leave(&b));
+
+        // Group is created anew
+        let (_, created) = groups
+            .join(&ext(7, group_type::MAIN_BACKUP), None, SeqNo::new(0), 8)
+            .unwrap();
+        assert!(created);
+    }
+
+    #[test]
+    fn test_unsupported_type() {
+        let groups = Groups::default();
+
+        assert!(
+            groups
+                .join(&ext(7, group_type::BALANCING), None, SeqNo::new(0), 8)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_stream_id_mismatch() {
+        let groups = Groups::default();
+
+        let (group, _) = groups
+            .join(
+                &ext(7, group_type::BROADCAST),
+                Some("live/a"),
+                SeqNo::new(0),
+                8,
+            )
+            .unwrap();
+        assert!(
+            groups
+                .join(
+                    &ext(7, group_type::BROADCAST),
+                    Some("live/b"),
+                    SeqNo::new(0),
+                    8
+                )
+                .is_err()
+        );
+        assert_eq!(group.members(), 1);
+    }
+}
@@ -0,0 +1,211 @@
+//! Messages of a stream, waiting to be read
+//!
+//! Shared by [`crate::listener::Stream`] and its async twin: the listener pushes delivered
+//! messages, the stream reads them whole or as bytes (blocking, or polled by tokio).
+//! Up to [`STREAM_QUEUE_SIZE`] messages wait to be read, later ones are dropped and counted.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use anyhow::{Result, bail};
+
+use crate::constants::STREAM_QUEUE_SIZE;
+
+#[derive(Default)]
+struct State {
+    messages: VecDeque<Vec<u8>>,
+    dropped: u64,
+    /// Sender is gone, nothing is queued after what's left
+    closed: bool,
+    /// Reader is gone
+    abandoned: bool,
+    /// Async reader waiting for a message
+    waker: Option<Waker>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    readable: Condvar,
+}
+
+impl Shared {
+    fn notify(&self, state: &mut State) {
+        self.readable.notify_one();
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+pub fn channel() -> (InboxTx, Inbox) {
+    let shared = Arc::new(Shared::default());
+    let inbox = Inbox {
+        shared: Arc::clone(&shared),
+        pending: Vec::new(),
+        pending_offset: 0,
+    };
+
+    (InboxTx { shared }, inbox)
+}
+
+/// Sending side of an [`Inbox`] (closes it when dropped)
+pub struct InboxTx {
+    shared: Arc<Shared>,
+}
+
+impl InboxTx {
+    /// Queue a message, or drop it if the queue is full (not read fast enough)
+    ///
+    /// Fails once the [`Inbox`] was dropped by the application.
+    pub fn send(&self, message: &[u8]) -> Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.abandoned {
+            bail!("Stream was dropped");
+        }
+
+        if state.messages.len() < STREAM_QUEUE_SIZE {
+            state.messages.push_back(message.to_vec());
+            self.shared.notify(&mut state);
+        } else {
+            state.dropped += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for InboxTx {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        self.shared.notify(&mut state);
+    }
+}
+
+/// Receiving side, with the unread part of the last message
+pub struct Inbox {
+    shared: Arc<Shared>,
+
+    /// Unread part of the last message (used by [`Inbox::read`])
+    pending: Vec<u8>,
+    pending_offset: usize,
+}
+
+impl Inbox {
+    /// Wait for the next message
+    ///
+    /// Returns `None` once the connection is closed.
+    pub fn recv(&self) -> Option<Vec<u8>> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(message) = state.messages.pop_front() {
+                return Some(message);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.shared.readable.wait(state).unwrap();
+        }
+    }
+
+    /// Same as [`Inbox::recv`], for a task
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(message) = state.messages.pop_front() {
+            return Poll::Ready(Some(message));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// # of messages dropped, because they weren't read in time
+    pub fn dropped(&self) -> u64 {
+        self.shared.state.lock().unwrap().dropped
+    }
+
+    /// Read bytes, waiting for the next message if the last one was read
+    ///
+    /// Returns 0 once the connection is closed.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        while self.pending_offset == self.pending.len() {
+            let Some(message) = self.recv() else {
+                return 0;
+            };
+            self.set_pending(message);
+        }
+
+        self.read_pending(buf)
+    }
+
+    /// Same as [`Inbox::read`], for a task
+    pub fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<usize> {
+        while self.pending_offset == self.pending.len() {
+            let Some(message) = std::task::ready!(self.poll_recv(cx)) else {
+                return Poll::Ready(0);
+            };
+            self.set_pending(message);
+        }
+
+        Poll::Ready(self.read_pending(buf))
+    }
+
+    fn set_pending(&mut self, message: Vec<u8>) {
+        self.pending = message;
+        self.pending_offset = 0;
+    }
+
+    fn read_pending(&mut self, buf: &mut [u8]) -> usize {
+        let available = &self.pending[self.pending_offset..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.pending_offset += n;
+
+        n
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.abandoned = true;
+        state.messages.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue() {
+        let (tx, mut inbox) = channel();
+
+        for i in 0..=STREAM_QUEUE_SIZE {
+            tx.send(&i.to_be_bytes()).unwrap();
+        }
+        assert_eq!(inbox.dropped(), 1);
+        assert_eq!(inbox.recv().unwrap(), 0usize.to_be_bytes());
+
+        // Messages are read in parts
+        let mut buf = [0; 6];
+        assert_eq!(inbox.read(&mut buf), 6);
+        assert_eq!(inbox.read(&mut buf), 2);
+        assert_eq!(buf[..2], 1usize.to_be_bytes()[6..]);
+
+        // What's left is read after the sender is gone
+        drop(tx);
+        assert_eq!(inbox.recv().unwrap(), 2usize.to_be_bytes());
+
+        let (tx, inbox) = channel();
+        drop(inbox);
+        assert!(tx.send(b"unread").is_err());
+    }
+}
@@ -3,10 +3,33 @@
 #![allow(clippy::missing_errors_doc)]
 #![forbid(clippy::print_stdout)]
 
+pub mod access;
+pub mod admission;
+pub mod batch;
+pub mod buffer;
+pub mod capture;
+pub mod clock;
 pub mod connection;
 pub mod constants;
+pub mod crypto;
+pub mod error;
+pub mod filter;
+pub mod group;
+pub mod inbox;
+pub mod listener;
 pub mod macros;
 pub mod ops;
+pub mod options;
 pub mod packet;
+pub mod relay;
+pub mod seq;
 pub mod serial;
 pub mod server;
+pub mod socket;
+pub mod subscription;
+pub mod timeline;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+pub mod transport;
+
+pub use error::Error;
@@ -0,0 +1,240 @@
+//! Socket-like (pull) API on top of [`Server`]
+
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+};
+
+use anyhow::{Result, anyhow};
+
+use crate::{
+    connection::{Connection, StreamKey},
+    inbox::{self, Inbox, InboxTx},
+    options::ListenerOptions,
+    packet::control::peer_error::error_codes,
+    server::Server,
+};
+
+/// Listens for SRT callers on a background thread
+///
+/// Every established connection is returned by [`Listener::accept`] as an owned [`Stream`],
+/// which can be moved to its own thread.
+pub struct Listener {
+    local_addr: SocketAddr,
+    incoming: Receiver<Stream>,
+}
+
+impl Listener {
+    pub fn bind<A>(addr: A) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        Self::bind_with_options(addr, ListenerOptions::default())
+    }
+
+    /// Same as [`Listener::bind`], with options (defaults and overrides by stream ID)
+    pub fn bind_with_options<A>(addr: A, options: ListenerOptions) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        options.validate()?;
+
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("No address to bind to"))?;
+
+        let (bound_tx, bound_rx) = mpsc::channel();
+        let (incoming_tx, incoming) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut server = match Server::new(addr) {
+                Ok(server) => server,
+                Err(e) => {
+                    _ = bound_tx.send(Err(e));
+                    return;
+                }
+            };
+            _ = bound_tx.send(server.options(options).and_then(|()| server.local_addr()));
+
+            Self::register(&mut server, incoming_tx);
+
+            if let Err(e) = server.run() {
+                tracing::error!("Listener stopped: {e}");
+            }
+        });
+
+        let local_addr = bound_rx
+            .recv()
+            .map_err(|_| anyhow!("Listener thread exited"))??;
+
+        Ok(Self {
+            local_addr,
+            incoming,
+        })
+    }
+
+    /// Route server callbacks to per-connection channels
+    fn register(server: &mut Server, incoming: Sender<Stream>) {
+        let streams = Arc::new(Mutex::new(HashMap::<StreamKey, InboxTx>::new()));
+
+        server.on_connect({
+            let streams = streams.clone();
+            move |conn| {
+                let (tx, inbox) = inbox::channel();
+                if incoming.send(Stream::new(conn, inbox)).is_ok() {
+                    streams.lock().unwrap().insert(conn.stream_key(), tx);
+                }
+            }
+        });
+
+        server.on_disconnect({
+            let streams = streams.clone();
+            move |conn, _| {
+                streams.lock().unwrap().remove(&conn.stream_key());
+            }
+        });
+
+        server.on_data(move |conn, data| {
+            let mut streams = streams.lock().unwrap();
+
+            let Some(stream) = streams.get(&conn.stream_key()) else {
+                return;
+            };
+
+            // Handle was dropped by the application
+            if stream.send(data).is_err() {
+                streams.remove(&conn.stream_key());
+                _ = conn.fail(error_codes::WRITE);
+            }
+        });
+    }
+
+    /// Wait for the next established connection
+    pub fn accept(&self) -> Result<Stream> {
+        self.incoming
+            .recv()
+            .map_err(|_| anyhow!("Listener thread exited"))
+    }
+
+    pub fn incoming(&self) -> impl Iterator<Item = Stream> + '_ {
+        self.incoming.iter()
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+/// Owned handle of an established connection
+///
+/// Yields messages in sequence order. Reading ends once the peer disconnects.
+/// Up to [`STREAM_QUEUE_SIZE`](crate::constants::STREAM_QUEUE_SIZE) messages wait to be read,
+/// later ones are dropped.
+pub struct Stream {
+    pub stream_id: Option<String>,
+    pub addr: SocketAddr,
+    pub peer_srt_socket_id: u32,
+
+    inbox: Inbox,
+}
+
+impl Stream {
+    fn new(conn: &Connection, inbox: Inbox) -> Self {
+        Self {
+            stream_id: conn.stream_id.clone(),
+            addr: conn.addr,
+            peer_srt_socket_id: conn.peer_srt_socket_id,
+            inbox,
+        }
+    }
+
+    /// Wait for the next message
+    ///
+    /// Returns `None` once the connection is closed.
+    pub fn recv(&self) -> Option<Vec<u8>> {
+        self.inbox.recv()
+    }
+
+    /// # of messages dropped, because they weren't read in time
+    pub fn dropped(&self) -> u64 {
+        self.inbox.dropped()
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self.inbox.read(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::UdpSocket,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::{
+        clock::SystemClock, connection::caller::Caller, constants::MAX_PACKET_SIZE,
+        options::Options, packet::Packet,
+    };
+
+    #[test]
+    fn test_loopback() {
+        let listener = Listener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        let mut caller = Caller::new(
+            server,
+            Some("live/cam1".to_owned()),
+            Options::default(),
+            Arc::new(SystemClock),
+        )
+        .unwrap();
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let conn = loop {
+            if let Some(pack) = caller.poll_transmit() {
+                socket.send_to(&pack.to_raw(), server).unwrap();
+            }
+            if let Ok((n, _)) = socket.recv_from(&mut buf)
+                && let Some(conn) = caller
+                    .handle(&Packet::from_raw(&buf[..n]).unwrap())
+                    .unwrap()
+            {
+                break conn;
+            }
+        };
+
+        let mut stream = listener.accept().unwrap();
+        assert_eq!(stream.stream_id.as_deref(), Some("live/cam1"));
+        assert_eq!(stream.addr, socket.local_addr().unwrap());
+
+        for message in [&b"hello"[..], b"SRT"] {
+            conn.send_message(message).unwrap();
+        }
+        while let Some(pack) = conn.poll_transmit() {
+            socket.send_to(&pack.to_raw(), server).unwrap();
+        }
+
+        let start = Instant::now();
+        assert_eq!(stream.recv().unwrap(), b"hello");
+        // Played after the latency (TSBPD)
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        let mut read = [0; 8];
+        assert_eq!(stream.read(&mut read).unwrap(), 3);
+        assert_eq!(&read[..3], b"SRT");
+        assert_eq!(stream.dropped(), 0);
+    }
+}
@@ -0,0 +1,239 @@
+//! Socket options (in the spirit of libsrt `SRTO_*`)
+//!
+//! A listener has default [`Options`], that can be overridden per stream ID,
+//! and [`Limits`] and [`AccessControl`] rules for callers, that apply to the listener as a whole.
+
+use std::{collections::HashMap, fmt, str::FromStr, time::Duration};
+
+use anyhow::{Error, Result, bail};
+
+use crate::{
+    access::AccessControl,
+    constants::{
+        CONNECT_TIMEOUT, DEFAULT_LATENCY, MAX_PACKET_SIZE, PACKET_OVERHEAD, PEER_IDLE_TIMEOUT,
+        RECEIVE_BUFFER_SIZE,
+    },
+};
+
+/// Congestion control type (`SRTO_CONGESTION`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Congestion {
+    #[default]
+    Live,
+    File,
+}
+
+impl FromStr for Congestion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "live" => Ok(Self::Live),
+            "file" => Ok(Self::File),
+            _ => bail!("Unsupported congestion control: {s}"),
+        }
+    }
+}
+
+impl fmt::Display for Congestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Live => "live",
+            Self::File => "file",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// Receiver TSBPD delay, the peer may ask for more (`SRTO_RCVLATENCY`)
+    pub latency: Duration,
+    /// Max payload of a data packet (`SRTO_PAYLOADSIZE`, bytes)
+    pub payload_size: usize,
+    /// (`SRTO_RCVBUF`, packets)
+    pub receive_buffer_size: usize,
+    /// Max # of packets in flight (`SRTO_FC`)
+    pub flow_window: u32,
+    /// Encrypt with this passphrase, required from callers of a listener (`SRTO_PASSPHRASE`)
+    pub passphrase: Option<String>,
+    /// Required key length, any if `None` (`SRTO_PBKEYLEN`, bytes)
+    ///
+    /// Callers generate keys of this length (16 if `None`).
+    pub key_length: Option<usize>,
+    /// (`SRTO_PEERIDLETIMEO`)
+    pub peer_idle_timeout: Duration,
+    /// How long a caller waits for the listener to answer (`SRTO_CONNTIMEO`)
+    pub connect_timeout: Duration,
+    /// Limit of the data sending rate, unlimited if `None` (`SRTO_MAXBW`, bytes/s)
+    ///
+    /// Includes retransmissions and packet headers, control packets aren't limited.
+    /// The peer's congestion warnings lower the rate for a while (also without a limit).
+    pub max_bandwidth: Option<u64>,
+    /// (`SRTO_CONGESTION`, only live is supported)
+    pub congestion: Congestion,
+}
+
+impl Default for Options {
+    #[allow(clippy::cast_possible_truncation)]
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(DEFAULT_LATENCY.into()),
+            payload_size: MAX_PACKET_SIZE - PACKET_OVERHEAD,
+            receive_buffer_size: RECEIVE_BUFFER_SIZE,
+            flow_window: RECEIVE_BUFFER_SIZE as u32,
+            passphrase: None,
+            key_length: None,
+            peer_idle_timeout: Duration::from_micros(PEER_IDLE_TIMEOUT.into()),
+            connect_timeout: Duration::from_micros(CONNECT_TIMEOUT.into()),
+            max_bandwidth: None,
+            congestion: Congestion::default(),
+        }
+    }
+}
+
+impl Options {
+    /// Check values against libsrt limits
+    pub fn validate(&self) -> Result<()> {
+        if self.latency.as_millis() > u16::MAX.into() {
+            bail!("Latency too large: {:?}", self.latency);
+        }
+        if !(1..=MAX_PACKET_SIZE - PACKET_OVERHEAD).contains(&self.payload_size) {
+            bail!("Invalid payload size: {}", self.payload_size);
+        }
+        if self.receive_buffer_size == 0 || self.flow_window == 0 {
+            bail!("Receive buffer and flow window can't be empty");
+        }
+        if let Some(passphrase) = &self.passphrase
+            && !(10..=79).contains(&passphrase.len())
+        {
+            bail!("Passphrase must be 10 to 79 characters long");
+        }
+        if let Some(key_length) = self.key_length
+            && !matches!(key_length, 16 | 24 | 32)
+        {
+            bail!("Invalid key length: {key_length}");
+        }
+        if self.max_bandwidth == Some(0) {
+            bail!("Max bandwidth can't be 0");
+        }
+        if self.congestion != Congestion::Live {
+            bail!("Unsupported congestion control: {}", self.congestion);
+        }
+
+        Ok(())
+    }
+}
+
+/// Protection against handshake floods (see [`crate::admission`])
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Max # of concurrent connections, unlimited if `None`
+    pub max_connections: Option<usize>,
+    /// Handshake packets accepted from one IP address per second, unlimited if `None`
+    pub handshake_rate: Option<u32>,
+    /// Handshake packets one IP address may send at once
+    pub handshake_burst: u32,
+    /// # of IP addresses tracked for the rate limit (others are dropped while it's full)
+    pub max_tracked_addresses: usize,
+    /// How long a Conclusion may use the cookie of an Induction response (at least)
+    pub cookie_lifetime: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            // libsrt callers repeat each handshake step every 250 ms
+            handshake_rate: Some(10),
+            handshake_burst: 20,
+            max_tracked_addresses: 65_536,
+            cookie_lifetime: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Limits {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_connections == Some(0) || self.handshake_rate == Some(0) {
+            bail!("Connection and handshake limits can't be 0 (use None for unlimited)");
+        }
+        if self.handshake_burst == 0 || self.max_tracked_addresses == 0 {
+            bail!("Handshake burst and # of tracked addresses can't be 0");
+        }
+        if self.cookie_lifetime < Duration::from_secs(1) {
+            bail!("Cookie lifetime too short: {:?}", self.cookie_lifetime);
+        }
+
+        Ok(())
+    }
+}
+
+/// Options of a listener
+#[derive(Clone, Debug, Default)]
+pub struct ListenerOptions {
+    pub default: Options,
+    /// Overrides by stream ID
+    pub streams: HashMap<String, Options>,
+    pub limits: Limits,
+    /// Shared, so rules can be reloaded while the listener runs
+    pub access: AccessControl,
+}
+
+impl ListenerOptions {
+    /// Options for a caller with `stream_id`
+    pub fn get(&self, stream_id: Option<&str>) -> &Options {
+        stream_id
+            .and_then(|stream_id| self.streams.get(stream_id))
+            .unwrap_or(&self.default)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.default.validate()?;
+        for options in self.streams.values() {
+            options.validate()?;
+        }
+        self.limits.validate()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_overrides() {
+        let mut options = ListenerOptions::default();
+        options.streams.insert(
+            "secure".to_owned(),
+            Options {
+                passphrase: Some("0123456789".to_owned()),
+                ..Default::default()
+            },
+        );
+        assert!(options.validate().is_ok());
+
+        assert_eq!(options.get(None), &Options::default());
+        assert_eq!(options.get(Some("other")), &Options::default());
+        assert!(options.get(Some("secure")).passphrase.is_some());
+
+        options.default.passphrase = Some("short".to_owned());
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let file = Options {
+            congestion: "file".parse().unwrap(),
+            ..Default::default()
+        };
+        assert!(file.validate().is_err());
+
+        let no_bandwidth = Options {
+            max_bandwidth: Some(0),
+            ..Default::default()
+        };
+        assert!(no_bandwidth.validate().is_err());
+    }
+}
@@ -1,7 +1,10 @@
 pub mod control;
 pub mod data;
 
-use crate::packet::{control::ControlPacketInfo, data::DataPacketInfo};
+use crate::{
+    error::{Result, u8_at, u32_at},
+    packet::{control::ControlPacketInfo, data::DataPacketInfo},
+};
 
 #[derive(Debug)]
 pub enum PacketContent {
@@ -10,16 +13,24 @@ pub enum PacketContent {
 }
 
 impl PacketContent {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let packet_type = (raw[0] & 0b1000_0000) >> 7;
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let is_control = u8_at(raw, 0)? & 0b1000_0000 != 0;
 
-        Ok(match packet_type {
-            1 => Self::Control(ControlPacketInfo::from_raw(raw)?),
-            0 => Self::Data(DataPacketInfo::from_raw(raw)?),
-            _ => unreachable!(),
+        Ok(if is_control {
+            Self::Control(ControlPacketInfo::from_raw(raw)?)
+        } else {
+            Self::Data(DataPacketInfo::from_raw(raw)?)
         })
     }
 
+    /// Whole datagram (see [`crate::serial`])
+    pub fn to_raw(&self) -> Vec<u8> {
+        match self {
+            Self::Control(p) => p.to_raw(),
+            Self::Data(p) => p.to_raw(),
+        }
+    }
+
     pub fn raw_header(&self) -> Vec<u8> {
         match self {
             Self::Control(p) => p.raw_header(),
@@ -44,9 +55,9 @@ pub struct Packet {
 }
 
 impl Packet {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let timestamp = u32::from_be_bytes(raw[8..12].try_into()?);
-        let dest_socket_id = u32::from_be_bytes(raw[12..16].try_into()?);
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let timestamp = u32_at(raw, 8)?;
+        let dest_socket_id = u32_at(raw, 12)?;
         let content = PacketContent::from_raw(raw)?;
 
         Ok(Self {
@@ -67,3 +78,71 @@ impl Packet {
         res
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::Error,
+        packet::control::{control_types, nak::Nak},
+        seq::SeqNo,
+    };
+
+    #[test]
+    fn test_nak_roundtrip() {
+        let loss_list = vec![
+            Nak::Single {
+                lost_packet: SeqNo::new(5),
+            },
+            Nak::Range {
+                lost_packets_from: SeqNo::new(7),
+                lost_packets_to: SeqNo::new(9),
+            },
+        ];
+        let pack = Packet {
+            timestamp: 1,
+            dest_socket_id: 2,
+            content: PacketContent::Control(ControlPacketInfo::Nak(loss_list.clone())),
+        };
+
+        let PacketContent::Control(ControlPacketInfo::Nak(parsed)) =
+            Packet::from_raw(&pack.to_raw()).unwrap().content
+        else {
+            panic!("Not a NAK");
+        };
+        assert_eq!(parsed, loss_list);
+    }
+
+    #[test]
+    fn test_bad_packets() {
+        assert_eq!(
+            Packet::from_raw(&[0x80, 0, 0, 0, 0, 0]).unwrap_err(),
+            Error::Truncated {
+                offset: 8,
+                length: 4
+            }
+        );
+
+        let mut raw = [0; 16];
+        raw[0..2].copy_from_slice(&0x80_10u16.to_be_bytes());
+        assert_eq!(
+            Packet::from_raw(&raw).unwrap_err(),
+            Error::UnknownControlType {
+                offset: 0,
+                r#type: 0x10
+            }
+        );
+
+        // Handshake is cut in the middle of the CIF
+        raw[0..2].copy_from_slice(&(control_types::HANDSHAKE | 1 << 15).to_be_bytes());
+        let mut raw = raw.to_vec();
+        raw.extend([0; 10]);
+        assert_eq!(
+            Packet::from_raw(&raw).unwrap_err(),
+            Error::Truncated {
+                offset: 24,
+                length: 4
+            }
+        );
+    }
+}
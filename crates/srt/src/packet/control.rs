@@ -1,12 +1,16 @@
-use crate::packet::control::{
-    ack::Ack, ack_ack::AckAck, drop_req::DropReq, handshake::Handshake, nak::Nak,
-    peer_error::PeerError,
+use crate::{
+    error::{Error, Result, rest_at, u16_at},
+    packet::control::{
+        ack::Ack, ack_ack::AckAck, drop_req::DropReq, extended::Extended, handshake::Handshake,
+        nak::Nak, peer_error::PeerError,
+    },
 };
 
 // Control Information Field of different Types
 pub mod ack;
 pub mod ack_ack;
 pub mod drop_req;
+pub mod extended;
 pub mod handshake;
 pub mod nak;
 pub mod peer_error;
@@ -24,43 +28,59 @@ pub mod control_types {
     pub const OTHER: u16 = 0x7FFF;
 }
 
+/// CIF of types, that carry none
+///
+/// libsrt still sends 4 zero bytes (`m_extra_pad`), as `writev` can't send an empty buffer.
+pub(crate) const EXTRA_PAD: [u8; 4] = [0; 4];
+
 /// Contains `Type`, `Subtype`, `Type-specific Information`, `CIF`
 #[derive(Clone, Debug)]
 pub enum ControlPacketInfo {
     Handshake(Handshake),
     KeepAlive,
     Ack(Ack),
-    Nak(Nak),
+    /// Loss list
+    Nak(Vec<Nak>),
     CongestionWarning,
     Shutdown,
     AckAck(AckAck),
     DropReq(DropReq),
     PeerError(PeerError),
-    Other,
+    /// `UMSG_EXT`
+    Extended(Extended),
 }
 
 impl ControlPacketInfo {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let control_type = u16::from_be_bytes(raw[0..2].try_into()?) & !(1 << 15);
-        let _subtype = u16::from_be_bytes(raw[2..4].try_into()?);
-        let _type_specific = &raw[4..8];
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let control_type = u16_at(raw, 0)? & !(1 << 15);
+        let _subtype = u16_at(raw, 2)?;
+
+        // Timestamp and Destination Socket ID
+        let cif = rest_at(raw, 16)?;
 
         Ok(match control_type {
-            control_types::HANDSHAKE => Self::Handshake(Handshake::from_raw_cif(&raw[16..])?),
+            control_types::HANDSHAKE => {
+                Self::Handshake(Handshake::from_raw_cif(cif).map_err(|e| e.offset_by(16))?)
+            }
             control_types::KEEPALIVE => Self::KeepAlive,
             control_types::ACK => Self::Ack(Ack::from_raw(raw)?),
-            control_types::NAK => todo!("Nak"),
-            control_types::CONGESTION_WARNING => todo!("CongestionWarning"),
+            control_types::NAK => Self::Nak(Nak::from_raw_all(raw)?),
+            control_types::CONGESTION_WARNING => Self::CongestionWarning,
             control_types::SHUTDOWN => Self::Shutdown,
             control_types::ACKACK => Self::AckAck(AckAck::from_raw(raw)?),
             control_types::DROPREQ => Self::DropReq(DropReq::from_raw(raw)?),
-            control_types::PEER_ERROR => todo!("PeerError"),
-            control_types::OTHER => todo!("Other"),
+            control_types::PEER_ERROR => Self::PeerError(PeerError::from_raw(raw)?),
+            control_types::OTHER => Self::Extended(Extended::from_raw(raw)?),
 
-            _ => unreachable!(),
+            r#type => return Err(Error::UnknownControlType { offset: 0, r#type }),
         })
     }
 
+    /// Whole datagram (see [`crate::serial`])
+    pub fn to_raw(&self) -> Vec<u8> {
+        [self.raw_header(), vec![0; 8], self.raw_content()].concat()
+    }
+
     /// Get control header (`Type`, `Subtype` and `Type-specific Information`) with `Subtype` and `Type-specific Information` fields equal to 0.
     /// (For types, that don't have info in those fields)
     fn empty_header_with_type(r#type: u16) -> Vec<u8> {
@@ -81,7 +101,7 @@ impl ControlPacketInfo {
             Self::AckAck(ack_ack) => ack_ack.raw_header(),
             Self::DropReq(drop_req) => drop_req.raw_header(),
             Self::PeerError(peer_error) => peer_error.raw_header(),
-            Self::Other => Self::empty_header_with_type(control_types::OTHER),
+            Self::Extended(extended) => extended.raw_header(),
         }
     }
 
@@ -90,12 +110,15 @@ impl ControlPacketInfo {
         match self {
             Self::Handshake(h) => h.raw_content(),
             Self::Ack(ack) => ack.raw_content(),
-            Self::Nak(nak) => nak.raw_content(),
-            Self::DropReq(_) => todo!(),
-            Self::Other => todo!(),
+            Self::Nak(nak) => nak.iter().flat_map(Nak::raw_content).collect(),
+            Self::DropReq(drop_req) => drop_req.raw_content(),
+            Self::Extended(extended) => extended.raw_content(),
 
-            // Other types don't have CIF
-            _ => Vec::new(),
+            Self::KeepAlive
+            | Self::CongestionWarning
+            | Self::Shutdown
+            | Self::AckAck(_)
+            | Self::PeerError(_) => EXTRA_PAD.to_vec(),
         }
     }
 }
@@ -1,6 +1,10 @@
 //! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.4>
 
-use crate::packet::control::control_types;
+use crate::{
+    error::{Error, Result, u32_at},
+    packet::control::control_types,
+    seq::SeqNo,
+};
 
 #[derive(Clone, Debug)]
 pub enum Ack {
@@ -9,7 +13,7 @@ pub enum Ack {
         ack_number: u32,
 
         // CIF
-        last_ackd_packet_sequence_number: u32,
+        last_ackd_packet_sequence_number: SeqNo,
         rtt: u32,
         rtt_variance: u32,
         available_buffer_size: u32,
@@ -19,11 +23,11 @@ pub enum Ack {
     },
     Light {
         // CIF
-        last_ackd_packet_sequence_number: u32,
+        last_ackd_packet_sequence_number: SeqNo,
     },
     Small {
         // CIF
-        last_ackd_packet_sequence_number: u32,
+        last_ackd_packet_sequence_number: SeqNo,
         rtt: u32,
         rtt_variance: u32,
         available_buffer_size: u32,
@@ -32,19 +36,19 @@ pub enum Ack {
 
 impl Ack {
     /// 44 BYTES
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
         match raw.len() {
             // Full
             44 => {
-                let ack_number = u32::from_be_bytes(raw[4..8].try_into()?);
+                let ack_number = u32_at(raw, 4)?;
 
-                let last_ackd_packet_sequence_number = u32::from_be_bytes(raw[16..20].try_into()?);
-                let rtt = u32::from_be_bytes(raw[20..24].try_into()?);
-                let rtt_variance = u32::from_be_bytes(raw[24..28].try_into()?);
-                let available_buffer_size = u32::from_be_bytes(raw[28..32].try_into()?);
-                let packets_receiving_rate = u32::from_be_bytes(raw[32..36].try_into()?);
-                let estimated_link_capacity = u32::from_be_bytes(raw[36..40].try_into()?);
-                let receiving_rate = u32::from_be_bytes(raw[40..44].try_into()?);
+                let last_ackd_packet_sequence_number = SeqNo::new(u32_at(raw, 16)?);
+                let rtt = u32_at(raw, 20)?;
+                let rtt_variance = u32_at(raw, 24)?;
+                let available_buffer_size = u32_at(raw, 28)?;
+                let packets_receiving_rate = u32_at(raw, 32)?;
+                let estimated_link_capacity = u32_at(raw, 36)?;
+                let receiving_rate = u32_at(raw, 40)?;
 
                 Ok(Self::Full {
                     ack_number,
@@ -59,7 +63,7 @@ impl Ack {
             }
             // Light
             20 => {
-                let last_ackd_packet_sequence_number = u32::from_be_bytes(raw[16..20].try_into()?);
+                let last_ackd_packet_sequence_number = SeqNo::new(u32_at(raw, 16)?);
 
                 Ok(Self::Light {
                     last_ackd_packet_sequence_number,
@@ -67,10 +71,10 @@ impl Ack {
             }
             // Small
             32 => {
-                let last_ackd_packet_sequence_number = u32::from_be_bytes(raw[16..20].try_into()?);
-                let rtt = u32::from_be_bytes(raw[20..24].try_into()?);
-                let rtt_variance = u32::from_be_bytes(raw[24..28].try_into()?);
-                let available_buffer_size = u32::from_be_bytes(raw[28..32].try_into()?);
+                let last_ackd_packet_sequence_number = SeqNo::new(u32_at(raw, 16)?);
+                let rtt = u32_at(raw, 20)?;
+                let rtt_variance = u32_at(raw, 24)?;
+                let available_buffer_size = u32_at(raw, 28)?;
 
                 Ok(Self::Small {
                     last_ackd_packet_sequence_number,
@@ -79,10 +83,19 @@ impl Ack {
                     available_buffer_size,
                 })
             }
-            _ => Err(anyhow::anyhow!("Wrong package size")),
+            len => Err(Error::BadValue {
+                offset: 0,
+                field: "Ack length",
+                value: len as u64,
+            }),
         }
     }
 
+    /// Whole datagram (see [`crate::serial`])
+    pub fn to_raw(&self) -> Vec<u8> {
+        [self.raw_header(), vec![0; 8], self.raw_content()].concat()
+    }
+
     /// 8 BYTES
     pub fn raw_header(&self) -> Vec<u8> {
         let mut res = Vec::new();
@@ -114,7 +127,7 @@ impl Ack {
                 receiving_rate,
                 ..
             } => {
-                res.extend(last_ackd_packet_sequence_number.to_be_bytes());
+                res.extend(last_ackd_packet_sequence_number.get().to_be_bytes());
                 res.extend(rtt.to_be_bytes());
                 res.extend(rtt_variance.to_be_bytes());
                 res.extend(available_buffer_size.to_be_bytes());
@@ -125,7 +138,7 @@ impl Ack {
             Ack::Light {
                 last_ackd_packet_sequence_number,
             } => {
-                res.extend(last_ackd_packet_sequence_number.to_be_bytes());
+                res.extend(last_ackd_packet_sequence_number.get().to_be_bytes());
             }
             Ack::Small {
                 last_ackd_packet_sequence_number,
@@ -133,7 +146,7 @@ impl Ack {
                 rtt_variance,
                 available_buffer_size,
             } => {
-                res.extend(last_ackd_packet_sequence_number.to_be_bytes());
+                res.extend(last_ackd_packet_sequence_number.get().to_be_bytes());
                 res.extend(rtt.to_be_bytes());
                 res.extend(rtt_variance.to_be_bytes());
                 res.extend(available_buffer_size.to_be_bytes());
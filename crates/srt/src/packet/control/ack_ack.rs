@@ -1,4 +1,7 @@
-use crate::packet::control::control_types;
+use crate::{
+    error::{Result, u32_at},
+    packet::control::{EXTRA_PAD, control_types},
+};
 
 #[derive(Clone, Debug)]
 pub struct AckAck {
@@ -6,13 +9,18 @@ pub struct AckAck {
 }
 
 impl AckAck {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let ack_number = u32::from_be_bytes(raw[4..8].try_into()?);
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let ack_number = u32_at(raw, 4)?;
 
         Ok(Self { ack_number })
     }
 
     /// 8 BYTES
+    /// Whole datagram (see [`crate::serial`])
+    pub fn to_raw(&self) -> Vec<u8> {
+        [self.raw_header(), vec![0; 8], EXTRA_PAD.to_vec()].concat()
+    }
+
     pub fn raw_header(&self) -> Vec<u8> {
         let mut res = Vec::new();
 
@@ -1,18 +1,22 @@
-use anyhow::Result;
+use crate::{
+    error::{Result, u32_at},
+    packet::control::control_types,
+    seq::{MsgNo, SeqNo},
+};
 
 #[derive(Clone, Debug)]
 pub struct DropReq {
-    pub message_number: u32,
-    pub first_packet_sequence_number: u32,
-    pub last_packet_sequence_number: u32,
+    pub message_number: MsgNo,
+    pub first_packet_sequence_number: SeqNo,
+    pub last_packet_sequence_number: SeqNo,
 }
 
 impl DropReq {
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let message_number = u32::from_be_bytes(raw[4..8].try_into()?);
+        let message_number = MsgNo::new(u32_at(raw, 4)?);
 
-        let first_packet_sequence_number = u32::from_be_bytes(raw[16..20].try_into()?);
-        let last_packet_sequence_number = u32::from_be_bytes(raw[20..24].try_into()?);
+        let first_packet_sequence_number = SeqNo::new(u32_at(raw, 16)?);
+        let last_packet_sequence_number = SeqNo::new(u32_at(raw, 20)?);
 
         Ok(Self {
             message_number,
@@ -21,7 +25,29 @@ impl DropReq {
         })
     }
 
+    /// Whole datagram (see [`crate::serial`])
+    pub fn to_raw(&self) -> Vec<u8> {
+        [self.raw_header(), vec![0; 8], self.raw_content()].concat()
+    }
+
+    /// 8 BYTES
     pub fn raw_header(&self) -> Vec<u8> {
-        todo!()
+        let mut res = Vec::new();
+
+        res.extend((control_types::DROPREQ | (1 << 15)).to_be_bytes()); // Control Flag + Control Type
+        res.extend(0u16.to_be_bytes()); // Reserved
+        res.extend(self.message_number.get().to_be_bytes());
+
+        res
+    }
+
+    /// 8 BYTES
+    pub fn raw_content(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+
+        res.extend(self.first_packet_sequence_number.get().to_be_bytes());
+        res.extend(self.last_packet_sequence_number.get().to_be_bytes());
+
+        res
     }
 }
@@ -0,0 +1,82 @@
+//! Extended control packets (`UMSG_EXT`)
+//!
+//! `Subtype` is an `SRT_CMD_*` value (HSv4 handshake, key refresh, ...), with the content
+//! of the matching handshake extension as CIF (without its type/length header).
+//! Any other subtype is application-defined and carried as is.
+
+use crate::{
+    error::{Result, rest_at, u16_at},
+    packet::control::{
+        control_types,
+        handshake::extension::{Extension, extension_types},
+    },
+};
+
+#[derive(Clone, Debug)]
+pub enum Extended {
+    /// `SRT_CMD_*` subtype
+    Srt(Extension),
+    /// Application-defined subtype (e.g. tally or stream metadata)
+    User { subtype: u16, data: Vec<u8> },
+}
+
+impl Extended {
+    /// Whether `subtype` is reserved by SRT
+    pub fn is_srt_subtype(subtype: u16) -> bool {
+        (extension_types::HSREQ..=extension_types::GROUP).contains(&subtype)
+    }
+
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let subtype = u16_at(raw, 2)?;
+        let cif = rest_at(raw, 16)?;
+
+        if !Self::is_srt_subtype(subtype) {
+            return Ok(Self::User {
+                subtype,
+                data: Vec::from(cif),
+            });
+        }
+
+        // Restore the extension block header, so the extension parsers can be reused
+        #[allow(clippy::cast_possible_truncation)]
+        let length = cif.len().div_ceil(4) as u16;
+        let mut block = [subtype.to_be_bytes(), length.to_be_bytes()].concat();
+        block.extend(cif);
+        block.resize(4 + usize::from(length) * 4, 0);
+
+        // Block header takes the place of the 16-byte packet header
+        let (ext, _) = Extension::from_raw(&block).map_err(|e| e.offset_by(12))?;
+
+        Ok(Self::Srt(ext))
+    }
+
+    pub fn subtype(&self) -> u16 {
+        match self {
+            Self::Srt(ext) => ext.r#type(),
+            Self::User { subtype, .. } => *subtype,
+        }
+    }
+
+    /// Whole datagram (see [`crate::serial`])
+    pub fn to_raw(&self) -> Vec<u8> {
+        [self.raw_header(), vec![0; 8], self.raw_content()].concat()
+    }
+
+    /// 8 BYTES
+    pub fn raw_header(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+
+        res.extend((control_types::OTHER | (1 << 15)).to_be_bytes()); // Control Flag + Control Type
+        res.extend(self.subtype().to_be_bytes());
+        res.extend(0u32.to_be_bytes()); // Type-specific Information
+
+        res
+    }
+
+    pub fn raw_content(&self) -> Vec<u8> {
+        match self {
+            Self::Srt(ext) => ext.to_raw().split_off(4),
+            Self::User { data, .. } => data.clone(),
+        }
+    }
+}
@@ -2,11 +2,15 @@
 
 pub mod extension;
 
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
 use crate::{
+    error::{Error, Result, bytes_at, rest_at, u16_at, u32_at},
     macros::auto_try_from,
     packet::control::handshake::extension::{
-        extension_flags, handshake::HandshakeExtension, key_material::KeyMaterialExtension,
-        stream_id::StreamIdExtension,
+        Extension, congestion::CongestionExtension, group_membership::GroupMembershipExtension,
+        handshake::HandshakeExtension, key_material::KeyMaterialExtension,
+        packet_filter::PacketFilterExtension, stream_id::StreamIdExtension,
     },
 };
 
@@ -53,56 +57,41 @@ pub struct Handshake {
     pub handshake_type: HandshakeType,
     pub srt_socket_id: u32,
     pub syn_cookie: u32,
-    pub peer_ip_address: (u32, u32, u32, u32),
-    pub handshake_extension: Option<HandshakeExtension>,
-    pub key_material_extension: Option<KeyMaterialExtension>,
-    pub stream_id_extension: Option<StreamIdExtension>,
+    /// Address of the receiver of this handshake, as seen by its sender
+    pub peer_ip_address: IpAddr,
+    /// In order of appearance
+    pub extensions: Vec<Extension>,
 }
 
 impl Handshake {
-    pub fn from_raw_cif(raw: &[u8]) -> anyhow::Result<Self> {
-        let version = u32::from_be_bytes(raw[0..4].try_into()?);
-
-        let encryption = u16::from_be_bytes(raw[4..6].try_into()?).try_into()?;
-        let extension_field = u16::from_be_bytes(raw[6..8].try_into()?);
-
-        let initial_packet_sequence_number = u32::from_be_bytes(raw[8..12].try_into()?);
-        let maximum_transmission_unit_size = u32::from_be_bytes(raw[12..16].try_into()?);
-        let maximum_flow_window_size = u32::from_be_bytes(raw[16..20].try_into()?);
-        let handshake_type = u32::from_be_bytes(raw[20..24].try_into()?).try_into()?;
-        let srt_socket_id = u32::from_be_bytes(raw[24..28].try_into()?);
-        let syn_cookie = u32::from_be_bytes(raw[28..32].try_into()?);
-
-        let peer_ip_address = (
-            u32::from_be_bytes(raw[32..36].try_into()?),
-            u32::from_be_bytes(raw[36..40].try_into()?),
-            u32::from_be_bytes(raw[40..44].try_into()?),
-            u32::from_be_bytes(raw[44..48].try_into()?),
-        );
+    pub fn from_raw_cif(raw: &[u8]) -> Result<Self> {
+        let version = u32_at(raw, 0)?;
 
-        // Extensions
-        let mut ext_pad = 0;
+        let encryption = u16_at(raw, 4)?
+            .try_into()
+            .map_err(|value: u16| Error::BadValue {
+                offset: 4,
+                field: "Encryption",
+                value: value.into(),
+            })?;
+        let extension_field = u16_at(raw, 6)?;
 
-        let handshake_extension = if extension_field & extension_flags::HSREQ != 0 {
-            ext_pad += 4 * 4;
-            Some(HandshakeExtension::from_raw(&raw[48..])?)
-        } else {
-            None
-        };
+        let initial_packet_sequence_number = u32_at(raw, 8)?;
+        let maximum_transmission_unit_size = u32_at(raw, 12)?;
+        let maximum_flow_window_size = u32_at(raw, 16)?;
+        let handshake_type = u32_at(raw, 20)?
+            .try_into()
+            .map_err(|value: u32| Error::BadValue {
+                offset: 20,
+                field: "Handshake Type",
+                value: value.into(),
+            })?;
+        let srt_socket_id = u32_at(raw, 24)?;
+        let syn_cookie = u32_at(raw, 28)?;
 
-        let key_material_extension = if (extension_field & extension_flags::KMREQ != 0)
-            && handshake_type != HandshakeType::Induction
-        {
-            Some(KeyMaterialExtension::from_raw(&raw[(48 + ext_pad)..])?)
-        } else {
-            None
-        };
+        let peer_ip_address = ip_from_raw(bytes_at(raw, 32, 16)?);
 
-        let stream_id_extension = if extension_field & extension_flags::CONFIG != 0 {
-            Some(StreamIdExtension::from_raw(&raw[(48 + ext_pad)..])?)
-        } else {
-            None
-        };
+        let extensions = Extension::from_raw_all(rest_at(raw, 48)?).map_err(|e| e.offset_by(48))?;
 
         Ok(Self {
             version,
@@ -115,9 +104,51 @@ impl Handshake {
             srt_socket_id,
             syn_cookie,
             peer_ip_address,
-            handshake_extension,
-            key_material_extension,
-            stream_id_extension,
+            extensions,
+        })
+    }
+
+    /// HSREQ or HSRSP
+    pub fn handshake_extension(&self) -> Option<&HandshakeExtension> {
+        self.extensions.iter().find_map(|ext| match ext {
+            Extension::Handshake(ext) => Some(ext),
+            _ => None,
+        })
+    }
+
+    /// KMREQ or KMRSP
+    pub fn key_material_extension(&self) -> Option<&KeyMaterialExtension> {
+        self.extensions.iter().find_map(|ext| match ext {
+            Extension::KeyMaterial(ext) => Some(ext),
+            _ => None,
+        })
+    }
+
+    pub fn stream_id_extension(&self) -> Option<&StreamIdExtension> {
+        self.extensions.iter().find_map(|ext| match ext {
+            Extension::StreamId(ext) => Some(ext),
+            _ => None,
+        })
+    }
+
+    pub fn congestion_extension(&self) -> Option<&CongestionExtension> {
+        self.extensions.iter().find_map(|ext| match ext {
+            Extension::Congestion(ext) => Some(ext),
+            _ => None,
+        })
+    }
+
+    pub fn packet_filter_extension(&self) -> Option<&PacketFilterExtension> {
+        self.extensions.iter().find_map(|ext| match ext {
+            Extension::PacketFilter(ext) => Some(ext),
+            _ => None,
+        })
+    }
+
+    pub fn group_membership_extension(&self) -> Option<&GroupMembershipExtension> {
+        self.extensions.iter().find_map(|ext| match ext {
+            Extension::GroupMembership(ext) => Some(ext),
+            _ => None,
         })
     }
 
@@ -134,21 +165,156 @@ impl Handshake {
         res.extend(self.srt_socket_id.to_be_bytes());
         res.extend(self.syn_cookie.to_be_bytes());
 
-        res.extend(self.peer_ip_address.0.to_be_bytes());
-        res.extend(self.peer_ip_address.1.to_be_bytes());
-        res.extend(self.peer_ip_address.2.to_be_bytes());
-        res.extend(self.peer_ip_address.3.to_be_bytes());
+        res.extend(ip_to_raw(self.peer_ip_address));
 
-        if let Some(ext) = &self.handshake_extension {
-            res.extend(ext.to_raw());
-        }
-        if let Some(ext) = &self.key_material_extension {
+        for ext in &self.extensions {
             res.extend(ext.to_raw());
         }
-        // if let Some(ext) = &self.stream_id_extension {
-        //     res.extend(ext.to_raw());
-        // }
 
         res
     }
 }
+
+/// `Peer IP Address` (16 bytes)
+///
+/// libsrt copies the address into four 32-bit words in host (little-endian) order,
+/// so each word's bytes are reversed on the wire. IPv4 takes only the first word,
+/// the rest are zero.
+fn ip_from_raw(raw: &[u8]) -> IpAddr {
+    let mut octets = [0; 16];
+    for (word, raw) in octets.chunks_exact_mut(4).zip(raw.chunks_exact(4)) {
+        word.copy_from_slice(raw);
+        word.reverse();
+    }
+
+    match octets[4..].iter().all(|&x| x == 0) {
+        true => Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]).into(),
+        false => Ipv6Addr::from(octets).into(),
+    }
+}
+
+fn ip_to_raw(ip: IpAddr) -> [u8; 16] {
+    let mut raw = [0; 16];
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => raw[..4].copy_from_slice(&ip.octets()),
+        IpAddr::V6(ip) => raw = ip.octets(),
+    }
+
+    for word in raw.chunks_exact_mut(4) {
+        word.reverse();
+    }
+
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::control::handshake::extension::{
+        extension_types, key_material::KeyBasedEncryption,
+    };
+
+    #[test]
+    fn test_stream_id_non_ascii() {
+        // Multi-byte characters across block boundaries
+        let ext = StreamIdExtension::new("#!::r=caméra/数据,u=jürgen".to_owned());
+        let parsed = StreamIdExtension::from_raw(&ext.to_raw()).unwrap();
+        assert_eq!(parsed.stream_id, ext.stream_id);
+    }
+
+    #[test]
+    fn test_string_length() {
+        // Length follows the content, also after it changed
+        let mut ext = StreamIdExtension::new("live".to_owned());
+        ext.stream_id = "live/stream".to_owned();
+        let raw = Extension::StreamId(ext).to_raw();
+        assert_eq!(raw.len(), 4 + 12);
+        assert_eq!(raw[2..4], [0, 3]);
+
+        let (parsed, size) = Extension::from_raw(&raw).unwrap();
+        assert_eq!(size, raw.len());
+        assert_eq!(parsed.to_raw(), raw);
+    }
+
+    #[test]
+    fn test_extensions_roundtrip() {
+        let handshake = Handshake {
+            version: 5,
+            encryption: HandshakeEncryption::AES128,
+            extension_field: 0x07,
+            initial_packet_sequence_number: 100,
+            maximum_transmission_unit_size: 1500,
+            maximum_flow_window_size: 8192,
+            handshake_type: HandshakeType::Conclusion,
+            srt_socket_id: 7,
+            syn_cookie: 42,
+            peer_ip_address: Ipv4Addr::LOCALHOST.into(),
+            extensions: vec![
+                Extension::Handshake(HandshakeExtension {
+                    r#type: extension_types::HSREQ,
+                    srt_version: 0x01_05_00,
+                    srt_flags: 0xBF,
+                    receiver_delay: 120,
+                    sender_delay: 120,
+                }),
+                Extension::KeyMaterial(KeyMaterialExtension {
+                    r#type: extension_types::KMREQ,
+                    version: 1,
+                    packet_type: 2,
+                    sign: 0x2029,
+                    key_based_encryption: KeyBasedEncryption::EvenKey,
+                    keki: 0,
+                    cipher: 2,
+                    auth: 0,
+                    stream_encapsulation: 2,
+                    salt: vec![1; 16],
+                    wrapped_key: vec![2; 24],
+                }),
+                // Used to be read at the wrong offset after a KM block
+                Extension::StreamId(StreamIdExtension::new("live/stream".to_owned())),
+                Extension::Unknown {
+                    r#type: 0x7F,
+                    data: b"evil".to_vec(),
+                },
+                Extension::GroupMembership(GroupMembershipExtension {
+                    group_id: 0x4000_0001,
+                    r#type: 1,
+                    flags: 0,
+                    weight: 0,
+                }),
+            ],
+        };
+
+        let raw = handshake.raw_content();
+        let parsed = Handshake::from_raw_cif(&raw).unwrap();
+
+        assert_eq!(
+            parsed.stream_id_extension().unwrap().stream_id,
+            "live/stream"
+        );
+        assert_eq!(parsed.key_material_extension().unwrap().key_length(), 16);
+        assert!(matches!(
+            &parsed.extensions[3],
+            Extension::Unknown { r#type: 0x7F, data } if data == b"evil"
+        ));
+        assert_eq!(
+            parsed.group_membership_extension().unwrap().group_id,
+            0x4000_0001
+        );
+        assert_eq!(parsed.raw_content(), raw);
+    }
+
+    #[test]
+    fn test_peer_ip_address() {
+        let localhost = [0x01, 0x00, 0x00, 0x7F, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(ip_from_raw(&localhost), Ipv4Addr::LOCALHOST);
+        assert_eq!(ip_to_raw(Ipv4Addr::LOCALHOST.into()), localhost);
+        // Dual-stack sockets see IPv4 peers as mapped IPv6
+        assert_eq!(ip_to_raw("::ffff:127.0.0.1".parse().unwrap()), localhost);
+
+        let ip: IpAddr = "2001:db8::8a2e:370:7334".parse().unwrap();
+        let raw = ip_to_raw(ip);
+        assert_eq!(raw[..4], [0xB8, 0x0D, 0x01, 0x20]);
+        assert_eq!(ip_from_raw(&raw), ip);
+    }
+}
@@ -1,10 +1,173 @@
+pub mod congestion;
 pub mod group_membership;
 pub mod handshake;
 pub mod key_material;
+pub mod packet_filter;
 pub mod stream_id;
 
+use crate::{
+    error::{Result, bytes_at, u16_at},
+    packet::control::handshake::extension::{
+        congestion::CongestionExtension, group_membership::GroupMembershipExtension,
+        handshake::HandshakeExtension, key_material::KeyMaterialExtension,
+        packet_filter::PacketFilterExtension, stream_id::StreamIdExtension,
+    },
+};
+
 pub mod extension_flags {
     pub const HSREQ: u16 = 0x00_01;
     pub const KMREQ: u16 = 0x00_02;
     pub const CONFIG: u16 = 0x00_04;
 }
+
+/// Type of an extension block (`SRT_CMD_*`)
+pub mod extension_types {
+    pub const HSREQ: u16 = 1;
+    pub const HSRSP: u16 = 2;
+    pub const KMREQ: u16 = 3;
+    pub const KMRSP: u16 = 4;
+    pub const SID: u16 = 5;
+    pub const CONGESTION: u16 = 6;
+    pub const FILTER: u16 = 7;
+    pub const GROUP: u16 = 8;
+}
+
+/// Extension block (type, length in 4-byte blocks, content)
+///
+/// <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1.1>
+#[derive(Clone, Debug)]
+pub enum Extension {
+    /// HSREQ or HSRSP
+    Handshake(HandshakeExtension),
+    /// KMREQ or KMRSP
+    KeyMaterial(KeyMaterialExtension),
+    StreamId(StreamIdExtension),
+    Congestion(CongestionExtension),
+    PacketFilter(PacketFilterExtension),
+    GroupMembership(GroupMembershipExtension),
+    /// Kept as is
+    Unknown {
+        r#type: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl Extension {
+    /// Parse the block at the start of `raw`
+    ///
+    /// Returns the extension and the # of bytes it takes.
+    pub fn from_raw(raw: &[u8]) -> Result<(Self, usize)> {
+        let r#type = u16_at(raw, 0)?;
+        let length = u16_at(raw, 2)?;
+
+        let size = 4 + usize::from(length) * 4;
+        let raw = bytes_at(raw, 0, size)?;
+
+        let ext = match r#type {
+            extension_types::HSREQ | extension_types::HSRSP => {
+                Self::Handshake(HandshakeExtension::from_raw(raw)?)
+            }
+            extension_types::KMREQ | extension_types::KMRSP => {
+                Self::KeyMaterial(KeyMaterialExtension::from_raw(raw)?)
+            }
+            extension_types::SID => Self::StreamId(StreamIdExtension::from_raw(raw)?),
+            extension_types::CONGESTION => Self::Congestion(CongestionExtension::from_raw(raw)?),
+            extension_types::FILTER => Self::PacketFilter(PacketFilterExtension::from_raw(raw)?),
+            extension_types::GROUP => {
+                Self::GroupMembership(GroupMembershipExtension::from_raw(raw)?)
+            }
+            _ => Self::Unknown {
+                r#type,
+                data: Vec::from(&raw[4..]),
+            },
+        };
+
+        Ok((ext, size))
+    }
+
+    /// Parse all blocks of the extension area
+    pub fn from_raw_all(raw: &[u8]) -> Result<Vec<Self>> {
+        let mut res = Vec::new();
+        let mut offset = 0;
+
+        while offset < raw.len() {
+            let (ext, size) = Self::from_raw(&raw[offset..]).map_err(|e| e.offset_by(offset))?;
+            res.push(ext);
+            offset += size;
+        }
+
+        Ok(res)
+    }
+
+    pub fn r#type(&self) -> u16 {
+        match self {
+            Self::Handshake(ext) => ext.r#type,
+            Self::KeyMaterial(ext) => ext.r#type,
+            Self::StreamId(ext) => ext.r#type,
+            Self::Congestion(ext) => ext.r#type,
+            Self::PacketFilter(ext) => ext.r#type,
+            Self::GroupMembership(_) => extension_types::GROUP,
+            Self::Unknown { r#type, .. } => *r#type,
+        }
+    }
+
+    pub fn to_raw(&self) -> Vec<u8> {
+        match self {
+            Self::Handshake(ext) => ext.to_raw(),
+            Self::KeyMaterial(ext) => ext.to_raw(),
+            Self::StreamId(ext) => ext.to_raw(),
+            Self::Congestion(ext) => ext.to_raw(),
+            Self::PacketFilter(ext) => ext.to_raw(),
+            Self::GroupMembership(ext) => ext.to_raw(),
+            Self::Unknown { r#type, data } => {
+                #[allow(clippy::cast_possible_truncation)]
+                let length = data.len().div_ceil(4) as u16;
+
+                let mut res = Vec::new();
+                res.extend(r#type.to_be_bytes());
+                res.extend(length.to_be_bytes());
+                res.extend(data);
+                // Padding of the last block
+                res.resize(4 + usize::from(length) * 4, 0);
+                res
+            }
+        }
+    }
+}
+
+/// Decode content of a string extension (4-byte blocks with reversed byte order)
+///
+/// Multi-byte characters may span blocks, so they're joined before decoding.
+pub(crate) fn string_from_raw(raw: &[u8], length: u16) -> Result<String> {
+    let mut bytes = Vec::from(bytes_at(raw, 4, usize::from(length) * 4)?);
+    for block in bytes.chunks_mut(4) {
+        block.reverse();
+    }
+
+    // Padding of the last block
+    while bytes.last() == Some(&0) {
+        bytes.pop();
+    }
+
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Length of an encoded string extension (in 4-byte blocks)
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn string_length(s: &str) -> u16 {
+    s.len().div_ceil(4) as u16
+}
+
+/// Encode content of a string extension (see [`string_from_raw`])
+pub(crate) fn string_to_raw(s: &str) -> Vec<u8> {
+    let mut res = Vec::new();
+
+    for chunk in s.as_bytes().chunks(4) {
+        let mut block = [0; 4];
+        block[..chunk.len()].copy_from_slice(chunk);
+        block.reverse();
+        res.extend(block);
+    }
+
+    res
+}
@@ -0,0 +1,43 @@
+//! `SRT_CMD_CONGESTION`
+
+use crate::{
+    error::{Result, u16_at},
+    packet::control::handshake::extension::{
+        extension_types, string_from_raw, string_length, string_to_raw,
+    },
+};
+
+/// Congestion control type, e.g. `live` or `file`
+#[derive(Clone, Debug)]
+pub struct CongestionExtension {
+    pub r#type: u16,
+    pub congestion: String,
+}
+
+impl CongestionExtension {
+    pub fn new(congestion: String) -> Self {
+        Self {
+            r#type: extension_types::CONGESTION,
+            congestion,
+        }
+    }
+
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = u16_at(raw, 0)?;
+        let length = u16_at(raw, 2)?;
+
+        let congestion = string_from_raw(raw, length)?;
+
+        Ok(Self { r#type, congestion })
+    }
+
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+
+        res.extend(self.r#type.to_be_bytes());
+        res.extend(string_length(&self.congestion).to_be_bytes());
+        res.extend(string_to_raw(&self.congestion));
+
+        res
+    }
+}
@@ -1,13 +1,19 @@
 //! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.1.4>
 
 pub mod group_type {
-    pub const UNDEFINED: u32 = 0;
-    pub const BROADCAST: u32 = 1;
-    pub const MAIN_BACKUP: u32 = 2;
-    pub const BALANCING: u32 = 3;
-    pub const MULTICAST: u32 = 4;
+    pub const UNDEFINED: u8 = 0;
+    pub const BROADCAST: u8 = 1;
+    pub const MAIN_BACKUP: u8 = 2;
+    pub const BALANCING: u8 = 3;
+    pub const MULTICAST: u8 = 4;
 }
 
+use crate::{
+    error::{Result, u8_at, u16_at, u32_at},
+    packet::control::handshake::extension::extension_types,
+};
+
+#[derive(Clone, Debug)]
 pub struct GroupMembershipExtension {
     pub group_id: u32,
     pub r#type: u8,
@@ -16,11 +22,11 @@ pub struct GroupMembershipExtension {
 }
 
 impl GroupMembershipExtension {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let group_id = u32::from_be_bytes(raw[4..8].try_into()?);
-        let r#type = raw[8];
-        let flags = raw[9];
-        let weight = u16::from_be_bytes(raw[10..12].try_into()?);
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let group_id = u32_at(raw, 4)?;
+        let r#type = u8_at(raw, 8)?;
+        let flags = u8_at(raw, 9)?;
+        let weight = u16_at(raw, 10)?;
 
         Ok(Self {
             group_id,
@@ -29,4 +35,17 @@ impl GroupMembershipExtension {
             weight,
         })
     }
+
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+
+        res.extend(extension_types::GROUP.to_be_bytes());
+        res.extend(2u16.to_be_bytes()); // Length (in 4-byte blocks)
+        res.extend(self.group_id.to_be_bytes());
+        res.push(self.r#type);
+        res.push(self.flags);
+        res.extend(self.weight.to_be_bytes());
+
+        res
+    }
 }
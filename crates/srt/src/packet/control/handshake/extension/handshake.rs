@@ -1,4 +1,4 @@
-use crate::macros::simple_raw;
+use crate::error::{Result, u16_at, u32_at};
 
 pub mod handshake_extension_message_flags {
     pub const TSBPDSND: u32 = 0x00_00_00_01;
@@ -11,36 +11,44 @@ pub mod handshake_extension_message_flags {
     pub const PACKET_FILTER: u32 = 0x00_00_00_80;
 }
 
-simple_raw! {
-    #[derive(Clone, Debug)]
-    pub struct HandshakeExtension {
-        pub r#type: u16,
-        pub length: u16,
-        pub srt_version: u32,
-        /// Refer to [`handshake_extension_message_flags`]
-        pub srt_flags: u32,
-        pub receiver_delay: u16,
-        pub sender_delay: u16,
-    }
+#[derive(Clone, Debug)]
+pub struct HandshakeExtension {
+    pub r#type: u16,
+    pub srt_version: u32,
+    /// Refer to [`handshake_extension_message_flags`]
+    pub srt_flags: u32,
+    pub receiver_delay: u16,
+    pub sender_delay: u16,
 }
 
 impl HandshakeExtension {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let r#type = u16::from_be_bytes(raw[0..2].try_into()?);
-        let length = u16::from_be_bytes(raw[2..4].try_into()?);
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = u16_at(raw, 0)?;
 
-        let srt_version = u32::from_be_bytes(raw[4..8].try_into()?);
-        let srt_flags = u32::from_be_bytes(raw[8..12].try_into()?);
-        let receiver_delay = u16::from_be_bytes(raw[12..14].try_into()?);
-        let sender_delay = u16::from_be_bytes(raw[14..16].try_into()?);
+        let srt_version = u32_at(raw, 4)?;
+        let srt_flags = u32_at(raw, 8)?;
+        let receiver_delay = u16_at(raw, 12)?;
+        let sender_delay = u16_at(raw, 14)?;
 
         Ok(Self {
             r#type,
-            length,
             srt_version,
             srt_flags,
             receiver_delay,
             sender_delay,
         })
     }
+
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+
+        res.extend(self.r#type.to_be_bytes());
+        res.extend(3u16.to_be_bytes()); // Length (in 4-byte blocks)
+        res.extend(self.srt_version.to_be_bytes());
+        res.extend(self.srt_flags.to_be_bytes());
+        res.extend(self.receiver_delay.to_be_bytes());
+        res.extend(self.sender_delay.to_be_bytes());
+
+        res
+    }
 }
@@ -1,6 +1,8 @@
-use anyhow::bail;
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-3.2.2>
 
-#[derive(Clone, Debug)]
+use crate::error::{Error, Result, bytes_at, u8_at, u16_at, u32_at};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum KeyBasedEncryption {
     // None,
     EvenKey,
@@ -11,39 +13,116 @@ pub enum KeyBasedEncryption {
 #[derive(Clone, Debug)]
 pub struct KeyMaterialExtension {
     pub r#type: u16,
-    pub length: u16,
+    pub version: u8,
     pub packet_type: u8,
+    /// = 0x2029
+    pub sign: u16,
     pub key_based_encryption: KeyBasedEncryption,
+    /// = 0
+    pub keki: u32,
+    pub cipher: u8,
+    /// = 0
+    pub auth: u8,
+    pub stream_encapsulation: u8,
+    pub salt: Vec<u8>,
+    pub wrapped_key: Vec<u8>,
 }
 
 impl KeyMaterialExtension {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let r#type = u16::from_be_bytes(raw[0..2].try_into()?);
-        let length = u16::from_be_bytes(raw[2..4].try_into()?);
-
-        let packet_type = raw[4] & 0b0000_1111;
-        // let sign = u16::from_be_bytes(raw[5..7].try_into()?); // = 0x2029
-        let key_based_encryption = match raw[7] & 0x0000_0011 {
-            0b00 => bail!("Invalid extension format"),
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = u16_at(raw, 0)?;
+        let length = u16_at(raw, 2)?;
+
+        let first = u8_at(raw, 4)?;
+        let version = (first >> 4) & 0b0111;
+        let packet_type = first & 0b0000_1111;
+        let sign = u16_at(raw, 5)?;
+        let key_based_encryption = match u8_at(raw, 7)? & 0b0000_0011 {
             0b01 => KeyBasedEncryption::EvenKey,
             0b10 => KeyBasedEncryption::OddKey,
             0b11 => KeyBasedEncryption::Both,
-            _ => unreachable!(),
+            value => {
+                return Err(Error::BadValue {
+                    offset: 7,
+                    field: "KK",
+                    value: value.into(),
+                });
+            }
         };
-        // let keki = u32::from_be_bytes(raw[8..12].try_into()?); // = 0
-        let _cipher = u8::from_be_bytes(raw[12..13].try_into()?);
-        // let auth = u8::from_be_bytes(raw[13..14].try_into()?); // = 0
-        let _stream_encapsulation = u8::from_be_bytes(raw[14..15].try_into()?);
+        let keki = u32_at(raw, 8)?;
+        let cipher = u8_at(raw, 12)?;
+        let auth = u8_at(raw, 13)?;
+        let stream_encapsulation = u8_at(raw, 14)?;
+        // Resv2 (8), Resv3 (16)
+        let salt_length = usize::from(u8_at(raw, 18)?) * 4;
+
+        let salt_end = 20 + salt_length;
+        let end = 4 + usize::from(length) * 4;
+        if salt_end > end {
+            return Err(Error::BadValue {
+                offset: 18,
+                field: "SLen",
+                value: salt_length as u64,
+            });
+        }
+
+        let salt = Vec::from(bytes_at(raw, 20, salt_length)?);
+        let wrapped_key = Vec::from(bytes_at(raw, salt_end, end - salt_end)?);
 
         Ok(Self {
             r#type,
-            length,
+            version,
             packet_type,
+            sign,
             key_based_encryption,
+            keki,
+            cipher,
+            auth,
+            stream_encapsulation,
+            salt,
+            wrapped_key,
         })
     }
 
+    /// Length of a single key (bytes)
+    pub fn key_length(&self) -> usize {
+        let keys = match self.key_based_encryption {
+            KeyBasedEncryption::EvenKey | KeyBasedEncryption::OddKey => 1,
+            KeyBasedEncryption::Both => 2,
+        };
+
+        // Wrapped keys are prefixed with an 8-byte integrity check value
+        self.wrapped_key.len().saturating_sub(8) / keys
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
     pub fn to_raw(&self) -> Vec<u8> {
-        todo!()
+        let mut res = Vec::new();
+
+        res.extend(self.r#type.to_be_bytes());
+        res.extend(0u16.to_be_bytes()); // Length, once known
+
+        res.push((self.version << 4) | self.packet_type);
+        res.extend(self.sign.to_be_bytes());
+        res.push(match self.key_based_encryption {
+            KeyBasedEncryption::EvenKey => 0b01,
+            KeyBasedEncryption::OddKey => 0b10,
+            KeyBasedEncryption::Both => 0b11,
+        });
+        res.extend(self.keki.to_be_bytes());
+        res.push(self.cipher);
+        res.push(self.auth);
+        res.push(self.stream_encapsulation);
+        res.push(0); // Resv2
+        res.extend(0u16.to_be_bytes()); // Resv3
+        res.push((self.salt.len() / 4) as u8);
+        res.push((self.key_length() / 4) as u8);
+        res.extend(&self.salt);
+        res.extend(&self.wrapped_key);
+
+        let length = ((res.len() - 4) / 4) as u16;
+        res[2..4].copy_from_slice(&length.to_be_bytes());
+
+        res
     }
 }
@@ -0,0 +1,47 @@
+//! `SRT_CMD_FILTER`
+//!
+//! <https://github.com/Haivision/srt/blob/master/docs/features/packet-filtering-and-fec.md>
+
+use crate::{
+    error::{Result, u16_at},
+    packet::control::handshake::extension::{
+        extension_types, string_from_raw, string_length, string_to_raw,
+    },
+};
+
+/// Packet filter configuration string, e.g. `fec,cols:10,rows:5,arq:onreq`
+#[derive(Clone, Debug)]
+pub struct PacketFilterExtension {
+    pub r#type: u16,
+    pub config: String,
+}
+
+impl PacketFilterExtension {
+    pub fn new(config: String) -> Self {
+        Self {
+            r#type: extension_types::FILTER,
+            config,
+        }
+    }
+
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = u16_at(raw, 0)?;
+        let length = u16_at(raw, 2)?;
+
+        // Same encoding as Stream ID: 4-byte blocks with reversed byte order
+        let config = string_from_raw(raw, length)?;
+
+        Ok(Self { r#type, config })
+    }
+
+    pub fn to_raw(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+
+        res.extend(self.r#type.to_be_bytes());
+        res.extend(string_length(&self.config).to_be_bytes());
+
+        res.extend(string_to_raw(&self.config));
+
+        res
+    }
+}
@@ -1,32 +1,41 @@
+use crate::{
+    error::{Result, u16_at},
+    packet::control::handshake::extension::{
+        extension_types, string_from_raw, string_length, string_to_raw,
+    },
+};
+
 #[derive(Clone, Debug)]
 pub struct StreamIdExtension {
     pub r#type: u16,
-    pub length: u16,
     pub stream_id: String,
 }
 
 impl StreamIdExtension {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let r#type = u16::from_be_bytes(raw[0..2].try_into()?);
-        let length = u16::from_be_bytes(raw[2..4].try_into()?);
+    pub fn new(stream_id: String) -> Self {
+        Self {
+            r#type: extension_types::SID,
+            stream_id,
+        }
+    }
 
-        let mut stream_id = String::new();
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let r#type = u16_at(raw, 0)?;
+        let length = u16_at(raw, 2)?;
 
-        for i in 0..length {
-            let pos = 4 + i as usize * 4;
-            let mut bytes = Vec::from(&raw[pos..(pos + 4)]);
-            bytes.reverse();
-            stream_id += String::from_utf8_lossy(&bytes).trim_matches(char::from(0));
-        }
+        let stream_id = string_from_raw(raw, length)?;
 
-        Ok(Self {
-            r#type,
-            length,
-            stream_id,
-        })
+        Ok(Self { r#type, stream_id })
     }
 
     pub fn to_raw(&self) -> Vec<u8> {
-        todo!()
+        let mut res = Vec::new();
+
+        res.extend(self.r#type.to_be_bytes());
+        res.extend(string_length(&self.stream_id).to_be_bytes());
+
+        res.extend(string_to_raw(&self.stream_id));
+
+        res
     }
 }
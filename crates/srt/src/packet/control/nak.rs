@@ -1,31 +1,59 @@
-use anyhow::Result;
+use crate::{
+    error::{Result, u32_at},
+    seq::SeqNo,
+};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Nak {
     Single {
-        lost_packet: u32,
+        lost_packet: SeqNo,
     },
     Range {
-        lost_packets_from: u32,
-        lost_packets_to: u32,
+        lost_packets_from: SeqNo,
+        lost_packets_to: SeqNo,
     },
 }
 
 impl Nak {
-    pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let is_range = raw[16] >> 7 == 1;
+    /// Parse the entry at `offset`
+    ///
+    /// Returns the entry and the # of bytes it takes.
+    pub fn from_raw_at(raw: &[u8], offset: usize) -> Result<(Self, usize)> {
+        let first = u32_at(raw, offset)?;
+        let is_range = first >> 31 == 1;
 
         if is_range {
-            let lost_packets_from = u32::from_be_bytes(raw[16..20].try_into()?) & !(1 << 31);
-            let lost_packets_to = u32::from_be_bytes(raw[20..24].try_into()?);
-            Ok(Self::Range {
-                lost_packets_from,
-                lost_packets_to,
-            })
+            let lost_packets_from = SeqNo::new(first);
+            let lost_packets_to = SeqNo::new(u32_at(raw, offset + 4)?);
+            Ok((
+                Self::Range {
+                    lost_packets_from,
+                    lost_packets_to,
+                },
+                8,
+            ))
         } else {
-            let lost_packet = u32::from_be_bytes(raw[16..20].try_into()?);
-            Ok(Self::Single { lost_packet })
+            Ok((
+                Self::Single {
+                    lost_packet: SeqNo::new(first),
+                },
+                4,
+            ))
+        }
+    }
+
+    /// Parse the loss list (CIF of the packet)
+    pub fn from_raw_all(raw: &[u8]) -> Result<Vec<Self>> {
+        let mut res = Vec::new();
+        let mut offset = 16;
+
+        while offset < raw.len() {
+            let (nak, size) = Self::from_raw_at(raw, offset)?;
+            res.push(nak);
+            offset += size;
         }
+
+        Ok(res)
     }
 
     pub fn raw_content(&self) -> Vec<u8> {
@@ -33,14 +61,14 @@ impl Nak {
 
         match self {
             Self::Single { lost_packet } => {
-                res.extend(lost_packet.to_be_bytes());
+                res.extend(lost_packet.get().to_be_bytes());
             }
             Self::Range {
                 lost_packets_from,
                 lost_packets_to,
             } => {
-                res.extend((lost_packets_from | (1 << 31)).to_be_bytes());
-                res.extend(lost_packets_to.to_be_bytes());
+                res.extend((lost_packets_from.get() | (1 << 31)).to_be_bytes());
+                res.extend(lost_packets_to.get().to_be_bytes());
             }
         }
 
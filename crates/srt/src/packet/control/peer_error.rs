@@ -1,6 +1,13 @@
-use anyhow::Result;
+use crate::{
+    error::{Result, u32_at},
+    packet::control::{EXTRA_PAD, control_types},
+};
 
-use crate::packet::control::control_types;
+/// Codes sent by libsrt
+pub mod error_codes {
+    /// Received data couldn't be written (e.g. `srt_recvfile`)
+    pub const WRITE: u32 = 4000;
+}
 
 #[derive(Clone, Debug)]
 pub struct PeerError {
@@ -9,11 +16,16 @@ pub struct PeerError {
 
 impl PeerError {
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let error_code = u32::from_be_bytes(raw[4..8].try_into()?);
+        let error_code = u32_at(raw, 4)?;
 
         Ok(Self { error_code })
     }
 
+    /// Whole datagram (see [`crate::serial`])
+    pub fn to_raw(&self) -> Vec<u8> {
+        [self.raw_header(), vec![0; 8], EXTRA_PAD.to_vec()].concat()
+    }
+
     pub fn raw_header(&self) -> Vec<u8> {
         let mut res = Vec::new();
 
@@ -1,4 +1,9 @@
-#[derive(Debug)]
+use crate::{
+    error::{Error, Result, rest_at, u8_at, u32_at},
+    seq::{MsgNo, SeqNo},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PacketPosition {
     Middle,
     First,
@@ -6,56 +11,53 @@ pub enum PacketPosition {
     Single,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum EncryptionFlag {
     NoEncryption,
     EvenKey,
     OddKey,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DataPacketInfo {
-    pub packet_sequence_number: u32,
+    pub packet_sequence_number: SeqNo,
     pub position: PacketPosition,
     pub order: bool,
     pub encryption: EncryptionFlag,
     pub retransmitted: bool,
-    pub message_number: u32,
+    pub message_number: MsgNo,
     pub content: Vec<u8>,
 }
 
 impl DataPacketInfo {
-    pub fn from_raw(raw: &[u8]) -> anyhow::Result<Self> {
-        let packet_sequence_number = u32::from_be_bytes(raw[0..4].try_into()?) & !(1 << 31);
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let packet_sequence_number = SeqNo::new(u32_at(raw, 0)?);
 
-        let fb = raw[4];
+        let fb = u8_at(raw, 4)?;
         let position = match (fb & 0b1100_0000) >> 6 {
             0b00 => PacketPosition::Middle,
             0b01 => PacketPosition::Last,
             0b10 => PacketPosition::First,
-            0b11 => PacketPosition::Single,
-            _ => unreachable!(),
-        };
-        let order = match (fb & 0b0010_0000) >> 5 {
-            0 => false,
-            1 => true,
-            _ => unreachable!(),
+            _ => PacketPosition::Single,
         };
+        let order = fb & 0b0010_0000 != 0;
         let encryption = match (fb & 0b0001_1000) >> 3 {
             0b00 => EncryptionFlag::NoEncryption,
             0b01 => EncryptionFlag::EvenKey,
             0b10 => EncryptionFlag::OddKey,
-            _ => unreachable!(),
-        };
-        let retransmitted = match (fb & 0b0000_0100) >> 2 {
-            0 => false,
-            1 => true,
-            _ => unreachable!(),
+            kk => {
+                return Err(Error::BadValue {
+                    offset: 4,
+                    field: "encryption flag",
+                    value: kk.into(),
+                });
+            }
         };
+        let retransmitted = fb & 0b0000_0100 != 0;
 
-        let message_number = u32::from_be_bytes(raw[4..8].try_into()?) & !(0b11_11_11 << 26);
+        let message_number = MsgNo::new(u32_at(raw, 4)?);
 
-        let content = Vec::from(&raw[16..]);
+        let content = Vec::from(rest_at(raw, 16)?);
 
         Ok(Self {
             packet_sequence_number,
@@ -68,11 +70,38 @@ impl DataPacketInfo {
         })
     }
 
+    /// Whole datagram (see [`crate::serial`])
+    pub fn to_raw(&self) -> Vec<u8> {
+        [self.raw_header(), vec![0; 8], self.raw_content()].concat()
+    }
+
+    /// `Packet Sequence Number`, `PP`, `O`, `KK`, `R` and `Message Number`
     pub fn raw_header(&self) -> Vec<u8> {
-        todo!()
+        let mut res = Vec::new();
+
+        res.extend(self.packet_sequence_number.get().to_be_bytes());
+
+        let position: u32 = match self.position {
+            PacketPosition::Middle => 0b00,
+            PacketPosition::Last => 0b01,
+            PacketPosition::First => 0b10,
+            PacketPosition::Single => 0b11,
+        };
+        let encryption: u32 = match self.encryption {
+            EncryptionFlag::NoEncryption => 0b00,
+            EncryptionFlag::EvenKey => 0b01,
+            EncryptionFlag::OddKey => 0b10,
+        };
+        let flags = position << 30
+            | u32::from(self.order) << 29
+            | encryption << 27
+            | u32::from(self.retransmitted) << 26;
+        res.extend((flags | self.message_number.get()).to_be_bytes());
+
+        res
     }
 
     pub fn raw_content(&self) -> Vec<u8> {
-        todo!()
+        self.content.clone()
     }
 }
@@ -0,0 +1,589 @@
+//! Forwards a stream to downstream SRT listeners
+//!
+//! Every [`Output`] is a caller with its own options (latency, passphrase) and thread.
+//! Messages go out in order, while an output is connected, and are dropped for it otherwise.
+//! An output, that can't connect or whose listener goes away, tries again after a delay,
+//! that doubles on every failure (see [`Backoff`]).
+//!
+//! Feed it from a listener:
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use srt::{relay::{Output, Relay}, server::Server};
+//!
+//! let relay = Relay::new(vec![
+//!     Output::new("198.51.100.1:9000".parse()?),
+//!     Output::new("198.51.100.2:9000".parse()?),
+//! ])?;
+//!
+//! let mut server = Server::new("0.0.0.0:9000")?;
+//! server.on_data(move |_, message| relay.send(message));
+//! server.run()
+//! # }
+//! ```
+
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    connection::{Connection, caller::Caller},
+    constants::{FULL_ACK_INTERVAL, MAX_PACKET_SIZE},
+    options::Options,
+    packet::{Packet, PacketContent, control::ControlPacketInfo},
+    socket,
+    transport::Transport,
+};
+
+/// Messages and packets waiting for each output (more are dropped)
+const OUTPUT_QUEUE_SIZE: usize = 8192;
+
+/// How often the receiving thread of an output checks, if it should stop
+const STOP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Delay before connecting again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    /// After the first failure
+    pub initial: Duration,
+    /// Doubling stops here
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Downstream listener
+#[derive(Clone, Debug)]
+pub struct Output {
+    pub addr: SocketAddr,
+    pub stream_id: Option<String>,
+    /// `latency` is asked of the listener, `passphrase` encrypts this output only
+    pub options: Options,
+    pub backoff: Backoff,
+}
+
+impl Output {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            stream_id: None,
+            options: Options::default(),
+            backoff: Backoff::default(),
+        }
+    }
+}
+
+/// State of an output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputStatus {
+    pub addr: SocketAddr,
+    pub connected: bool,
+    /// # of connections made so far
+    pub connections: u32,
+    /// Messages dropped while not connected (or while its queue was full)
+    pub dropped: u64,
+}
+
+enum Event {
+    Message(Arc<[u8]>),
+    Packet(Packet),
+    Stop,
+}
+
+struct Handle {
+    events: SyncSender<Event>,
+    status: Arc<Mutex<OutputStatus>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+/// Sends messages to every output, until dropped
+pub struct Relay {
+    outputs: Vec<Handle>,
+}
+
+impl Relay {
+    /// Each output gets a UDP socket on an ephemeral port
+    pub fn new(outputs: Vec<Output>) -> Result<Self> {
+        let outputs = outputs
+            .into_iter()
+            .map(|output| {
+                let local = match output.addr {
+                    SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                    SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+                };
+                Ok((output, socket::bind(local)?))
+            })
+            .collect::<Result<Vec<(Output, UdpSocket)>>>()?;
+
+        Self::with_transports(outputs)
+    }
+
+    /// Same as [`Relay::new`], over other transports
+    pub fn with_transports<T: Transport + 'static>(outputs: Vec<(Output, T)>) -> Result<Self> {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let mut handles = Vec::new();
+
+        for (output, transport) in outputs {
+            output.options.validate()?;
+            transport.set_read_timeout(Some(STOP_INTERVAL))?;
+
+            let transport = Arc::new(transport);
+            let stop = Arc::new(AtomicBool::new(false));
+            let (events, rx) = mpsc::sync_channel(OUTPUT_QUEUE_SIZE);
+            let status = Arc::new(Mutex::new(OutputStatus {
+                addr: output.addr,
+                connected: false,
+                connections: 0,
+                dropped: 0,
+            }));
+
+            let receiving = thread::spawn({
+                let (transport, events, stop) = (transport.clone(), events.clone(), stop.clone());
+                let addr = output.addr;
+                move || receive(&*transport, addr, &events, &stop)
+            });
+
+            let worker = Worker {
+                delay: output.backoff.initial,
+                state: State::Waiting(clock.now()),
+                output,
+                transport,
+                clock: clock.clone(),
+                status: status.clone(),
+            };
+            let sending = thread::spawn(move || {
+                worker.run(&rx);
+                stop.store(true, Ordering::Relaxed);
+            });
+
+            handles.push(Handle {
+                events,
+                status,
+                threads: vec![receiving, sending],
+            });
+        }
+
+        Ok(Self { outputs: handles })
+    }
+
+    /// Queue a message for every output
+    pub fn send(&self, message: &[u8]) {
+        let message: Arc<[u8]> = message.into();
+
+        for output in &self.outputs {
+            if let Err(TrySendError::Full(_)) =
+                output.events.try_send(Event::Message(message.clone()))
+            {
+                output.status.lock().unwrap().dropped += 1;
+            }
+        }
+    }
+
+    /// In the order of outputs
+    pub fn status(&self) -> Vec<OutputStatus> {
+        self.outputs
+            .iter()
+            .map(|output| *output.status.lock().unwrap())
+            .collect()
+    }
+}
+
+/// Shuts down connected outputs
+impl Drop for Relay {
+    fn drop(&mut self) {
+        for output in &self.outputs {
+            _ = output.events.send(Event::Stop);
+        }
+        for output in &mut self.outputs {
+            for thread in output.threads.drain(..) {
+                _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Pass packets from the listener to the worker
+fn receive(
+    transport: &impl Transport,
+    addr: SocketAddr,
+    events: &SyncSender<Event>,
+    stop: &AtomicBool,
+) {
+    let mut buf = [0; MAX_PACKET_SIZE];
+
+    while !stop.load(Ordering::Relaxed) {
+        let (n, from) = match transport.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => {
+                tracing::debug!("Relay to {addr}: {e}");
+                continue;
+            }
+        };
+        if from != addr {
+            continue;
+        }
+
+        match Packet::from_raw(&buf[..n]) {
+            Ok(pack) => {
+                if let Err(TrySendError::Disconnected(_)) = events.try_send(Event::Packet(pack)) {
+                    return;
+                }
+            }
+            Err(e) => tracing::debug!("Bad packet from {addr}: {e}"),
+        }
+    }
+}
+
+enum State {
+    /// Connect at this time
+    Waiting(Instant),
+    Connecting(Box<Caller>),
+    Connected(Box<Connection>),
+}
+
+/// Drives the connection of one output
+struct Worker<T> {
+    output: Output,
+    transport: Arc<T>,
+    clock: Arc<dyn Clock>,
+    status: Arc<Mutex<OutputStatus>>,
+    state: State,
+    /// Before the next attempt
+    delay: Duration,
+}
+
+impl<T: Transport> Worker<T> {
+    fn run(mut self, events: &Receiver<Event>) {
+        let timer_interval = Duration::from_micros(FULL_ACK_INTERVAL.into());
+        let mut last_timer = Instant::now();
+
+        loop {
+            let timeout = timer_interval.saturating_sub(last_timer.elapsed());
+
+            match events.recv_timeout(timeout) {
+                Ok(Event::Message(message)) => self.send(&message),
+                Ok(Event::Packet(pack)) => self.handle(&pack),
+                Ok(Event::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+
+            if last_timer.elapsed() >= timer_interval {
+                last_timer = Instant::now();
+                self.update();
+            }
+
+            self.flush();
+        }
+
+        if let State::Connected(conn) = &self.state {
+            _ = conn.send(PacketContent::Control(ControlPacketInfo::Shutdown));
+            self.flush();
+        }
+    }
+
+    fn send(&self, message: &[u8]) {
+        match &self.state {
+            State::Connected(conn) => {
+                if let Err(e) = conn.send_message(message) {
+                    tracing::warn!("Relay to {}: {e}", self.output.addr);
+                }
+            }
+            _ => self.status.lock().unwrap().dropped += 1,
+        }
+    }
+
+    fn handle(&mut self, pack: &Packet) {
+        let addr = self.output.addr;
+
+        match &mut self.state {
+            State::Waiting(_) => {}
+            State::Connecting(caller) => match caller.handle(pack) {
+                Ok(Some(conn)) => {
+                    tracing::info!("Relay connected to {addr}");
+                    self.delay = self.output.backoff.initial;
+                    self.state = State::Connected(Box::new(conn));
+
+                    let mut status = self.status.lock().unwrap();
+                    status.connected = true;
+                    status.connections += 1;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!("Relay to {addr} rejected: {e}");
+                    self.retry();
+                }
+            },
+            State::Connected(conn) => {
+                if let Err(e) = conn.handle(pack) {
+                    tracing::warn!("Relay to {addr}: {e}");
+                }
+            }
+        }
+    }
+
+    /// Run timers, (re)connect and notice lost connections
+    fn update(&mut self) {
+        let addr = self.output.addr;
+
+        match &self.state {
+            State::Waiting(at) if self.clock.now() >= *at => {
+                let caller = Caller::new(
+                    addr,
+                    self.output.stream_id.clone(),
+                    self.output.options.clone(),
+                    self.clock.clone(),
+                );
+                match caller {
+                    Ok(caller) => self.state = State::Connecting(Box::new(caller)),
+                    Err(e) => {
+                        tracing::error!("Relay to {addr}: {e}");
+                        self.retry();
+                    }
+                }
+            }
+            State::Waiting(_) => {}
+            State::Connecting(caller) => {
+                if caller.is_timed_out() {
+                    tracing::warn!("Relay to {addr}: no answer, retrying in {:?}", self.delay);
+                    self.retry();
+                }
+            }
+            State::Connected(conn) => {
+                if let Err(e) = conn.update() {
+                    tracing::warn!("Relay to {addr}: {e}");
+                }
+
+                let reason = match conn.disconnect_reason() {
+                    Some(reason) => Some(format!("{reason:?}")),
+                    None => conn.is_idle().then(|| "Timeout".to_owned()),
+                };
+                if let Some(reason) = reason {
+                    tracing::warn!(
+                        "Relay to {addr} lost ({reason}), retrying in {:?}",
+                        self.delay
+                    );
+                    self.status.lock().unwrap().connected = false;
+                    self.retry();
+                }
+            }
+        }
+    }
+
+    /// Wait for the current delay, and double it for next time
+    fn retry(&mut self) {
+        self.state = State::Waiting(self.clock.now() + self.delay);
+        self.delay = (self.delay * 2).min(self.output.backoff.max);
+    }
+
+    fn flush(&mut self) {
+        let addr = self.output.addr;
+        let send = |pack: Packet| {
+            if let Err(e) = self.transport.send_to(&pack.to_raw(), addr) {
+                tracing::debug!("Relay to {addr}: {e}");
+            }
+        };
+
+        match &mut self.state {
+            State::Waiting(_) => {}
+            State::Connecting(caller) => {
+                if let Some(pack) = caller.poll_transmit() {
+                    send(pack);
+                }
+            }
+            State::Connected(conn) => {
+                // Application-defined control messages of the output are of no use here
+                while conn.poll_extended().is_some() {}
+
+                while let Some(pack) = conn.poll_transmit() {
+                    send(pack);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        group::Groups,
+        options::ListenerOptions,
+        server::Server,
+        transport::{
+            MemoryNetwork, MemoryTransport,
+            impair::{Impaired, Impairment, Loss},
+        },
+    };
+
+    type Shared<T> = Arc<Mutex<Vec<T>>>;
+
+    fn wait_for(mut f: impl FnMut() -> bool) {
+        let start = Instant::now();
+        while !f() {
+            assert!(start.elapsed() < Duration::from_secs(10), "Timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Downstream listener, collecting messages and the latency of each connection
+    fn listen(
+        transport: impl Transport + 'static,
+        options: ListenerOptions,
+    ) -> (SocketAddr, Shared<Vec<u8>>, Shared<Duration>) {
+        let mut server = Server::with_transport(transport);
+        server.options(options).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let latencies = Arc::new(Mutex::new(Vec::new()));
+        server.on_data({
+            let received = received.clone();
+            move |_, message| received.lock().unwrap().push(message.to_vec())
+        });
+        server.on_connect({
+            let latencies = latencies.clone();
+            move |conn| latencies.lock().unwrap().push(conn.negotiated.latency)
+        });
+        // Runs until the test ends
+        thread::spawn(move || server.run());
+
+        (addr, received, latencies)
+    }
+
+    /// Answer one handshake, then go away (on drop)
+    fn accept(listener: &MemoryTransport) {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let mut buf = [0; MAX_PACKET_SIZE];
+
+        loop {
+            let (n, addr) = listener.recv_from(&mut buf).unwrap();
+            let pack = Packet::from_raw(&buf[..n]).unwrap();
+            let (response, conn) = Connection::accept(
+                &pack,
+                addr,
+                42,
+                &Groups::default(),
+                &ListenerOptions::default(),
+                &clock,
+            )
+            .unwrap();
+            listener.send_to(&response.to_raw(), addr).unwrap();
+
+            if conn.is_some() {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_relay() {
+        const COUNT: usize = 200;
+
+        let network = MemoryNetwork::default();
+        let passphrase = Some("0123456789".to_owned());
+        let (secure, secure_received, latencies) = listen(
+            network.bind_any().unwrap(),
+            ListenerOptions {
+                default: Options {
+                    passphrase: passphrase.clone(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let (lossy, lossy_received, _) =
+            listen(network.bind_any().unwrap(), ListenerOptions::default());
+
+        let mut first = Output::new(secure);
+        first.options.passphrase = passphrase;
+        first.options.latency = Duration::from_millis(400);
+        let second = Output::new(lossy);
+        let loss = Impairment {
+            loss: Loss::Random(0.05),
+            seed: 1,
+            ..Default::default()
+        };
+        let relay = Relay::with_transports(vec![
+            (
+                first,
+                Impaired::new(network.bind_any().unwrap(), Impairment::default()),
+            ),
+            (second, Impaired::new(network.bind_any().unwrap(), loss)),
+        ])
+        .unwrap();
+        wait_for(|| relay.status().iter().all(|status| status.connected));
+
+        // Live rate, until lost packets are sent again (the tail has nothing to reveal a gap)
+        let start = Instant::now();
+        let mut sent = 0u32;
+        let done = |received: &Shared<Vec<u8>>| received.lock().unwrap().len() >= COUNT;
+        while !done(&secure_received) || !done(&lossy_received) {
+            assert!(start.elapsed() < Duration::from_secs(10), "Stalled");
+            relay.send(&sent.to_be_bytes().repeat(100));
+            sent += 1;
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let expected: Vec<_> = (0..COUNT as u32)
+            .map(|k| k.to_be_bytes().repeat(100))
+            .collect();
+        assert_eq!(secure_received.lock().unwrap()[..COUNT], expected);
+        assert_eq!(lossy_received.lock().unwrap()[..COUNT], expected);
+        assert_eq!(*latencies.lock().unwrap(), vec![Duration::from_millis(400)]);
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let network = MemoryNetwork::default();
+        let addr = "10.0.0.9:9000".parse().unwrap();
+
+        let mut output = Output::new(addr);
+        output.options.connect_timeout = Duration::from_millis(200);
+        output.options.peer_idle_timeout = Duration::from_millis(300);
+        output.backoff = Backoff {
+            initial: Duration::from_millis(50),
+            max: Duration::from_millis(200),
+        };
+        let relay = Relay::with_transports(vec![(output, network.bind_any().unwrap())]).unwrap();
+
+        // Nobody there yet
+        thread::sleep(Duration::from_millis(500));
+        relay.send(b"lost");
+        wait_for(|| relay.status()[0].dropped == 1);
+        assert!(!relay.status()[0].connected);
+
+        // Goes away after the handshake
+        let listener = network.bind(addr).unwrap();
+        accept(&listener);
+        wait_for(|| relay.status()[0].connected);
+        drop(listener);
+        wait_for(|| !relay.status()[0].connected);
+
+        let (_, received, _) = listen(network.bind(addr).unwrap(), ListenerOptions::default());
+        wait_for(|| relay.status()[0].connected);
+        relay.send(b"hello");
+        wait_for(|| !received.lock().unwrap().is_empty());
+
+        assert_eq!(*received.lock().unwrap(), vec![b"hello".to_vec()]);
+        assert_eq!(relay.status()[0].connections, 2);
+    }
+}
@@ -0,0 +1,188 @@
+//! Wrapping packet sequence numbers (31 bits) and message numbers (26 bits)
+//!
+//! Both count up and wrap around to 0, so they are compared by their distance
+//! (less than half the number space apart), the same way as libsrt's `CSeqNo`.
+
+use std::{
+    cmp::Ordering,
+    fmt,
+    ops::{Add, AddAssign, Sub},
+};
+
+macro_rules! wrapping_number {
+    ($(#[$meta:meta])* $name:ident, $bits:expr) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+        pub struct $name(u32);
+
+        impl $name {
+            pub const BITS: u32 = $bits;
+            pub const MAX: Self = Self((1 << $bits) - 1);
+
+            /// Bits above [`Self::BITS`] are dropped
+            pub const fn new(raw: u32) -> Self {
+                Self(raw & Self::MAX.0)
+            }
+
+            pub const fn get(self) -> u32 {
+                self.0
+            }
+
+            /// `other - self`, the shorter way around
+            ///
+            /// Ranges from `-2^(BITS - 1)` to `2^(BITS - 1) - 1`.
+            #[allow(clippy::cast_possible_wrap)]
+            pub const fn distance(self, other: Self) -> i32 {
+                let diff = other.0.wrapping_sub(self.0) & Self::MAX.0;
+                // Sign-extend
+                ((diff << (32 - $bits)) as i32) >> (32 - $bits)
+            }
+
+            /// `self..=last` (empty if `last` comes before `self`)
+            pub fn range_inclusive(self, last: Self) -> impl Iterator<Item = Self> {
+                let count = u32::try_from(self.distance(last) + 1).unwrap_or(0);
+                (0..count).map(move |k| self + k)
+            }
+        }
+
+        impl Add<u32> for $name {
+            type Output = Self;
+
+            fn add(self, rhs: u32) -> Self {
+                Self::new(self.0.wrapping_add(rhs))
+            }
+        }
+
+        impl AddAssign<u32> for $name {
+            fn add_assign(&mut self, rhs: u32) {
+                *self = *self + rhs;
+            }
+        }
+
+        impl Sub<u32> for $name {
+            type Output = Self;
+
+            fn sub(self, rhs: u32) -> Self {
+                Self::new(self.0.wrapping_sub(rhs))
+            }
+        }
+
+        /// By distance, see [`Self::distance`]
+        impl PartialOrd for $name {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(0.cmp(&self.distance(*other)))
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl From<$name> for u32 {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+    };
+}
+
+wrapping_number! {
+    /// `Packet Sequence Number`
+    SeqNo, 31
+}
+
+wrapping_number! {
+    /// `Message Number`
+    MsgNo, 26
+}
+
+impl SeqNo {
+    #[must_use]
+    pub fn next(self) -> Self {
+        self + 1
+    }
+}
+
+impl MsgNo {
+    /// Skips 0, which is reserved (`SRT_MSGNO_CONTROL`)
+    #[must_use]
+    pub fn next(self) -> Self {
+        match self + 1 {
+            Self(0) => Self(1),
+            next => next,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seq_wrap() {
+        let max = SeqNo::MAX;
+        assert_eq!(max.get(), (1 << 31) - 1);
+        assert_eq!(SeqNo::new(u32::MAX), max);
+        assert_eq!(max.next(), SeqNo::new(0));
+        assert_eq!(SeqNo::new(0) - 1, max);
+        assert_eq!(max + 10, SeqNo::new(9));
+
+        assert_eq!(max.distance(SeqNo::new(0)), 1);
+        assert_eq!(SeqNo::new(0).distance(max), -1);
+        assert_eq!(max.distance(SeqNo::new(4)), 5);
+        assert!(max < SeqNo::new(0));
+        assert!(SeqNo::new(3) > max - 3);
+
+        let range: Vec<_> = (max - 1)
+            .range_inclusive(SeqNo::new(1))
+            .map(SeqNo::get)
+            .collect();
+        assert_eq!(range, vec![max.get() - 1, max.get(), 0, 1]);
+        assert_eq!(SeqNo::new(1).range_inclusive(max).count(), 0);
+        assert_eq!(max.range_inclusive(max).count(), 1);
+    }
+
+    #[test]
+    fn test_seq_distance_exhaustive() {
+        // Every distance around every boundary
+        let half: i32 = 1 << 30;
+        let bases = [0, 1, half - 1, half, half + 1, -2, -1];
+        for base in bases.map(|base| SeqNo::new(base.cast_unsigned())) {
+            for d in (-half..half)
+                .step_by(65_537)
+                .chain([-half, -1, 0, 1, half - 1])
+            {
+                let other = match u32::try_from(d) {
+                    Ok(d) => base + d,
+                    Err(_) => base - d.unsigned_abs(),
+                };
+                assert_eq!(base.distance(other), d, "{base} -> {other}");
+                assert_eq!(other.distance(base), if d == -half { -half } else { -d });
+                assert_eq!(base < other, d > 0);
+                assert_eq!(base == other, d == 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_msg_wrap() {
+        let max = MsgNo::MAX;
+        assert_eq!(max.get(), (1 << 26) - 1);
+        assert_eq!(MsgNo::new(0xFFFF_FFFF), max);
+        assert_eq!(max.next(), MsgNo::new(1));
+        assert_eq!(max + 1, MsgNo::new(0));
+
+        assert_eq!(max.distance(MsgNo::new(1)), 2);
+        assert_eq!(MsgNo::new(1).distance(max), -2);
+        assert!(max < MsgNo::new(1));
+        assert_eq!(MsgNo::new(1 << 25).distance(MsgNo::new(0)), -(1 << 25));
+
+        for raw in 0..(1 << 12) {
+            let number = MsgNo::new(raw) - (1 << 11);
+            assert_eq!(number.distance(number + 1000), 1000);
+            assert_eq!((number + 1000).distance(number), -1000);
+        }
+    }
+}
@@ -1,4 +1,261 @@
+//! Common interface of everything, that goes over the wire
+//!
+//! Control and data packet info is laid out as a whole datagram, with
+//! `Timestamp` and `Destination Socket ID` set to 0 (those belong to [`Packet`]).
+//!
+//! [`Packet`]: crate::packet::Packet
+
+use crate::{
+    error::Result,
+    packet::{
+        Packet, PacketContent,
+        control::{
+            ControlPacketInfo,
+            ack::Ack,
+            ack_ack::AckAck,
+            drop_req::DropReq,
+            extended::Extended,
+            handshake::{
+                Handshake,
+                extension::{
+                    Extension, congestion::CongestionExtension,
+                    group_membership::GroupMembershipExtension, handshake::HandshakeExtension,
+                    key_material::KeyMaterialExtension, packet_filter::PacketFilterExtension,
+                    stream_id::StreamIdExtension,
+                },
+            },
+            nak::Nak,
+            peer_error::PeerError,
+        },
+        data::DataPacketInfo,
+    },
+};
+
 pub trait Serial: Sized {
-    fn from_raw(raw: &[u8]) -> anyhow::Result<Self>;
+    fn from_raw(raw: &[u8]) -> Result<Self>;
     fn to_raw(&self) -> Vec<u8>;
 }
+
+/// Implement [`Serial`] with the inherent `from_raw` and `to_raw`
+macro_rules! inherent_serial {
+    ($($name:ty),* $(,)?) => {
+        $(
+            impl Serial for $name {
+                fn from_raw(raw: &[u8]) -> Result<Self> {
+                    <$name>::from_raw(raw)
+                }
+
+                fn to_raw(&self) -> Vec<u8> {
+                    <$name>::to_raw(self)
+                }
+            }
+        )*
+    };
+}
+
+inherent_serial!(
+    Packet,
+    PacketContent,
+    ControlPacketInfo,
+    DataPacketInfo,
+    Ack,
+    AckAck,
+    DropReq,
+    PeerError,
+    Extended,
+    HandshakeExtension,
+    KeyMaterialExtension,
+    StreamIdExtension,
+    CongestionExtension,
+    PacketFilterExtension,
+    GroupMembershipExtension,
+);
+
+/// `Control Information Field` only
+impl Serial for Handshake {
+    fn from_raw(raw: &[u8]) -> Result<Self> {
+        Self::from_raw_cif(raw)
+    }
+
+    fn to_raw(&self) -> Vec<u8> {
+        self.raw_content()
+    }
+}
+
+/// Single loss list entry
+impl Serial for Nak {
+    fn from_raw(raw: &[u8]) -> Result<Self> {
+        Ok(Self::from_raw_at(raw, 0)?.0)
+    }
+
+    fn to_raw(&self) -> Vec<u8> {
+        self.raw_content()
+    }
+}
+
+/// Single extension block (the rest of `raw` is ignored)
+impl Serial for Extension {
+    fn from_raw(raw: &[u8]) -> Result<Self> {
+        Ok(Self::from_raw(raw)?.0)
+    }
+
+    fn to_raw(&self) -> Vec<u8> {
+        self.to_raw()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Datagrams laid out as libsrt 1.5 sends them (incl. the 4-byte pad of empty CIFs)
+    const INDUCTION: &str = "
+        80000000 00000000 000003e8 00000000
+        00000004 00000002 1f2e3d4c 000005dc 00002000 00000001 2a3b4c5d 00000000
+        0100007f 00000000 00000000 00000000";
+    const INDUCTION_RESPONSE: &str = "
+        80000000 00000000 000003e9 2a3b4c5d
+        00000005 00004a17 1f2e3d4c 000005dc 00002000 00000001 10203040 7d1fe2b3
+        0100007f 00000000 00000000 00000000";
+    const CONCLUSION: &str = "
+        80000000 00000000 000007d0 00000000
+        00000005 00020007 1f2e3d4c 000005dc 00002000 ffffffff 2a3b4c5d 7d1fe2b3
+        0100007f 00000000 00000000 00000000
+        00010003 00010502 000000bf 00780078
+        0003000e 12202901 00000000 02000200 00000404
+        00112233 44556677 8899aabb ccddeeff
+        a0a1a2a3 a4a5a6a7 a8a9aaab acadaeaf b0b1b2b3 b4b5b6b7
+        00050002 6576696c 6362612f";
+    const DATA: &str = "
+        12345678 e0000001 000f4240 2a3b4c5d
+        47400010 00b00d00 01c10000";
+    const KEEPALIVE: &str = "80010000 00000000 00001388 2a3b4c5d 00000000";
+    const FULL_ACK: &str = "
+        80020000 00000007 00002710 2a3b4c5d
+        12345679 000186a0 0000c350 00001fff 000003e8 00002710 0001d4c0";
+    const LIGHT_ACK: &str = "80020000 00000000 00002711 2a3b4c5d 1234567a";
+    const NAK: &str = "80030000 00000000 00002712 2a3b4c5d 92345680 12345683 12345690";
+    const SHUTDOWN: &str = "80050000 00000000 00002713 2a3b4c5d 00000000";
+    const ACKACK: &str = "80060000 00000007 00002714 2a3b4c5d 00000000";
+    const DROPREQ: &str = "80070000 00000003 00002715 2a3b4c5d 12345670 12345672";
+    const PEER_ERROR: &str = "80080000 000003e8 00002716 2a3b4c5d 00000000";
+    const EXT_HSREQ: &str = "ffff0001 00000000 00002717 2a3b4c5d 00010502 000000bf 00780078";
+    const EXT_KMREQ: &str = "
+        ffff0003 00000000 00002718 2a3b4c5d
+        12202901 00000000 02000200 00000404
+        00112233 44556677 8899aabb ccddeeff
+        a0a1a2a3 a4a5a6a7 a8a9aaab acadaeaf b0b1b2b3 b4b5b6b7";
+    /// Application-defined subtype, CIF not padded to 32 bits
+    const EXT_USER: &str = "ffff1000 00000000 00002719 2a3b4c5d 6f6e2061 6972";
+
+    fn hex(s: &str) -> Vec<u8> {
+        let digits: Vec<u8> = s.bytes().filter(u8::is_ascii_hexdigit).collect();
+
+        digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    fn roundtrip<T: Serial>(raw: &[u8]) -> T {
+        let parsed = T::from_raw(raw).unwrap();
+        assert_eq!(parsed.to_raw(), raw);
+
+        parsed
+    }
+
+    /// Round-trip the whole datagram, and its content on its own
+    fn roundtrip_datagram(s: &str) -> PacketContent {
+        let raw = hex(s);
+        roundtrip::<Packet>(&raw);
+
+        let mut content = raw;
+        content[8..16].fill(0);
+        roundtrip(&content)
+    }
+
+    #[test]
+    fn test_handshake_roundtrip() {
+        for s in [INDUCTION, INDUCTION_RESPONSE, CONCLUSION] {
+            let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) =
+                roundtrip_datagram(s)
+            else {
+                panic!("Not a handshake");
+            };
+
+            let raw = hex(s);
+            roundtrip::<Handshake>(&raw[16..]);
+
+            let mut offset = 64;
+            for ext in &handshake.extensions {
+                roundtrip::<Extension>(&raw[offset..offset + ext.to_raw().len()]);
+                offset += ext.to_raw().len();
+            }
+        }
+
+        let raw = hex(CONCLUSION);
+        let handshake = roundtrip::<Handshake>(&raw[16..]);
+        assert_eq!(
+            handshake.stream_id_extension().unwrap().stream_id,
+            "live/abc"
+        );
+        assert_eq!(handshake.key_material_extension().unwrap().key_length(), 16);
+        roundtrip::<HandshakeExtension>(&raw[64..80]);
+        roundtrip::<KeyMaterialExtension>(&raw[80..140]);
+        roundtrip::<StreamIdExtension>(&raw[140..]);
+    }
+
+    #[test]
+    fn test_data_roundtrip() {
+        let PacketContent::Data(data) = roundtrip_datagram(DATA) else {
+            panic!("Not a data packet");
+        };
+        assert_eq!(data.packet_sequence_number.get(), 0x1234_5678);
+        assert_eq!(data.message_number.get(), 1);
+        assert!(data.order);
+        assert_eq!(data.content.len(), 12);
+    }
+
+    #[test]
+    fn test_control_roundtrip() {
+        for s in [
+            KEEPALIVE, FULL_ACK, LIGHT_ACK, NAK, SHUTDOWN, ACKACK, DROPREQ, PEER_ERROR, EXT_HSREQ,
+            EXT_KMREQ, EXT_USER,
+        ] {
+            roundtrip_datagram(s);
+        }
+
+        let mut raw = hex(NAK);
+        raw[8..16].fill(0);
+        let PacketContent::Control(ControlPacketInfo::Nak(loss_list)) = roundtrip(&raw) else {
+            panic!("Not a NAK");
+        };
+        assert_eq!(loss_list.len(), 2);
+        roundtrip::<Nak>(&raw[16..24]);
+        roundtrip::<Nak>(&raw[24..]);
+
+        let mut raw = hex(FULL_ACK);
+        raw[8..16].fill(0);
+        roundtrip::<Ack>(&raw);
+        let mut raw = hex(ACKACK);
+        raw[8..16].fill(0);
+        roundtrip::<AckAck>(&raw);
+        let mut raw = hex(DROPREQ);
+        raw[8..16].fill(0);
+        roundtrip::<DropReq>(&raw);
+        let mut raw = hex(PEER_ERROR);
+        raw[8..16].fill(0);
+        roundtrip::<PeerError>(&raw);
+
+        let mut raw = hex(EXT_KMREQ);
+        raw[8..16].fill(0);
+        let ext = roundtrip::<Extended>(&raw);
+        assert!(matches!(ext, Extended::Srt(Extension::KeyMaterial(_))));
+        let mut raw = hex(EXT_USER);
+        raw[8..16].fill(0);
+        let Extended::User { subtype, data } = roundtrip::<Extended>(&raw) else {
+            panic!("Not a user-defined subtype");
+        };
+        assert_eq!((subtype, data.as_slice()), (0x1000, b"on air".as_slice()));
+    }
+}
@@ -1,94 +1,1014 @@
 use std::{
     collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    io::{ErrorKind, Write},
     net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    num::NonZeroUsize,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 
 use crate::{
-    connection::Connection,
-    constants::MAX_PACKET_SIZE,
-    packet::{Packet, PacketContent, control::ControlPacketInfo},
+    admission::Admission,
+    batch::{BATCH_SIZE, Datagram, RecvBatch},
+    capture::{Direction, Recorder},
+    clock::{Clock, SystemClock},
+    connection::{Connection, DisconnectReason},
+    constants::FULL_ACK_INTERVAL,
+    group::Groups,
+    options::ListenerOptions,
+    packet::Packet,
+    socket,
+    subscription::{Mode, Subscriptions},
+    transport::Transport,
 };
 
-type OnConnectHandler = dyn Fn(&Connection);
-type OnDiscnnectHandler = dyn Fn(&Connection);
-pub type OnDataHandler = dyn Fn(&Connection, &[u8]);
+/// Datagrams waiting for each worker (more are dropped, so a flood can't use up memory)
+const WORKER_QUEUE_SIZE: usize = 8192;
 
-pub struct Server<'c> {
-    socket: UdpSocket,
-    connections: HashMap<SocketAddr, Connection<'c>>,
+type OnConnectHandler = dyn Fn(&Connection) + Send + Sync;
+type OnDiscnnectHandler = dyn Fn(&Connection, DisconnectReason) + Send + Sync;
+pub type OnDataHandler = dyn Fn(&Connection, &[u8]) + Send + Sync;
+type OnExtendedHandler = dyn Fn(&Connection, u16, &[u8]) + Send + Sync;
 
+#[derive(Default)]
+struct Handlers {
     on_connect: Option<Box<OnConnectHandler>>,
     on_disconnect: Option<Box<OnDiscnnectHandler>>,
     on_data: Option<Box<OnDataHandler>>,
+    on_extended: Option<Box<OnExtendedHandler>>,
 }
 
-impl<'c> Server<'c> {
+/// SRT listener
+///
+/// Connections are spread across worker threads (by peer address),
+/// so handlers may be called from several threads at once.
+///
+/// Members of a socket group are reported as one connection:
+/// `on_connect` is called for the first link and `on_disconnect` after the last one.
+///
+/// Callers in request mode receive what publishers of the same resource send
+/// (see [`crate::subscription`]), their messages aren't passed to `on_data`.
+///
+/// Datagrams, that fail to parse, are counted (see [`Server::bad_packets`]) and dropped.
+/// Handshakes of new callers go through [`Admission`] (see [`ListenerOptions::limits`]).
+///
+/// Runs over UDP by default, or any other [`Transport`] (see [`Server::with_transport`]).
+pub struct Server<T: Transport = UdpSocket> {
+    socket: T,
+    workers: NonZeroUsize,
+    options: ListenerOptions,
+    handlers: Handlers,
+    groups: Groups,
+    subscriptions: Subscriptions,
+    clock: Arc<dyn Clock>,
+    recorder: Option<Recorder>,
+    bad_packets: AtomicU64,
+}
+
+impl Server {
     pub fn new<A>(addr: A) -> Result<Self>
     where
         A: ToSocketAddrs,
     {
-        let socket = UdpSocket::bind(addr)?;
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("No address to bind to"))?;
 
-        Ok(Self {
+        Ok(Self::with_transport(socket::bind(addr)?))
+    }
+}
+
+impl<T: Transport> Server<T> {
+    pub fn with_transport(socket: T) -> Self {
+        let workers = thread::available_parallelism().unwrap_or(NonZeroUsize::MIN);
+
+        Self {
             socket,
-            connections: HashMap::new(),
-            on_connect: None,
-            on_disconnect: None,
-            on_data: None,
-        })
+            workers,
+            options: ListenerOptions::default(),
+            handlers: Handlers::default(),
+            groups: Groups::default(),
+            subscriptions: Subscriptions::default(),
+            clock: Arc::new(SystemClock),
+            recorder: None,
+            bad_packets: AtomicU64::new(0),
+        }
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
     }
 
-    pub fn on_connect(&mut self, f: impl Fn(&Connection) + 'static) {
-        self.on_connect = Some(Box::new(f));
+    /// Set # of worker threads (defaults to available parallelism)
+    pub fn workers(&mut self, n: NonZeroUsize) {
+        self.workers = n;
     }
 
-    pub fn on_disconnect(&mut self, f: impl Fn(&Connection) + 'static) {
-        self.on_disconnect = Some(Box::new(f));
+    /// Set options (defaults and overrides by stream ID)
+    pub fn options(&mut self, options: ListenerOptions) -> Result<()> {
+        options.validate()?;
+        self.options = options;
+
+        Ok(())
     }
 
-    pub fn on_data(&mut self, f: impl Fn(&Connection, &[u8]) + 'static) {
-        self.on_data = Some(Box::new(f));
+    /// Set the time source of connection timers (defaults to [`SystemClock`])
+    pub fn clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Capture every inbound and outbound datagram as pcapng (see [`crate::capture`])
+    pub fn record(&mut self, writer: impl Write + Send + 'static) -> Result<()> {
+        self.recorder = Some(Recorder::new(self.socket.local_addr()?, writer)?);
+
+        Ok(())
+    }
+
+    pub fn on_connect(&mut self, f: impl Fn(&Connection) + Send + Sync + 'static) {
+        self.handlers.on_connect = Some(Box::new(f));
+    }
+
+    pub fn on_disconnect(
+        &mut self,
+        f: impl Fn(&Connection, DisconnectReason) + Send + Sync + 'static,
+    ) {
+        self.handlers.on_disconnect = Some(Box::new(f));
+    }
+
+    pub fn on_data(&mut self, f: impl Fn(&Connection, &[u8]) + Send + Sync + 'static) {
+        self.handlers.on_data = Some(Box::new(f));
+    }
+
+    /// Application-defined control messages (subtype, data)
+    ///
+    /// Reply with [`Connection::send_extended`].
+    pub fn on_extended(&mut self, f: impl Fn(&Connection, u16, &[u8]) + Send + Sync + 'static) {
+        self.handlers.on_extended = Some(Box::new(f));
+    }
+
+    /// # of dropped datagrams, that failed to parse
+    pub fn bad_packets(&self) -> u64 {
+        self.bad_packets.load(Ordering::Relaxed)
+    }
+
+    pub fn run(&self) -> Result<()> {
+        // Wake up regularly to notice stopped workers
+        self.socket
+            .set_read_timeout(Some(Duration::from_micros(FULL_ACK_INTERVAL.into())))?;
+
+        let socket = &self.socket;
+        let handlers = &self.handlers;
+        let groups = &self.groups;
+        let subscriptions = &self.subscriptions;
+        let options = &self.options;
+        let clock = &self.clock;
+        let recorder = self.recorder.as_ref();
+        let bad_packets = &self.bad_packets;
+        let admission = &Admission::new(&self.options);
+
+        thread::scope(|s| {
+            let mut senders = Vec::new();
+            let mut workers = Vec::new();
+
+            for _ in 0..self.workers.get() {
+                let (tx, rx) = mpsc::sync_channel(WORKER_QUEUE_SIZE);
+                let worker = Worker {
+                    socket,
+                    handlers,
+                    groups,
+                    subscriptions,
+                    options,
+                    clock,
+                    recorder,
+                    bad_packets,
+                    admission,
+                    inbound: rx,
+                    connections: HashMap::new(),
+                    outbound: Vec::new(),
+                };
+
+                senders.push(tx);
+                workers.push(s.spawn(move || worker.run()));
+            }
+
+            let res = self.receive(&senders);
+
+            // Stop workers and collect the first error
+            drop(senders);
+            workers
+                .into_iter()
+                .map(|w| w.join().unwrap_or_else(|_| Err(anyhow!("Worker panicked"))))
+                .fold(res, Result::and)
+        })
     }
 
-    fn recv(&self) -> Result<(SocketAddr, Packet)> {
-        let mut buf = [0; MAX_PACKET_SIZE];
+    /// Read datagrams and pass them to workers
+    fn receive(&self, workers: &[SyncSender<Datagram>]) -> Result<()> {
+        let hasher = RandomState::new();
+        let mut batch = RecvBatch::new();
 
-        let (n, addr) = self.socket.recv_from(&mut buf)?;
-        let data = &buf[..n];
-        let pack = Packet::from_raw(data)?;
+        loop {
+            match self.socket.recv_batch(&mut batch) {
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => return Err(e.into()),
+            }
 
-        Ok((addr, pack))
+            for (data, addr) in batch.iter() {
+                if let Some(recorder) = &self.recorder {
+                    recorder.record(self.clock.system_now(), Direction::Inbound, addr, data);
+                }
+
+                #[allow(clippy::cast_possible_truncation)]
+                let index = hasher.hash_one(addr) as usize % workers.len();
+
+                match workers[index].try_send((data.to_vec(), addr)) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        tracing::debug!("Worker queue full, dropped datagram from {addr}");
+                    }
+                    Err(TrySendError::Disconnected(_)) => return Ok(()),
+                }
+            }
+        }
     }
+}
+
+/// Owns a share of connections
+struct Worker<'s, T> {
+    socket: &'s T,
+    handlers: &'s Handlers,
+    groups: &'s Groups,
+    subscriptions: &'s Subscriptions,
+    options: &'s ListenerOptions,
+    clock: &'s Arc<dyn Clock>,
+    recorder: Option<&'s Recorder>,
+    bad_packets: &'s AtomicU64,
+    admission: &'s Admission,
+
+    inbound: Receiver<Datagram>,
+    /// Shared with [`Subscriptions`] (subscribers only)
+    connections: HashMap<SocketAddr, Arc<Connection>>,
+
+    /// Packets waiting for the next [`Transport::send_batch`]
+    outbound: Vec<Datagram>,
+}
+
+impl<'s, T: Transport> Worker<'s, T> {
+    fn run(mut self) -> Result<()> {
+        let timer_interval = Duration::from_micros(FULL_ACK_INTERVAL.into());
+        let mut last_timer = Instant::now();
 
-    pub fn run(&'c mut self) -> Result<()> {
         loop {
-            let (addr, pack) = self.recv()?;
+            let timeout = timer_interval.saturating_sub(last_timer.elapsed());
 
-            if matches!(
-                pack.content,
-                PacketContent::Control(ControlPacketInfo::Shutdown)
-            ) {
-                if let Some(conn) = self.connections.remove(&addr) {
-                    conn.handle(&pack)?;
+            match self.inbound.recv_timeout(timeout) {
+                Ok((data, addr)) => {
+                    self.receive(addr, &data);
 
-                    if let Some(callback) = &self.on_disconnect {
-                        callback(&conn);
+                    // Handle whatever else is already waiting
+                    for (data, addr) in self.inbound.try_iter().take(BATCH_SIZE).collect::<Vec<_>>()
+                    {
+                        self.receive(addr, &data);
                     }
                 }
-            } else if let Some(conn) = self.connections.get(&addr) {
-                conn.handle(&pack)?;
-            } else {
-                let Ok(conn) = Connection::establish_v5(&self.socket, self.on_data.as_deref())
-                else {
-                    continue;
-                };
-                if let Some(callback) = &self.on_connect {
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+
+            if last_timer.elapsed() >= timer_interval {
+                last_timer = Instant::now();
+
+                for conn in self.connections.values() {
+                    if let Err(e) = conn.update() {
+                        tracing::warn!("Closing {}: {e}", conn.addr);
+                        conn.abort();
+                    }
+                    Self::collect(conn, &mut self.outbound);
+                }
+
+                self.expire_idle();
+            }
+
+            for conn in self.connections.values() {
+                self.deliver(conn);
+                // Handlers may have replied or failed the connection
+                Self::collect(conn, &mut self.outbound);
+            }
+
+            self.remove_closed();
+
+            if let Some(recorder) = self.recorder {
+                let now = self.clock.system_now();
+                for (data, addr) in &self.outbound {
+                    recorder.record(now, Direction::Outbound, *addr, data);
+                }
+            }
+
+            // e.g. unreachable callers, the rest of the batch is still sent
+            if let Err(e) = self.socket.send_batch(&self.outbound) {
+                tracing::debug!("Failed to send: {e}");
+            }
+            self.outbound.clear();
+        }
+    }
+
+    /// Parse and dispatch a datagram (bad ones are counted and skipped)
+    fn receive(&mut self, addr: SocketAddr, data: &[u8]) {
+        match Packet::from_raw(data) {
+            Ok(pack) => self.dispatch(addr, &pack),
+            Err(e) => {
+                self.bad_packets.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!("Bad packet from {addr}: {e}");
+            }
+        }
+    }
+
+    /// Move queued packets to the outbound batch
+    fn collect(conn: &Connection, outbound: &mut Vec<Datagram>) {
+        while let Some(pack) = conn.poll_transmit() {
+            outbound.push((pack.to_raw(), conn.addr));
+        }
+    }
+
+    /// Drop connections, that stopped responding
+    fn expire_idle(&mut self) {
+        let idle: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|(_, conn)| conn.is_idle())
+            .map(|(&addr, _)| addr)
+            .collect();
+
+        for addr in idle {
+            if let Some(conn) = self.connections.remove(&addr) {
+                tracing::warn!("Connection timed out: {addr}");
+                self.close(&conn, DisconnectReason::Timeout);
+            }
+        }
+    }
+
+    /// Drop connections, that were ended by either side
+    fn remove_closed(&mut self) {
+        let closed: Vec<_> = self
+            .connections
+            .iter()
+            .filter_map(|(&addr, conn)| Some((addr, conn.disconnect_reason()?)))
+            .collect();
+
+        for (addr, reason) in closed {
+            if let Some(conn) = self.connections.remove(&addr) {
+                tracing::info!("Connection closed: {addr} ({reason:?})");
+                self.close(&conn, reason);
+            }
+        }
+    }
+
+    fn close(&self, conn: &Connection, reason: DisconnectReason) {
+        self.admission.release();
+        if conn.mode() == Mode::Request {
+            self.subscriptions.unsubscribe(conn);
+        }
+        if conn.close(self.groups)
+            && let Some(callback) = &self.handlers.on_disconnect
+        {
+            callback(conn, reason);
+        }
+    }
+
+    /// Pass received messages to the handler and to subscribers
+    fn deliver(&self, conn: &Connection) {
+        let publish = conn.mode() == Mode::Publish;
+        let subscribed = publish && self.subscriptions.count(conn.resource()) > 0;
+        let on_data = self.handlers.on_data.as_ref().filter(|_| publish);
+
+        if subscribed || on_data.is_some() {
+            conn.deliver(|message| {
+                if let Some(callback) = on_data {
+                    callback(conn, message);
+                }
+                if subscribed {
+                    self.subscriptions.publish(conn.resource(), message);
+                }
+            });
+        }
+
+        // Always drain, so unhandled messages don't pile up
+        while let Some((subtype, data)) = conn.poll_extended() {
+            if let Some(callback) = &self.handlers.on_extended {
+                callback(conn, subtype, &data);
+            }
+        }
+    }
+
+    /// Errors only close the connection they belong to
+    fn dispatch(&mut self, addr: SocketAddr, pack: &Packet) {
+        // Closed connections are removed after delivering what's left (see `remove_closed`)
+        if let Some(conn) = self.connections.get(&addr) {
+            if let Err(e) = conn.handle(pack) {
+                tracing::warn!("Closing {addr}: {e}");
+                conn.abort();
+            }
+            Self::collect(conn, &mut self.outbound);
+        } else {
+            let Some(admitted) = self.admission.admit(pack, addr, &**self.clock) else {
+                return;
+            };
+            let accepted = Connection::accept(
+                pack,
+                addr,
+                admitted.cookie,
+                self.groups,
+                self.options,
+                self.clock,
+            );
+            let conn = match accepted {
+                Ok((response, conn)) => {
+                    self.outbound.push((response.to_raw(), addr));
+                    conn
+                }
+                Err(_) => None,
+            };
+
+            if conn.is_none() && admitted.reserved {
+                self.admission.release();
+            }
+            if let Some(conn) = conn {
+                if let Ok(local) = self.socket.local_addr()
+                    && conn.is_nat_mismatch(local.ip())
+                {
+                    tracing::info!("NAT: {addr} reached {}, bound to {local}", conn.reported_ip);
+                }
+
+                if conn.is_new_stream()
+                    && let Some(callback) = &self.handlers.on_connect
+                {
                     callback(&conn);
                 }
+                let conn = Arc::new(conn);
+                if conn.mode() == Mode::Request {
+                    self.subscriptions.subscribe(Arc::clone(&conn));
+                }
                 self.connections.insert(addr, conn);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        access::AccessControl,
+        capture::{Record, pcapng::Reader, replay::Replayer},
+        clock::ManualClock,
+        connection::caller::Caller,
+        options::{Limits, Options},
+        packet::{
+            PacketContent,
+            control::{
+                ControlPacketInfo,
+                ack::Ack,
+                handshake::{
+                    Handshake, HandshakeEncryption, HandshakeType,
+                    extension::{Extension, stream_id::StreamIdExtension},
+                },
+            },
+            data::{DataPacketInfo, EncryptionFlag, PacketPosition},
+        },
+        seq::{MsgNo, SeqNo},
+        transport::{
+            MemoryNetwork, MemoryTransport,
+            impair::{Impaired, Impairment, Loss},
+        },
+    };
+
+    const ISN: SeqNo = SeqNo::new(100);
+
+    fn handshake(handshake_type: HandshakeType, version: u32, syn_cookie: u32) -> Packet {
+        Packet {
+            timestamp: 0,
+            dest_socket_id: 0,
+            content: PacketContent::Control(ControlPacketInfo::Handshake(Handshake {
+                version,
+                encryption: HandshakeEncryption::NoEncryption,
+                extension_field: if version == 4 { 2 } else { 0x05 },
+                initial_packet_sequence_number: ISN.get(),
+                maximum_transmission_unit_size: 1500,
+                maximum_flow_window_size: 8192,
+                handshake_type,
+                srt_socket_id: 7,
+                syn_cookie,
+                peer_ip_address: "127.0.0.1".parse().unwrap(),
+                extensions: Vec::new(),
+            })),
+        }
+    }
+
+    fn payload(k: u32) -> Vec<u8> {
+        k.to_be_bytes().repeat(25)
+    }
+
+    fn data(k: u32, retransmitted: bool) -> Packet {
+        Packet {
+            timestamp: 1000 * k,
+            dest_socket_id: 42,
+            content: PacketContent::Data(DataPacketInfo {
+                packet_sequence_number: ISN + k,
+                position: PacketPosition::Single,
+                order: false,
+                encryption: EncryptionFlag::NoEncryption,
+                retransmitted,
+                message_number: MsgNo::new(1) + k,
+                content: payload(k),
+            }),
+        }
+    }
+
+    fn recv(caller: &impl Transport) -> Option<Packet> {
+        let mut buf = [0; 1500];
+        let (n, _) = caller.recv_from(&mut buf).ok()?;
+        Some(Packet::from_raw(&buf[..n]).unwrap())
+    }
+
+    /// Caller side of the handshake (each step is repeated until answered)
+    ///
+    /// The caller read timeout should be short.
+    fn connect(caller: &impl Transport, server: SocketAddr) {
+        let send = |pack: Packet| caller.send_to(&pack.to_raw(), server).unwrap();
+        let answered = |sent: Instant| {
+            std::iter::from_fn(|| {
+                (sent.elapsed() < Duration::from_millis(500)).then(|| recv(caller))
+            })
+            .flatten()
+            .next()
+        };
+
+        let cookie = loop {
+            send(handshake(HandshakeType::Induction, 4, 0));
+            if let Some(Packet {
+                content: PacketContent::Control(ControlPacketInfo::Handshake(induction)),
+                ..
+            }) = answered(Instant::now())
+            {
+                break induction.syn_cookie;
+            }
+        };
+
+        // Repeated Conclusions are answered again, anything else also confirms it (e.g. ACKs)
+        loop {
+            send(handshake(HandshakeType::Conclusion, 5, cookie));
+            if answered(Instant::now()).is_some() {
+                return;
+            }
+        }
+    }
+
+    /// Full ACKs come on the periodic timer (may start before the last packet arrives)
+    fn wait_for_ack(caller: &impl Transport, seq: SeqNo) {
+        std::iter::repeat_with(|| recv(caller).unwrap())
+            .find(|pack| {
+                matches!(
+                    pack.content,
+                    PacketContent::Control(ControlPacketInfo::Ack(Ack::Full {
+                        last_ackd_packet_sequence_number,
+                        ..
+                    })) if last_ackd_packet_sequence_number == seq
+                )
+            })
+            .unwrap();
+    }
+
+    fn run(server: Server<impl Transport + 'static>) -> (SocketAddr, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let mut server = server;
+        let addr = server.local_addr().unwrap();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let on_data = Arc::clone(&received);
+        server.on_data(move |_, message| on_data.lock().unwrap().push(message.to_vec()));
+        // Runs until the test ends
+        thread::spawn(move || server.run());
+
+        (addr, received)
+    }
+
+    #[test]
+    fn test_memory_session() {
+        let network = MemoryNetwork::default();
+        let (server, received) = run(Server::with_transport(network.bind_any().unwrap()));
+
+        let caller = network.bind_any().unwrap();
+        caller
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        connect(&caller, server);
+
+        for k in 0..3 {
+            caller.send_to(&data(k, false).to_raw(), server).unwrap();
+        }
+
+        wait_for_ack(&caller, ISN + 3);
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            (0..3).map(payload).collect::<Vec<_>>()
+        );
+    }
+
+    /// Sending to `unreachable` fails
+    struct Unreachable<T> {
+        inner: T,
+        unreachable: SocketAddr,
+    }
+
+    impl<T: Transport> Transport for Unreachable<T> {
+        fn send_to(&self, data: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+            if addr == self.unreachable {
+                return Err(ErrorKind::HostUnreachable.into());
+            }
+            self.inner.send_to(data, addr)
+        }
+
+        fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+            self.inner.recv_from(buf)
+        }
+
+        fn local_addr(&self) -> std::io::Result<SocketAddr> {
+            self.inner.local_addr()
+        }
+
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+            self.inner.set_read_timeout(timeout)
+        }
+    }
+
+    #[test]
+    fn test_send_error() {
+        let network = MemoryNetwork::default();
+        let unreachable = network.bind_any().unwrap();
+        let mut server = Server::with_transport(Unreachable {
+            inner: network.bind_any().unwrap(),
+            unreachable: unreachable.local_addr().unwrap(),
+        });
+        server.workers(NonZeroUsize::MIN);
+        let (server, received) = run(server);
+
+        // Its response fails, in the same batch as the other caller's
+        unreachable
+            .send_to(&handshake(HandshakeType::Induction, 4, 0).to_raw(), server)
+            .unwrap();
+
+        let caller = network.bind_any().unwrap();
+        caller
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        connect(&caller, server);
+        caller.send_to(&data(0, false).to_raw(), server).unwrap();
+        wait_for_ack(&caller, ISN + 1);
+
+        assert_eq!(*received.lock().unwrap(), [payload(0)]);
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_handshake_limits() {
+        let network = MemoryNetwork::default();
+        let mut server = Server::with_transport(network.bind_any().unwrap());
+        server
+            .options(ListenerOptions {
+                limits: Limits {
+                    max_connections: Some(1),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .unwrap();
+        let (server, _) = run(server);
+
+        let caller = || {
+            let caller = network.bind_any().unwrap();
+            caller
+                .set_read_timeout(Some(Duration::from_millis(100)))
+                .unwrap();
+            caller
+        };
+        let send = |caller: &MemoryTransport, pack: Packet| {
+            caller.send_to(&pack.to_raw(), server).unwrap();
+        };
+        let cookie = |caller: &MemoryTransport| {
+            send(caller, handshake(HandshakeType::Induction, 4, 0));
+            let Some(Packet {
+                content: PacketContent::Control(ControlPacketInfo::Handshake(induction)),
+                ..
+            }) = recv(caller)
+            else {
+                panic!("No Induction response");
+            };
+            induction.syn_cookie
+        };
+
+        let first = caller();
+        connect(&first, server);
+
+        // Over the connection limit
+        let second = caller();
+        let second_cookie = cookie(&second);
+        send(
+            &second,
+            handshake(HandshakeType::Conclusion, 5, second_cookie),
+        );
+        assert!(recv(&second).is_none());
+
+        // Cookie of another address
+        let third = caller();
+        send(
+            &third,
+            handshake(HandshakeType::Conclusion, 5, second_cookie),
+        );
+        assert!(recv(&third).is_none());
+
+        // A flood of Inductions (from an address of its own) is answered up to the burst
+        let flood = network.bind("192.0.2.1:0".parse().unwrap()).unwrap();
+        flood
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        for _ in 0..100 {
+            send(&flood, handshake(HandshakeType::Induction, 4, 0));
+        }
+        let answered = std::iter::from_fn(|| recv(&flood)).count();
+        assert!((20..25).contains(&answered), "{answered}");
+
+        // The slot is free again once the first caller leaves
+        send(
+            &first,
+            Packet {
+                timestamp: 0,
+                dest_socket_id: 42,
+                content: PacketContent::Control(ControlPacketInfo::Shutdown),
+            },
+        );
+        thread::sleep(Duration::from_millis(50));
+        connect(&second, server);
+    }
+
+    #[test]
+    fn test_access_rules() {
+        let network = MemoryNetwork::default();
+        let access = AccessControl::new("deny any live/venue*".parse().unwrap());
+        let mut server = Server::with_transport(network.bind_any().unwrap());
+        server
+            .options(ListenerOptions {
+                access: access.clone(),
+                ..Default::default()
+            })
+            .unwrap();
+        let (server, received) = run(server);
+
+        let venue = network.bind("10.20.0.1:0".parse().unwrap()).unwrap();
+        venue
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let send = |pack: Packet| venue.send_to(&pack.to_raw(), server).unwrap();
+
+        // Answered, as other streams are allowed
+        send(handshake(HandshakeType::Induction, 4, 0));
+        let Some(Packet {
+            content: PacketContent::Control(ControlPacketInfo::Handshake(induction)),
+            ..
+        }) = recv(&venue)
+        else {
+            panic!("No Induction response");
+        };
+
+        let mut conclusion = handshake(HandshakeType::Conclusion, 5, induction.syn_cookie);
+        if let PacketContent::Control(ControlPacketInfo::Handshake(handshake)) =
+            &mut conclusion.content
+        {
+            handshake
+                .extensions
+                .push(Extension::StreamId(StreamIdExtension::new(
+                    "live/venue1".to_owned(),
+                )));
+        }
+        let conclusion = conclusion.to_raw();
+        venue.send_to(&conclusion, server).unwrap();
+        assert!(recv(&venue).is_none());
+
+        access.reload(
+            "allow 10.20.0.0/16 live/venue*\ndeny any live/venue*"
+                .parse()
+                .unwrap(),
+        );
+        venue.send_to(&conclusion, server).unwrap();
+        assert!(recv(&venue).is_some());
+
+        // Live connections stay, when the rules change
+        access.reload("deny any".parse().unwrap());
+        send(data(0, false));
+        wait_for_ack(&venue, ISN + 1);
+        assert_eq!(*received.lock().unwrap(), [payload(0)]);
+
+        let other = network.bind_any().unwrap();
+        other
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        other
+            .send_to(&handshake(HandshakeType::Induction, 4, 0).to_raw(), server)
+            .unwrap();
+        assert!(recv(&other).is_none());
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let network = MemoryNetwork::default();
+        let capture = SharedBuf::default();
+        let mut server = Server::with_transport(network.bind_any().unwrap());
+        server.record(capture.clone()).unwrap();
+        let (server, received) = run(server);
+
+        let caller = network.bind_any().unwrap();
+        caller
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        connect(&caller, server);
+        for k in 0..3 {
+            caller.send_to(&data(k, false).to_raw(), server).unwrap();
+        }
+        wait_for_ack(&caller, ISN + 3);
+
+        let raw = capture.0.lock().unwrap().clone();
+        let records: Vec<_> = Reader::new(&raw[..])
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        let caller = caller.local_addr().unwrap();
+        let inbound = |record: &&Record| record.direction == Some(Direction::Inbound);
+        assert!(
+            records
+                .iter()
+                .filter(inbound)
+                .all(|record| (record.src, record.dst) == (caller, server))
+        );
+        assert_eq!(records.iter().filter(inbound).count(), 5);
+        assert!(records.iter().any(|record| {
+            record.direction == Some(Direction::Outbound)
+                && matches!(
+                    Packet::from_raw(&record.data).unwrap().content,
+                    PacketContent::Control(ControlPacketInfo::Ack(_))
+                )
+        }));
+
+        let mut replayer = Replayer::new(server);
+        let mut replayed = Vec::new();
+        for record in &records {
+            replayer
+                .replay(record, |_, message| replayed.push(message.to_vec()))
+                .unwrap();
+        }
+        replayer.finish(|_, message| replayed.push(message.to_vec()));
+        assert_eq!(replayed, *received.lock().unwrap());
+        assert_eq!(replayer.connections().count(), 1);
+    }
+
+    /// Caller on a manual clock, stepped by the test
+    struct Peer {
+        transport: MemoryTransport,
+        server: SocketAddr,
+        caller: Caller,
+        conn: Option<Connection>,
+        received: Vec<Vec<u8>>,
+    }
+
+    impl Peer {
+        fn new(
+            network: &MemoryNetwork,
+            server: SocketAddr,
+            stream_id: &str,
+            clock: &ManualClock,
+        ) -> Self {
+            let transport = network.bind_any().unwrap();
+            // Only long enough for the server thread to answer
+            transport
+                .set_read_timeout(Some(Duration::from_micros(200)))
+                .unwrap();
+            let caller = Caller::new(
+                server,
+                Some(stream_id.to_owned()),
+                Options::default(),
+                Arc::new(clock.clone()),
+            )
+            .unwrap();
+
+            Self {
+                transport,
+                server,
+                caller,
+                conn: None,
+                received: Vec::new(),
+            }
+        }
+
+        /// Handle what arrived, run timers, send what's queued and collect delivered messages
+        fn step(&mut self) {
+            while let Some(pack) = recv(&self.transport) {
+                match &self.conn {
+                    Some(conn) => conn.handle(&pack).unwrap(),
+                    None => self.conn = self.caller.handle(&pack).unwrap(),
+                }
+            }
+
+            let send = |pack: Packet| {
+                self.transport.send_to(&pack.to_raw(), self.server).unwrap();
+            };
+            match &self.conn {
+                Some(conn) => {
+                    conn.update().unwrap();
+                    std::iter::from_fn(|| conn.poll_transmit()).for_each(send);
+                    conn.deliver(|message| self.received.push(message.to_vec()));
+                }
+                None => self.caller.poll_transmit().into_iter().for_each(send),
+            }
+        }
+    }
+
+    #[test]
+    fn test_request_mode() {
+        const COUNT: usize = 200;
+
+        let clock = ManualClock::new();
+        let network = MemoryNetwork::default();
+        // Subscribers (and the publisher's ACKs) lose 5%
+        let link = Arc::new(Impaired::with_clock(
+            network.bind_any().unwrap(),
+            Impairment {
+                loss: Loss::Random(0.05),
+                seed: 3,
+                ..Default::default()
+            },
+            clock.clone(),
+        ));
+        let mut server = Server::with_transport(Arc::clone(&link));
+        server.clock(clock.clone());
+        let (server, published) = run(server);
+
+        let request = "#!::r=live/cam1,m=request";
+        let mut players = [
+            Peer::new(&network, server, request, &clock),
+            Peer::new(&network, server, request, &clock),
+        ];
+        let mut other = Peer::new(&network, server, "#!::r=live/cam2,m=request", &clock);
+        let mut publisher = Peer::new(&network, server, "live/cam1", &clock);
+
+        // Players may subscribe after the first messages
+        let mut k: u32 = 0;
+        while players.iter().any(|player| player.received.len() < COUNT) {
+            assert!(k < 10_000, "Stalled");
+            clock.advance(Duration::from_millis(1));
+            link.release();
+
+            if let Some(conn) = &publisher.conn {
+                conn.send_message(&k.to_be_bytes()).unwrap();
+                k += 1;
+            }
+            publisher.step();
+            for player in &mut players {
+                player.step();
+            }
+            other.step();
+        }
+
+        for player in &players {
+            let first = u32::from_be_bytes(player.received[0].clone().try_into().unwrap());
+            let expected: Vec<_> = (first..)
+                .take(COUNT)
+                .map(|k| k.to_be_bytes().to_vec())
+                .collect();
+            assert_eq!(player.received[..COUNT], expected);
+        }
+        assert!(other.conn.is_some() && other.received.is_empty());
+        // Still passed to the handler
+        assert!(published.lock().unwrap().len() >= COUNT);
+    }
+}
@@ -0,0 +1,44 @@
+//! UDP socket setup
+
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Bind a UDP socket
+///
+/// IPv6 sockets are dual-stack (IPv4 peers show up as `::ffff:a.b.c.d`),
+/// regardless of the system default.
+pub fn bind(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    socket.bind(&addr.into())?;
+
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dual_stack() {
+        let Ok(a) = bind("[::]:0".parse().unwrap()) else {
+            // No IPv6 in this environment
+            return;
+        };
+        let b = bind("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let port = a.local_addr().unwrap().port();
+        b.send_to(b"hello", ("127.0.0.1", port)).unwrap();
+
+        let mut buf = [0; 8];
+        let (n, from) = a.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        assert_eq!(from.ip().to_canonical(), b.local_addr().unwrap().ip());
+    }
+}
@@ -0,0 +1,129 @@
+//! Request (pull) mode
+//!
+//! Players call the listener with `m=request` in an access control stream ID
+//! (`#!::r=live/cam1,m=request`) to receive a stream instead of sending one.
+//! Messages of publishers are sent to every subscriber of the same resource,
+//! each over its own connection: packets are kept until the subscriber acknowledges them,
+//! sent again when it reports them lost, and dropped once they'd arrive too late to be played
+//! (the subscriber plays them with TSBPD).
+//!
+//! The resource is `r` of an access control stream ID, or the whole stream ID otherwise,
+//! so `live/cam1` publishes to the subscribers above.
+//! Callers without a stream ID, and `#!::m=request` without `r`, share the default resource (`""`).
+//!
+//! <https://github.com/Haivision/srt/blob/master/docs/features/access-control.md>
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{access, connection::Connection};
+
+/// `m` of an access control stream ID
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Caller sends the stream (also without `m`, unlike libsrt, which defaults to request)
+    #[default]
+    Publish,
+    /// Caller receives the stream
+    Request,
+}
+
+impl Mode {
+    pub fn from_stream_id(stream_id: Option<&str>) -> Self {
+        let request = stream_id
+            .and_then(|id| id.strip_prefix("#!::"))
+            .is_some_and(|pairs| pairs.split(',').any(|pair| pair == "m=request"));
+
+        match request {
+            true => Self::Request,
+            false => Self::Publish,
+        }
+    }
+}
+
+/// Resource a stream ID publishes to or requests
+pub fn resource(stream_id: Option<&str>) -> &str {
+    match stream_id {
+        Some(id) if id.starts_with("#!::") => access::resource(id).unwrap_or_default(),
+        Some(id) => id,
+        None => "",
+    }
+}
+
+/// Subscribers of all resources of a listener (shared between workers)
+#[derive(Default)]
+pub struct Subscriptions {
+    subscribers: Mutex<HashMap<String, Vec<Arc<Connection>>>>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&self, conn: Arc<Connection>) {
+        let resource = conn.resource().to_owned();
+        tracing::debug!("{} subscribed to {resource:?}", conn.addr);
+
+        self.subscribers
+            .lock()
+            .unwrap()
+            .entry(resource)
+            .or_default()
+            .push(conn);
+    }
+
+    pub fn unsubscribe(&self, conn: &Connection) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+
+        if let Some(conns) = subscribers.get_mut(conn.resource()) {
+            conns.retain(|sub| sub.addr != conn.addr);
+            if conns.is_empty() {
+                subscribers.remove(conn.resource());
+            }
+        }
+    }
+
+    /// # of subscribers of `resource`
+    pub fn count(&self, resource: &str) -> usize {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .get(resource)
+            .map_or(0, Vec::len)
+    }
+
+    /// Queue `message` for every subscriber of `resource`
+    ///
+    /// Packets go out with the next batch of each subscriber's worker.
+    pub fn publish(&self, resource: &str, message: &[u8]) {
+        let subscribers = self.subscribers.lock().unwrap();
+
+        for conn in subscribers.get(resource).into_iter().flatten() {
+            if let Err(e) = conn.send_message(message) {
+                tracing::warn!("Failed to send to subscriber {}: {e}", conn.addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_id() {
+        let request = Some("#!::r=live/cam1,m=request");
+        assert_eq!(Mode::from_stream_id(request), Mode::Request);
+        assert_eq!(resource(request), "live/cam1");
+
+        assert_eq!(Mode::from_stream_id(Some("live/cam1")), Mode::Publish);
+        assert_eq!(resource(Some("live/cam1")), "live/cam1");
+        assert_eq!(
+            Mode::from_stream_id(Some("#!::m=publish,r=live/cam1")),
+            Mode::Publish
+        );
+
+        assert_eq!(Mode::from_stream_id(Some("#!::m=request")), Mode::Request);
+        assert_eq!(resource(Some("#!::m=request")), resource(None));
+        assert_eq!(Mode::from_stream_id(None), Mode::Publish);
+    }
+}
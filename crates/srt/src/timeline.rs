@@ -0,0 +1,248 @@
+//! Clock of the peer
+//!
+//! Packet timestamps are microseconds since the peer's connection start, in 32 bits,
+//! so they wrap around every ~71.6 minutes. [`PeerClock`] maps them to local time,
+//! which is what TSBPD is based on, and follows the peer's clock drift.
+//!
+//! <https://datatracker.ietf.org/doc/html/draft-sharabayko-srt#section-4.5>
+
+use std::time::{Duration, Instant};
+
+use crate::constants::{DRIFT_MAX, DRIFT_MAX_SPAN};
+
+/// Period of the 32-bit timestamp (micros)
+pub const WRAP_PERIOD: u64 = 1 << 32;
+
+/// Extends 32-bit timestamps to 64 bits
+///
+/// Each timestamp is placed nearest to the latest one, so packets reordered
+/// around a wraparound land on the correct side of it.
+#[derive(Debug, Default)]
+pub struct Timeline {
+    /// Latest extended timestamp
+    last: Option<u64>,
+    wraps: u32,
+}
+
+impl Timeline {
+    pub fn extend(&mut self, timestamp: u32) -> u64 {
+        let Some(last) = self.last else {
+            self.last = Some(timestamp.into());
+            return timestamp.into();
+        };
+
+        #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
+        let diff = timestamp.wrapping_sub(last as u32) as i32;
+        let extended = last.saturating_add_signed(diff.into());
+
+        if extended > last {
+            if extended / WRAP_PERIOD > last / WRAP_PERIOD {
+                self.wraps += 1;
+            }
+            self.last = Some(extended);
+        }
+
+        extended
+    }
+
+    /// # of wraparounds so far
+    pub fn wraps(&self) -> u32 {
+        self.wraps
+    }
+}
+
+/// Averages drift samples (same as libsrt's `DriftTracer`)
+///
+/// Once [`DRIFT_MAX_SPAN`] samples are in, the average becomes the drift.
+/// Anything beyond [`DRIFT_MAX`] is returned as overdrift, to be moved into the TSBPD base.
+#[derive(Debug, Default)]
+pub struct DriftTracer {
+    sum: i64,
+    span: u32,
+    /// (micros)
+    drift: i64,
+}
+
+impl DriftTracer {
+    /// Add a sample (micros)
+    ///
+    /// Returns the overdrift, once the span is complete.
+    pub fn update(&mut self, sample: i64) -> Option<i64> {
+        self.sum += sample;
+        self.span += 1;
+
+        if self.span < DRIFT_MAX_SPAN {
+            return None;
+        }
+
+        self.drift = self.sum / i64::from(self.span);
+        self.sum = 0;
+        self.span = 0;
+
+        let overdrift = self.drift - self.drift.clamp(-DRIFT_MAX, DRIFT_MAX);
+        self.drift -= overdrift;
+
+        Some(overdrift)
+    }
+
+    /// (micros)
+    pub fn drift(&self) -> i64 {
+        self.drift
+    }
+}
+
+/// Maps peer timestamps to local time
+#[derive(Debug)]
+pub struct PeerClock {
+    timeline: Timeline,
+    drift: DriftTracer,
+    /// Local time of peer timestamp 0 (the TSBPD base)
+    base: Instant,
+    /// Sum of corrections applied to `base` (micros)
+    correction: i64,
+    /// First RTT sample, changes of RTT aren't drift
+    first_rtt: Option<Duration>,
+}
+
+impl PeerClock {
+    /// `timestamp` was received at `now` (usually the handshake)
+    pub fn new(timestamp: u32, now: Instant) -> Self {
+        let mut timeline = Timeline::default();
+        timeline.extend(timestamp);
+
+        Self {
+            timeline,
+            drift: DriftTracer::default(),
+            base: now
+                .checked_sub(Duration::from_micros(timestamp.into()))
+                .unwrap_or(now),
+            correction: 0,
+            first_rtt: None,
+        }
+    }
+
+    /// Track wraparounds with an inbound timestamp
+    pub fn observe(&mut self, timestamp: u32) {
+        self.timeline.extend(timestamp);
+    }
+
+    /// Local time, at which the peer sent `timestamp` (one-way delay not included)
+    pub fn time_of(&mut self, timestamp: u32) -> Instant {
+        let extended = self.timeline.extend(timestamp);
+        shift(
+            self.base + Duration::from_micros(extended),
+            self.drift.drift(),
+        )
+    }
+
+    /// When a packet with `timestamp` is due for delivery
+    pub fn tsbpd_time(&mut self, timestamp: u32, latency: Duration) -> Instant {
+        self.time_of(timestamp) + latency
+    }
+
+    /// Sample the drift from a packet, that is answered right away (ACKACK)
+    ///
+    /// `rtt` is the round trip measured with it.
+    pub fn sample_drift(&mut self, timestamp: u32, arrival: Instant, rtt: Duration) {
+        let extended = self.timeline.extend(timestamp);
+        let sent = self.base + Duration::from_micros(extended);
+
+        let first_rtt = *self.first_rtt.get_or_insert(rtt);
+        let sample = micros_between(sent, arrival) - micros_between(first_rtt, rtt) / 2;
+
+        if let Some(overdrift) = self.drift.update(sample)
+            && overdrift != 0
+        {
+            tracing::debug!(
+                "Clock drift: {}us, moving TSBPD base by {overdrift}us",
+                self.drift.drift()
+            );
+            self.base = shift(self.base, overdrift);
+            self.correction += overdrift;
+        }
+    }
+
+    pub fn wraps(&self) -> u32 {
+        self.timeline.wraps()
+    }
+
+    /// Current drift (micros)
+    pub fn drift(&self) -> i64 {
+        self.drift.drift()
+    }
+
+    /// Sum of TSBPD base corrections (micros)
+    pub fn correction(&self) -> i64 {
+        self.correction
+    }
+}
+
+/// `to - from` (micros)
+#[allow(clippy::cast_possible_truncation)]
+fn micros_between<T: Ord + std::ops::Sub<Output = Duration>>(from: T, to: T) -> i64 {
+    if to >= from {
+        (to - from).as_micros() as i64
+    } else {
+        -((from - to).as_micros() as i64)
+    }
+}
+
+fn shift(time: Instant, micros: i64) -> Instant {
+    let delta = Duration::from_micros(micros.unsigned_abs());
+    match micros >= 0 {
+        true => time + delta,
+        false => time.checked_sub(delta).unwrap_or(time),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wraparound() {
+        let mut timeline = Timeline::default();
+
+        assert_eq!(timeline.extend(u32::MAX - 10), u64::from(u32::MAX - 10));
+        assert_eq!(timeline.extend(5), WRAP_PERIOD + 5);
+        // Reordered from before the wrap
+        assert_eq!(timeline.extend(u32::MAX), u64::from(u32::MAX));
+        assert_eq!(timeline.extend(6), WRAP_PERIOD + 6);
+        assert_eq!(timeline.wraps(), 1);
+    }
+
+    #[test]
+    fn test_six_hours() {
+        let mut timeline = Timeline::default();
+
+        let step = 100_000; // 100 ms
+        let mut expected = 0u64;
+        while expected < 6 * 3600 * 1_000_000 {
+            #[allow(clippy::cast_possible_truncation)]
+            let extended = timeline.extend(expected as u32);
+            assert_eq!(extended, expected);
+            expected += step;
+        }
+
+        assert_eq!(timeline.wraps(), 5);
+    }
+
+    #[test]
+    fn test_drift_correction() {
+        let start = Instant::now();
+        let mut clock = PeerClock::new(0, start);
+        let rtt = Duration::from_millis(20);
+
+        // Peer clock runs 10 ms behind after the handshake
+        for i in 1..=DRIFT_MAX_SPAN {
+            let timestamp = i * 1000;
+            let arrival =
+                start + Duration::from_micros(timestamp.into()) + Duration::from_millis(10);
+            clock.sample_drift(timestamp, arrival, rtt);
+        }
+
+        assert_eq!(clock.drift(), DRIFT_MAX);
+        assert_eq!(clock.correction(), 10_000 - DRIFT_MAX);
+        assert_eq!(clock.time_of(0), start + Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,391 @@
+//! Async (tokio) listener
+//!
+//! Same as [`crate::listener`], but the socket and connection timers are driven
+//! by a task on the current runtime.
+//!
+//! Only received messages are exposed. Use [`crate::server::Server`] for the rest:
+//! application-defined control messages (`UMSG_EXT`) are dropped,
+//! request-mode callers (see [`crate::subscription`]) are accepted as publishers,
+//! and nothing is sent to callers.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    net::{ToSocketAddrs, UdpSocket},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time,
+};
+
+use crate::{
+    admission::Admission,
+    clock::{Clock, SystemClock},
+    connection::{Connection, StreamKey},
+    constants::{FULL_ACK_INTERVAL, MAX_PACKET_SIZE},
+    group::Groups,
+    inbox::{self, Inbox, InboxTx},
+    options::ListenerOptions,
+    packet::{Packet, control::peer_error::error_codes},
+    socket,
+};
+
+/// Listens for SRT callers on a spawned task
+pub struct Listener {
+    local_addr: SocketAddr,
+    incoming: UnboundedReceiver<Stream>,
+}
+
+impl Listener {
+    /// Must be called within a tokio runtime
+    pub async fn bind<A>(addr: A) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        Self::bind_with_options(addr, ListenerOptions::default()).await
+    }
+
+    /// Same as [`Listener::bind`], with options (defaults and overrides by stream ID)
+    pub async fn bind_with_options<A>(addr: A, options: ListenerOptions) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        options.validate()?;
+
+        let addr = tokio::net::lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| anyhow!("No address to bind to"))?;
+        let socket = socket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(socket)?;
+        let local_addr = socket.local_addr()?;
+
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+
+        let driver = Driver {
+            socket,
+            connections: HashMap::new(),
+            streams: HashMap::new(),
+            groups: Groups::default(),
+            admission: Admission::new(&options),
+            options,
+            clock: Arc::new(SystemClock),
+            incoming: incoming_tx,
+        };
+
+        tokio::spawn(driver.run());
+
+        Ok(Self {
+            local_addr,
+            incoming,
+        })
+    }
+
+    /// Wait for the next established connection
+    pub async fn accept(&mut self) -> Result<Stream> {
+        self.incoming
+            .recv()
+            .await
+            .ok_or_else(|| anyhow!("Listener task exited"))
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl futures_core::Stream for Listener {
+    type Item = Stream;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.incoming.poll_recv(cx)
+    }
+}
+
+/// Owns the socket and all connections of a [`Listener`]
+struct Driver {
+    socket: UdpSocket,
+    connections: HashMap<SocketAddr, Connection>,
+    streams: HashMap<StreamKey, InboxTx>,
+    groups: Groups,
+    admission: Admission,
+    options: ListenerOptions,
+    clock: Arc<dyn Clock>,
+    incoming: UnboundedSender<Stream>,
+}
+
+impl Driver {
+    /// Errors only close the connection they belong to (or drop the datagram)
+    async fn run(mut self) {
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let mut timer = time::interval(Duration::from_micros(FULL_ACK_INTERVAL.into()));
+        timer.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                res = self.socket.recv_from(&mut buf) => match res {
+                    Ok((n, addr)) => match Packet::from_raw(&buf[..n]) {
+                        Ok(pack) => self.dispatch(addr, &pack).await,
+                        Err(e) => tracing::debug!("Bad packet from {addr}: {e}"),
+                    },
+                    // e.g. ICMP errors of earlier datagrams
+                    Err(e) => tracing::debug!("Failed to receive: {e}"),
+                },
+                _ = timer.tick() => {
+                    for conn in self.connections.values() {
+                        if let Err(e) = conn.update() {
+                            tracing::warn!("Closing {}: {e}", conn.addr);
+                            conn.abort();
+                        }
+                        self.flush(conn).await;
+                    }
+                    self.expire_idle();
+                }
+            }
+
+            self.remove_closed();
+        }
+    }
+
+    async fn send(&self, data: &[u8], addr: SocketAddr) {
+        if let Err(e) = self.socket.send_to(data, addr).await {
+            tracing::debug!("Failed to send to {addr}: {e}");
+        }
+    }
+
+    /// Deliver received messages and send queued packets
+    async fn flush(&self, conn: &Connection) {
+        let stream = self.streams.get(&conn.stream_key());
+        let mut closed = false;
+        conn.deliver(|message| {
+            let Some(stream) = stream else {
+                return;
+            };
+            // Handle was dropped by the application
+            if stream.send(message).is_err() {
+                closed = true;
+            }
+        });
+
+        if closed {
+            _ = conn.fail(error_codes::WRITE);
+        }
+
+        // Application-defined control messages aren't exposed here
+        while conn.poll_extended().is_some() {}
+
+        while let Some(pack) = conn.poll_transmit() {
+            self.send(&pack.to_raw(), conn.addr).await;
+        }
+    }
+
+    /// Drop connections, that stopped responding
+    fn expire_idle(&mut self) {
+        let idle: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|(_, conn)| conn.is_idle())
+            .map(|(&addr, _)| addr)
+            .collect();
+
+        for addr in idle {
+            if let Some(conn) = self.connections.remove(&addr) {
+                tracing::warn!("Connection timed out: {addr}");
+                self.close(&conn);
+            }
+        }
+    }
+
+    /// Drop connections, that were ended by either side
+    fn remove_closed(&mut self) {
+        let closed: Vec<_> = self
+            .connections
+            .iter()
+            .filter_map(|(&addr, conn)| Some((addr, conn.disconnect_reason()?)))
+            .collect();
+
+        for (addr, reason) in closed {
+            if let Some(conn) = self.connections.remove(&addr) {
+                tracing::info!("Connection closed: {addr} ({reason:?})");
+                self.close(&conn);
+            }
+        }
+    }
+
+    fn close(&mut self, conn: &Connection) {
+        self.admission.release();
+        if conn.close(&self.groups) {
+            self.streams.remove(&conn.stream_key());
+        }
+    }
+
+    async fn dispatch(&mut self, addr: SocketAddr, pack: &Packet) {
+        // Closed connections are removed after flushing what's left (see `remove_closed`)
+        if let Some(conn) = self.connections.get(&addr) {
+            if let Err(e) = conn.handle(pack) {
+                tracing::warn!("Closing {addr}: {e}");
+                conn.abort();
+            }
+            self.flush(conn).await;
+        } else {
+            let Some(admitted) = self.admission.admit(pack, addr, &*self.clock) else {
+                return;
+            };
+            let accepted = Connection::accept(
+                pack,
+                addr,
+                admitted.cookie,
+                &self.groups,
+                &self.options,
+                &self.clock,
+            );
+            let conn = match accepted {
+                Ok((response, conn)) => {
+                    self.send(&response.to_raw(), addr).await;
+                    conn
+                }
+                Err(_) => None,
+            };
+
+            if conn.is_none() && admitted.reserved {
+                self.admission.release();
+            }
+            if let Some(conn) = conn {
+                if let Ok(local) = self.socket.local_addr()
+                    && conn.is_nat_mismatch(local.ip())
+                {
+                    tracing::info!("NAT: {addr} reached {}, bound to {local}", conn.reported_ip);
+                }
+
+                if conn.is_new_stream() {
+                    let (tx, inbox) = inbox::channel();
+                    if self.incoming.send(Stream::new(&conn, inbox)).is_ok() {
+                        self.streams.insert(conn.stream_key(), tx);
+                    }
+                }
+                self.connections.insert(addr, conn);
+            }
+        }
+    }
+}
+
+/// Owned handle of an established connection
+///
+/// Yields messages in sequence order. Reading ends once the peer disconnects.
+/// Up to [`STREAM_QUEUE_SIZE`](crate::constants::STREAM_QUEUE_SIZE) messages wait to be read,
+/// later ones are dropped.
+pub struct Stream {
+    pub stream_id: Option<String>,
+    pub addr: SocketAddr,
+    pub peer_srt_socket_id: u32,
+
+    inbox: Inbox,
+}
+
+impl Stream {
+    fn new(conn: &Connection, inbox: Inbox) -> Self {
+        Self {
+            stream_id: conn.stream_id.clone(),
+            addr: conn.addr,
+            peer_srt_socket_id: conn.peer_srt_socket_id,
+            inbox,
+        }
+    }
+
+    /// Wait for the next message
+    ///
+    /// Returns `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        std::future::poll_fn(|cx| self.inbox.poll_recv(cx)).await
+    }
+
+    /// # of messages dropped, because they weren't read in time
+    pub fn dropped(&self) -> u64 {
+        self.inbox.dropped()
+    }
+}
+
+impl futures_core::Stream for Stream {
+    type Item = Vec<u8>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inbox.poll_recv(cx)
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n = ready!(self.inbox.poll_read(cx, buf.initialize_unfilled()));
+        buf.advance(n);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{connection::caller::Caller, options::Options};
+
+    #[tokio::test]
+    async fn test_loopback() {
+        let mut listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut caller = Caller::new(
+            server,
+            Some("live/cam1".to_owned()),
+            Options::default(),
+            Arc::new(SystemClock),
+        )
+        .unwrap();
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let conn = loop {
+            if let Some(pack) = caller.poll_transmit() {
+                socket.send_to(&pack.to_raw(), server).await.unwrap();
+            }
+            if let Ok(Ok((n, _))) =
+                time::timeout(Duration::from_millis(10), socket.recv_from(&mut buf)).await
+                && let Some(conn) = caller
+                    .handle(&Packet::from_raw(&buf[..n]).unwrap())
+                    .unwrap()
+            {
+                break conn;
+            }
+        };
+
+        let mut stream = listener.accept().await.unwrap();
+        assert_eq!(stream.stream_id.as_deref(), Some("live/cam1"));
+        assert_eq!(stream.addr, socket.local_addr().unwrap());
+
+        for message in [&b"hello"[..], b"SRT"] {
+            conn.send_message(message).unwrap();
+        }
+        while let Some(pack) = conn.poll_transmit() {
+            socket.send_to(&pack.to_raw(), server).await.unwrap();
+        }
+
+        assert_eq!(stream.recv().await.unwrap(), b"hello");
+        let mut read = [0; 8];
+        let mut read = ReadBuf::new(&mut read);
+        std::future::poll_fn(|cx| Pin::new(&mut stream).poll_read(cx, &mut read))
+            .await
+            .unwrap();
+        assert_eq!(read.filled(), b"SRT");
+        assert_eq!(stream.dropped(), 0);
+    }
+}
@@ -0,0 +1,257 @@
+//! Datagram carriers for [`crate::server::Server`]
+//!
+//! [`UdpSocket`] is the default. [`MemoryNetwork`] connects endpoints in the same process,
+//! so whole sessions can run in tests without ports, and [`impair::Impaired`] degrades them.
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    time::Duration,
+};
+
+use crate::batch::{self, Datagram, RecvBatch};
+
+pub mod impair;
+
+/// Unreliable datagram I/O (same semantics as [`UdpSocket`])
+pub trait Transport: Send + Sync {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    /// Fails with [`ErrorKind::WouldBlock`] or [`ErrorKind::TimedOut`] once the read timeout passes
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// `None` blocks forever
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Receive at least one datagram (one by default)
+    fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<usize> {
+        batch.recv_with(|buf| self.recv_from(buf))
+    }
+
+    /// Send all datagrams (one by one by default)
+    ///
+    /// A datagram, that fails, doesn't stop the rest, the first error is returned.
+    fn send_batch(&self, datagrams: &[Datagram]) -> io::Result<()> {
+        let mut res = Ok(());
+        for (data, addr) in datagrams {
+            if let Err(e) = self.send_to(data, *addr) {
+                res = res.and(Err(e));
+            }
+        }
+
+        res
+    }
+}
+
+/// Shared, e.g. to keep a handle on an [`impair::Impaired`] transport, that a server owns
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        (**self).send_to(data, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        (**self).recv_from(buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        (**self).local_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<usize> {
+        (**self).recv_batch(batch)
+    }
+
+    fn send_batch(&self, datagrams: &[Datagram]) -> io::Result<()> {
+        (**self).send_batch(datagrams)
+    }
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, data, addr)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_read_timeout(self, timeout)
+    }
+
+    fn recv_batch(&self, batch: &mut RecvBatch) -> io::Result<usize> {
+        batch::recv_batch(self, batch)
+    }
+
+    fn send_batch(&self, datagrams: &[Datagram]) -> io::Result<()> {
+        batch::send_batch(self, datagrams)
+    }
+}
+
+/// Datagrams are delivered to the endpoint bound to the destination
+/// (or dropped, if there is none), instantly and in order.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    endpoints: Arc<Mutex<Endpoints>>,
+}
+
+#[derive(Default)]
+struct Endpoints {
+    inbound: HashMap<SocketAddr, Sender<Datagram>>,
+    /// Last port handed out for port 0
+    last_port: u16,
+}
+
+impl MemoryNetwork {
+    /// Port 0 picks a free port
+    pub fn bind(&self, mut addr: SocketAddr) -> io::Result<MemoryTransport> {
+        let mut endpoints = self.endpoints.lock().unwrap();
+
+        if addr.port() == 0 {
+            let port = (1..=u16::MAX)
+                .map(|k| endpoints.last_port.wrapping_add(k).max(1024))
+                .find(|&port| {
+                    !endpoints
+                        .inbound
+                        .contains_key(&SocketAddr::new(addr.ip(), port))
+                })
+                .ok_or_else(|| io::Error::from(ErrorKind::AddrNotAvailable))?;
+            endpoints.last_port = port;
+            addr.set_port(port);
+        }
+
+        if endpoints.inbound.contains_key(&addr) {
+            return Err(ErrorKind::AddrInUse.into());
+        }
+
+        let (tx, rx) = mpsc::channel();
+        endpoints.inbound.insert(addr, tx);
+
+        Ok(MemoryTransport {
+            addr,
+            network: self.clone(),
+            inbound: Mutex::new(rx),
+            read_timeout: Mutex::new(None),
+        })
+    }
+
+    /// Bind `127.0.0.1` on a free port
+    pub fn bind_any(&self) -> io::Result<MemoryTransport> {
+        self.bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))
+    }
+
+    fn deliver(&self, data: &[u8], from: SocketAddr, to: SocketAddr) {
+        let endpoints = self.endpoints.lock().unwrap();
+
+        if let Some(tx) = endpoints.inbound.get(&to) {
+            _ = tx.send((data.to_vec(), from));
+        }
+    }
+}
+
+/// Endpoint of a [`MemoryNetwork`] (unbound on drop)
+pub struct MemoryTransport {
+    addr: SocketAddr,
+    network: MemoryNetwork,
+    inbound: Mutex<Receiver<Datagram>>,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Transport for MemoryTransport {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.network.deliver(data, self.addr, addr);
+
+        Ok(data.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let timeout = *self.read_timeout.lock().unwrap();
+        let inbound = self.inbound.lock().unwrap();
+
+        let (data, from) = match timeout {
+            Some(timeout) => inbound.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => io::Error::from(ErrorKind::WouldBlock),
+                RecvTimeoutError::Disconnected => io::Error::from(ErrorKind::NotConnected),
+            })?,
+            None => inbound
+                .recv()
+                .map_err(|_| io::Error::from(ErrorKind::NotConnected))?,
+        };
+
+        // Truncated like UDP
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+
+        Ok((n, from))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        *self.read_timeout.lock().unwrap() = timeout;
+
+        Ok(())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network
+            .endpoints
+            .lock()
+            .unwrap()
+            .inbound
+            .remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_network() {
+        let network = MemoryNetwork::default();
+        let a = network.bind_any().unwrap();
+        let b = network.bind("10.0.0.2:9000".parse().unwrap()).unwrap();
+        assert!(network.bind(b.local_addr().unwrap()).is_err());
+
+        a.send_to(b"hello", b.local_addr().unwrap()).unwrap();
+        // Nobody there
+        a.send_to(b"lost", "10.0.0.3:9000".parse().unwrap())
+            .unwrap();
+
+        let mut buf = [0; 16];
+        let (n, from) = b.recv_from(&mut buf).unwrap();
+        assert_eq!((&buf[..n], from), (&b"hello"[..], a.local_addr().unwrap()));
+
+        b.set_read_timeout(Some(Duration::from_millis(1))).unwrap();
+        let err = b.recv_from(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+        // Unbound on drop
+        let addr = b.local_addr().unwrap();
+        drop(b);
+        network.bind(addr).unwrap();
+    }
+}
@@ -0,0 +1,422 @@
+//! Network impairment for tests
+//!
+//! [`Impaired`] wraps the sending side of a [`Transport`] and applies loss, delay, jitter,
+//! reordering, duplication and a bandwidth cap. Every decision comes from a seeded RNG,
+//! so a run with the same seed and the same traffic drops the same packets.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use super::Transport;
+use crate::clock::{Clock, ManualClock, SystemClock};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Loss {
+    #[default]
+    None,
+    /// Each packet is lost with this probability
+    Random(f64),
+    /// Bursty loss: a two-state Markov chain with a loss probability per state
+    GilbertElliott {
+        /// Good -> Bad (per packet)
+        p: f64,
+        /// Bad -> Good (per packet)
+        r: f64,
+        good_loss: f64,
+        bad_loss: f64,
+    },
+}
+
+/// Applied to outbound datagrams (impair both ends for a symmetric link)
+#[derive(Clone, Debug, Default)]
+pub struct Impairment {
+    pub loss: Loss,
+    /// One-way
+    pub delay: Duration,
+    /// Delay varies uniformly by up to this much either way (without reordering)
+    pub jitter: Duration,
+    /// Probability, that a packet skips the delay and overtakes packets in flight
+    pub reorder: f64,
+    /// Probability, that a packet is sent twice
+    pub duplicate: f64,
+    /// (bits/s)
+    pub bandwidth: Option<u64>,
+    /// Longest queue behind the bandwidth cap, packets beyond it are dropped (unbounded if `None`)
+    pub queue_limit: Option<Duration>,
+    pub seed: u64,
+}
+
+/// [`Transport`] with impaired sending
+///
+/// Datagrams are handed to the inner transport by a background thread once they are due
+/// (or by [`Impaired::release`] on a [`ManualClock`]).
+/// Those still in flight are discarded on drop.
+pub struct Impaired<T: Transport + 'static> {
+    inner: Arc<T>,
+    impairment: Impairment,
+    clock: Arc<dyn Clock>,
+    shaper: Mutex<Shaper>,
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+    dropped: AtomicU64,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shaper {
+    rng: fastrand::Rng,
+    /// Gilbert-Elliott state
+    bad: bool,
+    /// When the capped link finishes sending its backlog
+    link_free: Instant,
+    /// Latest due time so far (jitter alone doesn't reorder)
+    last_due: Instant,
+    /// Tie-breaker, so packets due at the same time keep their order
+    order: u64,
+}
+
+#[derive(Default)]
+struct Queue {
+    scheduled: BinaryHeap<Reverse<Scheduled>>,
+    closed: bool,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Scheduled {
+    due: Instant,
+    order: u64,
+    data: Vec<u8>,
+    addr: SocketAddr,
+}
+
+impl<T: Transport + 'static> Impaired<T> {
+    pub fn new(inner: T, impairment: Impairment) -> Self {
+        let inner = Arc::new(inner);
+        let queue = Arc::new((Mutex::new(Queue::default()), Condvar::new()));
+
+        let thread = {
+            let inner = Arc::clone(&inner);
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || Self::run(&*inner, &queue))
+        };
+
+        Self::build(
+            inner,
+            impairment,
+            Arc::new(SystemClock),
+            queue,
+            Some(thread),
+        )
+    }
+
+    /// Delays follow `clock` instead, there's no background thread
+    ///
+    /// Call [`Impaired::release`] after advancing the clock to send what is due.
+    pub fn with_clock(inner: T, impairment: Impairment, clock: ManualClock) -> Self {
+        let queue = Arc::new((Mutex::new(Queue::default()), Condvar::new()));
+        Self::build(Arc::new(inner), impairment, Arc::new(clock), queue, None)
+    }
+
+    fn build(
+        inner: Arc<T>,
+        impairment: Impairment,
+        clock: Arc<dyn Clock>,
+        queue: Arc<(Mutex<Queue>, Condvar)>,
+        thread: Option<JoinHandle<()>>,
+    ) -> Self {
+        let now = clock.now();
+
+        Self {
+            inner,
+            clock,
+            shaper: Mutex::new(Shaper {
+                rng: fastrand::Rng::with_seed(impairment.seed),
+                bad: false,
+                link_free: now,
+                last_due: now,
+                order: 0,
+            }),
+            impairment,
+            queue,
+            dropped: AtomicU64::new(0),
+            thread,
+        }
+    }
+
+    /// Send the datagrams, that are due (see [`Impaired::with_clock`])
+    pub fn release(&self) {
+        let now = self.clock.now();
+
+        loop {
+            let next = {
+                let mut queue = self.queue.0.lock().unwrap();
+                match queue.scheduled.peek() {
+                    Some(Reverse(next)) if next.due <= now => queue.scheduled.pop(),
+                    _ => None,
+                }
+            };
+            let Some(Reverse(next)) = next else {
+                return;
+            };
+
+            if let Err(e) = self.inner.send_to(&next.data, next.addr) {
+                tracing::debug!("Impaired send to {} failed: {e}", next.addr);
+            }
+        }
+    }
+
+    /// # of datagrams lost on purpose (including tail drops behind the bandwidth cap)
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Send datagrams, as they become due
+    fn run(inner: &T, queue: &(Mutex<Queue>, Condvar)) {
+        let (lock, ready) = queue;
+        let mut queue = lock.lock().unwrap();
+
+        while !queue.closed {
+            let now = Instant::now();
+
+            match queue.scheduled.peek() {
+                None => queue = ready.wait(queue).unwrap(),
+                Some(Reverse(next)) if next.due > now => {
+                    let timeout = next.due - now;
+                    queue = ready.wait_timeout(queue, timeout).unwrap().0;
+                }
+                Some(_) => {
+                    let Some(Reverse(next)) = queue.scheduled.pop() else {
+                        unreachable!();
+                    };
+                    drop(queue);
+                    if let Err(e) = inner.send_to(&next.data, next.addr) {
+                        tracing::debug!("Impaired send to {} failed: {e}", next.addr);
+                    }
+                    queue = lock.lock().unwrap();
+                }
+            }
+        }
+    }
+
+    fn is_lost(&self, shaper: &mut Shaper) -> bool {
+        match self.impairment.loss {
+            Loss::None => false,
+            Loss::Random(probability) => shaper.rng.f64() < probability,
+            Loss::GilbertElliott {
+                p,
+                r,
+                good_loss,
+                bad_loss,
+            } => {
+                let switch = if shaper.bad { r } else { p };
+                if shaper.rng.f64() < switch {
+                    shaper.bad = !shaper.bad;
+                }
+
+                let loss = if shaper.bad { bad_loss } else { good_loss };
+                shaper.rng.f64() < loss
+            }
+        }
+    }
+
+    /// When the datagram arrives, `None` if it's dropped behind the bandwidth cap
+    fn schedule(&self, shaper: &mut Shaper, len: usize) -> Option<Instant> {
+        let now = self.clock.now();
+
+        let departure = match self.impairment.bandwidth {
+            Some(bandwidth) => {
+                let start = shaper.link_free.max(now);
+                if let Some(limit) = self.impairment.queue_limit
+                    && start - now > limit
+                {
+                    return None;
+                }
+
+                #[allow(clippy::cast_precision_loss)]
+                let transmission = (len * 8) as f64 / bandwidth as f64;
+                shaper.link_free = start + Duration::from_secs_f64(transmission);
+                shaper.link_free
+            }
+            None => now,
+        };
+
+        if shaper.rng.f64() < self.impairment.reorder {
+            return Some(departure);
+        }
+
+        let jitter = self.impairment.jitter.as_secs_f64() * (2.0 * shaper.rng.f64() - 1.0);
+        let delay =
+            Duration::from_secs_f64((self.impairment.delay.as_secs_f64() + jitter).max(0.0));
+        let due = (departure + delay).max(shaper.last_due);
+        shaper.last_due = due;
+
+        Some(due)
+    }
+}
+
+impl<T: Transport + 'static> Transport for Impaired<T> {
+    fn send_to(&self, data: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let mut shaper = self.shaper.lock().unwrap();
+
+        if self.is_lost(&mut shaper) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Ok(data.len());
+        }
+
+        let copies = if shaper.rng.f64() < self.impairment.duplicate {
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            let Some(due) = self.schedule(&mut shaper, data.len()) else {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            };
+            shaper.order += 1;
+
+            let (lock, ready) = &*self.queue;
+            lock.lock().unwrap().scheduled.push(Reverse(Scheduled {
+                due,
+                order: shaper.order,
+                data: data.to_vec(),
+                addr,
+            }));
+            ready.notify_one();
+        }
+
+        Ok(data.len())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.inner.recv_from(buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+}
+
+impl<T: Transport + 'static> Drop for Impaired<T> {
+    fn drop(&mut self) {
+        let (lock, ready) = &*self.queue;
+        lock.lock().unwrap().closed = true;
+        ready.notify_one();
+
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MemoryNetwork, MemoryTransport};
+
+    /// Send `count` numbered datagrams from `a` to `b` and collect what arrives
+    fn transfer(impairment: Impairment, count: u32) -> (Vec<u32>, u64) {
+        let network = MemoryNetwork::default();
+        let a = Impaired::new(network.bind_any().unwrap(), impairment);
+        let b: MemoryTransport = network.bind_any().unwrap();
+        b.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+
+        for k in 0..count {
+            a.send_to(&k.to_be_bytes(), b.local_addr().unwrap())
+                .unwrap();
+        }
+
+        let mut buf = [0; 4];
+        let mut received = Vec::new();
+        while b.recv_from(&mut buf).is_ok() {
+            received.push(u32::from_be_bytes(buf));
+        }
+
+        (received, a.dropped())
+    }
+
+    #[test]
+    fn test_loss() {
+        let random = Impairment {
+            loss: Loss::Random(0.05),
+            seed: 1,
+            ..Default::default()
+        };
+        let (received, dropped) = transfer(random.clone(), 10_000);
+        assert_eq!(received.len() as u64 + dropped, 10_000);
+        assert!((400..600).contains(&dropped), "{dropped}");
+        // Same seed, same losses
+        assert_eq!(transfer(random, 10_000).0, received);
+
+        let bursty = Impairment {
+            loss: Loss::GilbertElliott {
+                p: 0.01,
+                r: 0.2,
+                good_loss: 0.0,
+                bad_loss: 1.0,
+            },
+            seed: 1,
+            ..Default::default()
+        };
+        let (received, dropped) = transfer(bursty, 10_000);
+        // Stationary P(Bad) = p / (p + r) ~ 4.8%, in bursts of 1 / r = 5 packets on average
+        assert!((300..700).contains(&dropped), "{dropped}");
+        let gaps = received.windows(2).filter(|w| w[1] != w[0] + 1).count();
+        assert!(gaps * 2 < usize::try_from(dropped).unwrap(), "{gaps}");
+    }
+
+    #[test]
+    fn test_delay() {
+        let impairment = Impairment {
+            delay: Duration::from_millis(30),
+            jitter: Duration::from_millis(10),
+            reorder: 0.1,
+            duplicate: 0.1,
+            seed: 7,
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let (received, dropped) = transfer(impairment, 200);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(dropped, 0);
+
+        assert!(received.len() > 200);
+        let mut unique = received.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique, (0..200).collect::<Vec<_>>());
+        assert!(received.windows(2).any(|w| w[1] < w[0]));
+    }
+
+    #[test]
+    fn test_bandwidth() {
+        // 32-bit datagrams at 32 kbit/s: 1 ms each
+        let impairment = Impairment {
+            bandwidth: Some(32_000),
+            queue_limit: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let (received, dropped) = transfer(impairment, 100);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(received.len() as u64 + dropped, 100);
+        assert!((40..=60).contains(&dropped), "{dropped}");
+        assert_eq!(received, (0..received.len() as u32).collect::<Vec<_>>());
+    }
+}
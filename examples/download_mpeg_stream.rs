@@ -1,6 +1,6 @@
 use std::{fs, io::Write};
 
-use srt::server::Server;
+use srt::{packet::control::peer_error::error_codes, server::Server};
 use tracing::Level;
 
 fn main() -> anyhow::Result<()> {
@@ -14,21 +14,25 @@ fn main() -> anyhow::Result<()> {
         fs::write(format!("_local/stream_{id}.mpg"), []).unwrap();
     });
 
-    srt_server.on_disconnect(|conn| {
+    srt_server.on_disconnect(|conn, reason| {
         let id = conn.stream_id.clone().unwrap_or_default();
-        tracing::info!("Client disconnected: {id:?}");
+        tracing::info!("Client disconnected: {id:?} ({reason:?})");
     });
 
     srt_server.on_data(|conn, mpeg_packet| {
         let id = conn.stream_id.clone().unwrap_or_default();
 
-        let mut file = fs::OpenOptions::new()
+        let written = fs::OpenOptions::new()
             .create(true)
             .append(true)
             .open(format!("_local/stream_{id}.mpg"))
-            .unwrap();
+            .and_then(|mut file| file.write_all(mpeg_packet));
 
-        file.write_all(mpeg_packet).unwrap();
+        // Tell the caller to stop sending
+        if let Err(e) = written {
+            tracing::error!("Write failed: {id:?}: {e}");
+            _ = conn.fail(error_codes::WRITE);
+        }
     });
 
     tracing::info!("Starting SRT");
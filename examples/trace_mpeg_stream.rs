@@ -18,9 +18,9 @@ fn main() -> anyhow::Result<()> {
         );
     });
 
-    srt_server.on_disconnect(|conn| {
+    srt_server.on_disconnect(|conn, reason| {
         tracing::info!(
-            "Client disconnected: {:?}",
+            "Client disconnected: {:?} ({reason:?})",
             conn.stream_id.clone().unwrap_or_default()
         );
     });
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpeg::pes::{header::PesHeader, packet::PesPacket};
+
+fuzz_target!(|data: &[u8]| {
+    _ = PesHeader::deserialize(data);
+    _ = PesPacket::from_raw(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpeg::psi::packet::ProgramSpecificInformation;
+
+fuzz_target!(|data: &[u8]| {
+    _ = ProgramSpecificInformation::from_raw(data);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use srt::packet::control::handshake::Handshake;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(handshake) = Handshake::from_raw_cif(data) {
+        let raw = handshake.raw_content();
+        Handshake::from_raw_cif(&raw).expect("Serialized handshake doesn't parse");
+    }
+});
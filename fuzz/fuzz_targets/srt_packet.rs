@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use srt::packet::Packet;
+
+fuzz_target!(|data: &[u8]| {
+    // Whatever parses must serialize and parse again
+    if let Ok(pack) = Packet::from_raw(data) {
+        let raw = pack.to_raw();
+        Packet::from_raw(&raw).expect("Serialized packet doesn't parse");
+    }
+});
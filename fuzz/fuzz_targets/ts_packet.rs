@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpeg::transport::packet::TransportPacket;
+
+fuzz_target!(|data: &[u8]| {
+    // First byte picks a PMT packet ID, so PMT sections are reached too
+    let Some((&pmt, data)) = data.split_first() else {
+        return;
+    };
+    _ = TransportPacket::from_raw(data, &[0x1000 | u16::from(pmt)]);
+});
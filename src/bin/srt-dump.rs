@@ -0,0 +1,699 @@
+//! Prints decoded SRT packets from a pcap/pcapng file or a UDP port
+//!
+//! Listening is passive: nothing is answered, so a caller never gets past the handshake
+//! unless the traffic is mirrored to the port.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    net::SocketAddr,
+    process::ExitCode,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Map, Value, json};
+use srt::{
+    capture::{self, Direction, Record},
+    packet::{
+        Packet, PacketContent,
+        control::{
+            ControlPacketInfo,
+            ack::Ack,
+            extended::Extended,
+            handshake::{
+                Handshake,
+                extension::{
+                    Extension, extension_types, group_membership::group_type,
+                    handshake::handshake_extension_message_flags as flags,
+                    key_material::KeyBasedEncryption,
+                },
+            },
+            nak::Nak,
+        },
+        data::{DataPacketInfo, EncryptionFlag, PacketPosition},
+    },
+    seq::SeqNo,
+};
+
+const USAGE: &str = "\
+Usage: srt-dump [OPTIONS] <FILE>
+       srt-dump [OPTIONS] --listen <ADDR>
+
+Options:
+  -l, --listen <ADDR>    Read datagrams from a UDP socket instead of a capture file
+  -j, --json             One JSON object per packet
+  -s, --socket-id <ID>   Only packets to (or handshakes of) this socket (decimal or 0x hex)
+  -t, --type <TYPE>      Only packets of this type (repeatable): data, handshake, keepalive,
+                         ack, nak, congestion, shutdown, ackack, dropreq, peererror, ext
+  -h, --help             Print this help";
+
+const TYPES: [&str; 11] = [
+    "data",
+    "handshake",
+    "keepalive",
+    "ack",
+    "nak",
+    "congestion",
+    "shutdown",
+    "ackack",
+    "dropreq",
+    "peererror",
+    "ext",
+];
+
+enum Source {
+    File(String),
+    Listen(SocketAddr),
+}
+
+struct Args {
+    source: Source,
+    json: bool,
+    socket_id: Option<u32>,
+    types: Vec<String>,
+}
+
+impl Args {
+    /// `None` if help was asked for
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Self>> {
+        let mut file = None;
+        let mut listen = None;
+        let mut json = false;
+        let mut socket_id = None;
+        let mut types = Vec::new();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().with_context(|| format!("{arg} needs a value"));
+
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-j" | "--json" => json = true,
+                "-l" | "--listen" => {
+                    let addr = value()?;
+                    listen = Some(
+                        addr.parse()
+                            .with_context(|| format!("Bad address: {addr}"))?,
+                    );
+                }
+                "-s" | "--socket-id" => {
+                    let id = value()?;
+                    let parsed = match id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16),
+                        None => id.parse(),
+                    };
+                    socket_id = Some(parsed.with_context(|| format!("Bad socket ID: {id}"))?);
+                }
+                "-t" | "--type" => {
+                    let r#type = value()?.to_lowercase();
+                    if !TYPES.contains(&r#type.as_str()) {
+                        bail!("Unknown packet type: {type}");
+                    }
+                    types.push(r#type);
+                }
+                _ if arg.starts_with('-') => bail!("Unknown option: {arg}"),
+                _ if file.is_none() => file = Some(arg),
+                _ => bail!("Unexpected argument: {arg}"),
+            }
+        }
+
+        let source = match (file, listen) {
+            (Some(file), None) => Source::File(file),
+            (None, Some(addr)) => Source::Listen(addr),
+            (None, None) => bail!("Either a file or --listen is needed"),
+            (Some(_), Some(_)) => bail!("A file and --listen can't be used together"),
+        };
+
+        Ok(Some(Self {
+            source,
+            json,
+            socket_id,
+            types,
+        }))
+    }
+}
+
+/// Ordered, so the text output reads like the packet layout
+type Fields = Vec<(&'static str, Field)>;
+
+enum Field {
+    Value(Value),
+    /// Nested blocks (handshake extensions), the first field of each names it
+    Blocks(Vec<Fields>),
+}
+
+impl<T: Into<Value>> From<T> for Field {
+    fn from(value: T) -> Self {
+        Self::Value(value.into())
+    }
+}
+
+fn to_json(fields: Fields) -> Value {
+    let map: Map<String, Value> = fields
+        .into_iter()
+        .map(|(key, field)| {
+            let value = match field {
+                Field::Value(value) => value,
+                Field::Blocks(blocks) => blocks.into_iter().map(to_json).collect(),
+            };
+            (key.to_string(), value)
+        })
+        .collect();
+
+    Value::Object(map)
+}
+
+/// `key=value` pairs, lists joined by `,` and inner pairs (ranges) by `-`
+fn to_text(out: &mut String, fields: &[(&'static str, Field)]) {
+    for (k, (key, field)) in fields.iter().enumerate() {
+        if k > 0 {
+            out.push(' ');
+        }
+        match field {
+            Field::Value(v) => {
+                _ = write!(out, "{key}=");
+                text_value(out, v, false);
+            }
+            Field::Blocks(blocks) => {
+                _ = write!(out, "{key}=[");
+                for (k, block) in blocks.iter().enumerate() {
+                    if k > 0 {
+                        out.push(' ');
+                    }
+                    let Some(((_, Field::Value(name)), rest)) = block.split_first() else {
+                        continue;
+                    };
+                    text_value(out, name, false);
+                    out.push('(');
+                    to_text(out, rest);
+                    out.push(')');
+                }
+                out.push(']');
+            }
+        }
+    }
+}
+
+fn text_value(out: &mut String, value: &Value, nested: bool) {
+    match value {
+        Value::String(s) if s.is_empty() || s.contains(char::is_whitespace) => {
+            _ = write!(out, "{s:?}");
+        }
+        Value::String(s) => out.push_str(s),
+        Value::Array(items) => {
+            let separator = if nested { "-" } else { "," };
+            for (k, item) in items.iter().enumerate() {
+                if k > 0 {
+                    out.push_str(separator);
+                }
+                text_value(out, item, true);
+            }
+        }
+        other => _ = write!(out, "{other}"),
+    }
+}
+
+fn type_name(content: &PacketContent) -> &'static str {
+    match content {
+        PacketContent::Data(_) => "data",
+        PacketContent::Control(control) => match control {
+            ControlPacketInfo::Handshake(_) => "handshake",
+            ControlPacketInfo::KeepAlive => "keepalive",
+            ControlPacketInfo::Ack(_) => "ack",
+            ControlPacketInfo::Nak(_) => "nak",
+            ControlPacketInfo::CongestionWarning => "congestion",
+            ControlPacketInfo::Shutdown => "shutdown",
+            ControlPacketInfo::AckAck(_) => "ackack",
+            ControlPacketInfo::DropReq(_) => "dropreq",
+            ControlPacketInfo::PeerError(_) => "peererror",
+            ControlPacketInfo::Extended(_) => "ext",
+        },
+    }
+}
+
+fn data_fields(data: &DataPacketInfo) -> Fields {
+    let position = match data.position {
+        PacketPosition::Single => "solo",
+        PacketPosition::First => "first",
+        PacketPosition::Middle => "middle",
+        PacketPosition::Last => "last",
+    };
+    let encryption = match data.encryption {
+        EncryptionFlag::NoEncryption => "none",
+        EncryptionFlag::EvenKey => "even",
+        EncryptionFlag::OddKey => "odd",
+    };
+
+    vec![
+        ("seq", data.packet_sequence_number.get().into()),
+        ("msg", data.message_number.get().into()),
+        ("pos", position.into()),
+        ("order", data.order.into()),
+        ("enc", encryption.into()),
+        ("rexmit", data.retransmitted.into()),
+        ("len", data.content.len().into()),
+    ]
+}
+
+fn control_fields(control: &ControlPacketInfo) -> Fields {
+    match control {
+        ControlPacketInfo::Handshake(handshake) => handshake_fields(handshake),
+        ControlPacketInfo::Ack(Ack::Full {
+            ack_number,
+            last_ackd_packet_sequence_number,
+            rtt,
+            rtt_variance,
+            available_buffer_size,
+            packets_receiving_rate,
+            estimated_link_capacity,
+            receiving_rate,
+        }) => vec![
+            ("kind", "full".into()),
+            ("ack_no", (*ack_number).into()),
+            ("last_ackd", last_ackd_packet_sequence_number.get().into()),
+            ("rtt_us", (*rtt).into()),
+            ("rtt_var_us", (*rtt_variance).into()),
+            ("buffer", (*available_buffer_size).into()),
+            ("pkt_rate", (*packets_receiving_rate).into()),
+            ("capacity", (*estimated_link_capacity).into()),
+            ("rate_bps", (*receiving_rate).into()),
+        ],
+        ControlPacketInfo::Ack(Ack::Light {
+            last_ackd_packet_sequence_number,
+        }) => vec![
+            ("kind", "light".into()),
+            ("last_ackd", last_ackd_packet_sequence_number.get().into()),
+        ],
+        ControlPacketInfo::Ack(Ack::Small {
+            last_ackd_packet_sequence_number,
+            rtt,
+            rtt_variance,
+            available_buffer_size,
+        }) => vec![
+            ("kind", "small".into()),
+            ("last_ackd", last_ackd_packet_sequence_number.get().into()),
+            ("rtt_us", (*rtt).into()),
+            ("rtt_var_us", (*rtt_variance).into()),
+            ("buffer", (*available_buffer_size).into()),
+        ],
+        ControlPacketInfo::Nak(losses) => {
+            let (mut ranges, mut count) = (Vec::new(), 0);
+            for nak in losses {
+                let (from, to) = match *nak {
+                    Nak::Single { lost_packet } => (lost_packet, lost_packet),
+                    Nak::Range {
+                        lost_packets_from,
+                        lost_packets_to,
+                    } => (lost_packets_from, lost_packets_to),
+                };
+                count += from.range_inclusive(to).count();
+                ranges.push(range(from, to));
+            }
+            vec![
+                ("lost", Value::Array(ranges).into()),
+                ("count", count.into()),
+            ]
+        }
+        ControlPacketInfo::AckAck(ack_ack) => vec![("ack_no", ack_ack.ack_number.into())],
+        ControlPacketInfo::DropReq(drop_req) => vec![
+            ("msg", drop_req.message_number.get().into()),
+            (
+                "seqs",
+                range(
+                    drop_req.first_packet_sequence_number,
+                    drop_req.last_packet_sequence_number,
+                )
+                .into(),
+            ),
+        ],
+        ControlPacketInfo::PeerError(peer_error) => {
+            vec![("code", peer_error.error_code.into())]
+        }
+        ControlPacketInfo::Extended(Extended::Srt(ext)) => {
+            vec![("ext", Field::Blocks(vec![extension_fields(ext)]))]
+        }
+        ControlPacketInfo::Extended(Extended::User { subtype, data }) => vec![
+            ("subtype", format!("0x{subtype:04X}").into()),
+            ("len", data.len().into()),
+        ],
+        ControlPacketInfo::KeepAlive
+        | ControlPacketInfo::CongestionWarning
+        | ControlPacketInfo::Shutdown => Vec::new(),
+    }
+}
+
+fn handshake_fields(handshake: &Handshake) -> Fields {
+    let mut fields = vec![
+        ("type", format!("{:?}", handshake.handshake_type).into()),
+        ("version", handshake.version.into()),
+        ("enc", format!("{:?}", handshake.encryption).into()),
+        (
+            "ext_field",
+            format!("0x{:04X}", handshake.extension_field).into(),
+        ),
+        ("isn", handshake.initial_packet_sequence_number.into()),
+        ("mtu", handshake.maximum_transmission_unit_size.into()),
+        ("flow_window", handshake.maximum_flow_window_size.into()),
+        ("socket_id", socket_id(handshake.srt_socket_id).into()),
+        ("cookie", format!("0x{:08X}", handshake.syn_cookie).into()),
+        ("peer_ip", handshake.peer_ip_address.to_string().into()),
+    ];
+    if !handshake.extensions.is_empty() {
+        let extensions = handshake.extensions.iter().map(extension_fields).collect();
+        fields.push(("ext", Field::Blocks(extensions)));
+    }
+
+    fields
+}
+
+fn extension_fields(ext: &Extension) -> Fields {
+    let name = match ext.r#type() {
+        extension_types::HSREQ => "HSREQ",
+        extension_types::HSRSP => "HSRSP",
+        extension_types::KMREQ => "KMREQ",
+        extension_types::KMRSP => "KMRSP",
+        extension_types::SID => "SID",
+        extension_types::CONGESTION => "CONGESTION",
+        extension_types::FILTER => "FILTER",
+        extension_types::GROUP => "GROUP",
+        _ => "UNKNOWN",
+    };
+    let mut fields: Fields = vec![("type", name.into())];
+
+    match ext {
+        Extension::Handshake(hs) => {
+            let v = hs.srt_version;
+            let names = [
+                (flags::TSBPDSND, "TSBPDSND"),
+                (flags::TSBPDRCV, "TSBPDRCV"),
+                (flags::CRYPT, "CRYPT"),
+                (flags::TLPKTDROP, "TLPKTDROP"),
+                (flags::PERIODICNAK, "PERIODICNAK"),
+                (flags::REXMITFLG, "REXMITFLG"),
+                (flags::STREAM, "STREAM"),
+                (flags::PACKET_FILTER, "PACKET_FILTER"),
+            ];
+            let set: Vec<Value> = names
+                .iter()
+                .filter(|(flag, _)| hs.srt_flags & flag != 0)
+                .map(|(_, name)| (*name).into())
+                .collect();
+
+            fields.extend([
+                (
+                    "srt_version",
+                    format!("{}.{}.{}", v >> 16, (v >> 8) & 0xFF, v & 0xFF).into(),
+                ),
+                ("flags", Value::Array(set).into()),
+                ("rcv_delay_ms", hs.receiver_delay.into()),
+                ("snd_delay_ms", hs.sender_delay.into()),
+            ]);
+        }
+        Extension::KeyMaterial(km) => {
+            let keys = match km.key_based_encryption {
+                KeyBasedEncryption::EvenKey => "even",
+                KeyBasedEncryption::OddKey => "odd",
+                KeyBasedEncryption::Both => "both",
+            };
+            fields.extend([
+                ("keys", keys.into()),
+                ("cipher", km.cipher.into()),
+                ("key_len", km.key_length().into()),
+                ("salt_len", km.salt.len().into()),
+            ]);
+        }
+        Extension::StreamId(sid) => fields.push(("stream_id", sid.stream_id.clone().into())),
+        Extension::Congestion(cc) => fields.push(("congestion", cc.congestion.clone().into())),
+        Extension::PacketFilter(filter) => fields.push(("config", filter.config.clone().into())),
+        Extension::GroupMembership(group) => {
+            let r#type = match group.r#type {
+                group_type::UNDEFINED => "undefined".into(),
+                group_type::BROADCAST => "broadcast".into(),
+                group_type::MAIN_BACKUP => "backup".into(),
+                group_type::BALANCING => "balancing".into(),
+                group_type::MULTICAST => "multicast".into(),
+                other => other.to_string(),
+            };
+            fields.extend([
+                ("group_id", socket_id(group.group_id).into()),
+                ("group_type", r#type.into()),
+                ("flags", format!("0x{:02X}", group.flags).into()),
+                ("weight", group.weight.into()),
+            ]);
+        }
+        Extension::Unknown { r#type, data } => {
+            fields.extend([("ext_type", (*r#type).into()), ("len", data.len().into())]);
+        }
+    }
+
+    fields
+}
+
+fn socket_id(id: u32) -> String {
+    format!("0x{id:08X}")
+}
+
+fn range(from: SeqNo, to: SeqNo) -> Value {
+    if from == to {
+        from.get().into()
+    } else {
+        json!([from.get(), to.get()])
+    }
+}
+
+/// Sequence numbers seen on each flow (source, destination)
+#[derive(Default)]
+struct Gaps {
+    next: HashMap<(SocketAddr, SocketAddr), SeqNo>,
+    lost: usize,
+}
+
+impl Gaps {
+    /// Missing range before `data`, or whether it came late
+    fn check(&mut self, src: SocketAddr, dst: SocketAddr, data: &DataPacketInfo) -> Fields {
+        let seq = data.packet_sequence_number;
+        let Some(next) = self.next.get_mut(&(src, dst)) else {
+            self.next.insert((src, dst), seq.next());
+            return Vec::new();
+        };
+
+        if seq >= *next {
+            let gap = *next;
+            *next = seq.next();
+            if seq > gap {
+                let missing = seq - 1;
+                self.lost += usize::try_from(gap.distance(seq)).unwrap_or_default();
+                return vec![("gap", range(gap, missing).into())];
+            }
+        } else if !data.retransmitted {
+            return vec![("late", true.into())];
+        }
+
+        Vec::new()
+    }
+}
+
+struct Dump<W: Write> {
+    out: W,
+    json: bool,
+    socket_id: Option<u32>,
+    types: Vec<String>,
+    start: Option<SystemTime>,
+    gaps: Gaps,
+    packets: usize,
+    shown: usize,
+    malformed: usize,
+}
+
+impl<W: Write> Dump<W> {
+    fn record(&mut self, record: &Record) -> Result<()> {
+        self.packets += 1;
+        let start = *self.start.get_or_insert(record.time);
+        let relative = record.time.duration_since(start).unwrap_or(Duration::ZERO);
+
+        let pack = match Packet::from_raw(&record.data) {
+            Ok(pack) => pack,
+            Err(e) => {
+                self.malformed += 1;
+                // Neither type nor socket ID is known
+                if !self.types.is_empty() || self.socket_id.is_some() {
+                    return Ok(());
+                }
+                let fields = vec![
+                    ("error", e.to_string().into()),
+                    ("len", record.data.len().into()),
+                ];
+                return self.print(record, relative, "malformed", None, fields);
+            }
+        };
+
+        let mut fields = match &pack.content {
+            PacketContent::Data(data) => {
+                let mut fields = data_fields(data);
+                fields.extend(self.gaps.check(record.src, record.dst, data));
+                fields
+            }
+            PacketContent::Control(control) => control_fields(control),
+        };
+
+        let r#type = type_name(&pack.content);
+        if !self.types.is_empty() && !self.types.iter().any(|t| t == r#type) {
+            return Ok(());
+        }
+        if let Some(id) = self.socket_id {
+            let handshake_id = match &pack.content {
+                PacketContent::Control(ControlPacketInfo::Handshake(h)) => Some(h.srt_socket_id),
+                _ => None,
+            };
+            if pack.dest_socket_id != id && handshake_id != Some(id) {
+                return Ok(());
+            }
+        }
+
+        fields.insert(0, ("ts_us", pack.timestamp.into()));
+        self.print(record, relative, r#type, Some(pack.dest_socket_id), fields)
+    }
+
+    fn print(
+        &mut self,
+        record: &Record,
+        relative: Duration,
+        r#type: &str,
+        dest_socket_id: Option<u32>,
+        fields: Fields,
+    ) -> Result<()> {
+        self.shown += 1;
+        let direction = record.direction.map(|direction| match direction {
+            Direction::Inbound => "in",
+            Direction::Outbound => "out",
+        });
+
+        if self.json {
+            let time = record
+                .time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            let mut object = json!({
+                "time": time.as_secs_f64(),
+                "src": record.src.to_string(),
+                "dst": record.dst.to_string(),
+                "direction": direction,
+                "type": r#type,
+                "dest_socket_id": dest_socket_id,
+            });
+            if let (Value::Object(object), Value::Object(fields)) = (&mut object, to_json(fields)) {
+                object.extend(fields);
+            }
+            writeln!(self.out, "{object}")?;
+        } else {
+            let mut line = format!(
+                "{:>12.6} {} > {}",
+                relative.as_secs_f64(),
+                record.src,
+                record.dst
+            );
+            if let Some(direction) = direction {
+                _ = write!(line, " ({direction})");
+            }
+            if let Some(id) = dest_socket_id {
+                _ = write!(line, " {}", socket_id(id));
+            }
+            _ = write!(line, " {}", r#type.to_uppercase());
+            if !fields.is_empty() {
+                line.push(' ');
+                to_text(&mut line, &fields);
+            }
+            writeln!(self.out, "{line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    let Args {
+        source,
+        json,
+        socket_id,
+        types,
+    } = args;
+
+    let mut dump = Dump {
+        out: BufWriter::new(io::stdout().lock()),
+        json,
+        socket_id,
+        types,
+        start: None,
+        gaps: Gaps::default(),
+        packets: 0,
+        shown: 0,
+        malformed: 0,
+    };
+
+    match source {
+        Source::File(path) => {
+            let file = File::open(&path).with_context(|| format!("Can't open {path}"))?;
+            for record in capture::read(BufReader::new(file))? {
+                dump.record(&record?)?;
+            }
+            dump.out.flush()?;
+
+            eprintln!(
+                "{} datagrams, {} shown, {} malformed, {} packets missing",
+                dump.packets, dump.shown, dump.malformed, dump.gaps.lost
+            );
+        }
+        Source::Listen(addr) => {
+            let socket = srt::socket::bind(addr)?;
+            let local = socket.local_addr()?;
+            eprintln!("Listening on {local}");
+
+            let mut buf = vec![0; 65536];
+            loop {
+                let (len, src) = socket.recv_from(&mut buf)?;
+                let record = Record {
+                    time: SystemTime::now(),
+                    direction: Some(Direction::Inbound),
+                    src,
+                    dst: local,
+                    data: buf[..len].to_vec(),
+                };
+                dump.record(&record)?;
+                dump.out.flush()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        // Output piped into `head`
+        Err(e)
+            if e.downcast_ref::<io::Error>().map(io::Error::kind)
+                == Some(io::ErrorKind::BrokenPipe) =>
+        {
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("srt-dump: {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
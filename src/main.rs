@@ -1,13 +1,11 @@
 use std::{
-    cell::RefCell,
     fs,
     io::Write,
-    rc::Rc,
     sync::{
         Arc,
         atomic::{AtomicBool, AtomicU64, Ordering},
     },
-    thread,
+    time::{Duration, SystemTime},
 };
 
 use mpeg::{
@@ -15,44 +13,36 @@ use mpeg::{
     psi::packet::{ProgramSpecificInformation, Section},
     transport::packet::{Payload, TransportPacket as MpegPacket},
 };
-use srt::server::Server as SrtServer;
+use srt::{
+    access::{AccessControl, AccessRules},
+    options::ListenerOptions,
+    tokio::{Listener as SrtListener, Stream as SrtStream},
+};
+
+/// File with access rules (see `srt::access`), checked for changes every few seconds
+const ACCESS_RULES_VAR: &str = "SRT_ACCESS_RULES";
+const ACCESS_RULES_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
-fn run_srt(
+async fn handle_stream(
+    stream: &mut SrtStream,
     segment_size: u64,
-    current_segment: Arc<AtomicU64>,
-    is_ended: Arc<AtomicBool>,
+    current_segment: &AtomicU64,
 ) -> anyhow::Result<()> {
-    let timer = Rc::new(RefCell::new(0u64));
-    let current_segment_data = Rc::new(RefCell::new(Vec::<u8>::new()));
-
-    let system_pid = Rc::new(RefCell::new(0u16));
-    let clock_pid = Rc::new(RefCell::new(0u16));
-
-    let mut srt_server = SrtServer::new("0.0.0.0:9000")?;
-
-    srt_server.on_connect({
-        let is_ended = is_ended.clone();
-        move |conn| {
-            let id = conn.stream_id.clone().unwrap_or_default();
-            tracing::info!("Stream started: {id:?}");
-            is_ended.store(false, Ordering::Relaxed);
-        }
-    });
+    let mut timer = 0u64;
+    let mut current_segment_data = Vec::<u8>::new();
 
-    srt_server.on_disconnect(move |conn| {
-        let id = conn.stream_id.clone().unwrap_or_default();
-        tracing::info!("Stream ended: {id:?}");
-        is_ended.store(true, Ordering::Relaxed);
-    });
+    let mut system_pid = 0u16;
+    let mut clock_pid = 0u16;
 
-    srt_server.on_data(move |_, mpeg_data| {
+    while let Some(mpeg_data) = stream.recv().await {
         for chunk in mpeg_data.chunks_exact(MPEG_PACKET_SIZE) {
-            let pmt_ids = {
-                let pmt = *system_pid.borrow();
-                if pmt != 0 { &[pmt] } else { &[] as &[u16] }
+            let pmt_ids = if system_pid != 0 {
+                &[system_pid]
+            } else {
+                &[] as &[u16]
             };
 
-            let pack = MpegPacket::from_raw(chunk, pmt_ids).unwrap();
+            let pack = MpegPacket::from_raw(chunk, pmt_ids)?;
 
             match pack.payload {
                 // If packet is PAS
@@ -60,12 +50,12 @@ fn run_srt(
                     section: Section::PAS(table),
                     ..
                 })) => {
-                    if *system_pid.borrow() == 0 {
+                    if system_pid == 0 {
                         tracing::info!("Got system program id: {}", table.programs[0].program_id);
-                        system_pid.replace(table.programs[0].program_id);
+                        system_pid = table.programs[0].program_id;
                     }
 
-                    let new_segment = *timer.borrow() / segment_size;
+                    let new_segment = timer / segment_size;
                     let old_segment = current_segment.swap(new_segment, Ordering::Relaxed);
 
                     // Flush
@@ -75,12 +65,10 @@ fn run_srt(
                         fs::OpenOptions::new()
                             .create(true)
                             .append(true)
-                            .open(format!("_local/stream/segment_{old_segment}.mpg"))
-                            .unwrap()
-                            .write_all(&current_segment_data.borrow())
-                            .unwrap();
+                            .open(format!("_local/stream/segment_{old_segment}.mpg"))?
+                            .write_all(&current_segment_data)?;
 
-                        current_segment_data.borrow_mut().clear();
+                        current_segment_data.clear();
                     }
                 }
 
@@ -88,33 +76,96 @@ fn run_srt(
                 Some(Payload::PSI(ProgramSpecificInformation {
                     section: Section::PMS(table),
                     ..
-                })) => {
-                    if *clock_pid.borrow() == 0 {
-                        tracing::info!("Got clock program id: {}", table.pcr_pid);
-                        clock_pid.replace(table.pcr_pid);
-                    }
+                })) if clock_pid == 0 => {
+                    tracing::info!("Got clock program id: {}", table.pcr_pid);
+                    clock_pid = table.pcr_pid;
                 }
 
                 // If packet is Video (OBS) + clock
-                Some(Payload::PES(pes)) if pack.header.packet_id == *clock_pid.borrow() => {
+                Some(Payload::PES(pes)) if pack.header.packet_id == clock_pid => {
                     if let Some(pts_dts) = &pes.pes_header.as_ref().unwrap().pts_dts {
-                        let seconds = pts_dts.pts() / 90_000;
-                        timer.replace(seconds);
+                        timer = pts_dts.pts() / 90_000;
                     }
                 }
 
                 _ => {}
             }
 
-            current_segment_data.borrow_mut().extend(chunk);
+            current_segment_data.extend(chunk);
         }
-    });
+    }
+
+    Ok(())
+}
+
+async fn run_srt(
+    segment_size: u64,
+    current_segment: Arc<AtomicU64>,
+    is_ended: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let access = AccessControl::default();
+    if let Ok(path) = std::env::var(ACCESS_RULES_VAR) {
+        let modified = fs::metadata(&path)?.modified()?;
+        load_access_rules(&path, &access)?;
+        tokio::spawn(watch_access_rules(path, access.clone(), modified));
+    }
+
+    let options = ListenerOptions {
+        access,
+        ..Default::default()
+    };
+    let mut listener = SrtListener::bind_with_options("0.0.0.0:9000", options).await?;
+
+    loop {
+        let mut stream = listener.accept().await?;
+        let current_segment = current_segment.clone();
+        let is_ended = is_ended.clone();
+
+        tokio::spawn(async move {
+            let id = stream.stream_id.clone().unwrap_or_default();
+            tracing::info!("Stream started: {id:?}");
+            is_ended.store(false, Ordering::Relaxed);
 
-    srt_server.run()?;
+            if let Err(e) = handle_stream(&mut stream, segment_size, &current_segment).await {
+                tracing::error!("Stream failed: {e}");
+            }
+
+            tracing::info!("Stream ended: {id:?}");
+            is_ended.store(true, Ordering::Relaxed);
+        });
+    }
+}
+
+fn load_access_rules(path: &str, access: &AccessControl) -> anyhow::Result<()> {
+    let rules: AccessRules = fs::read_to_string(path)?.parse()?;
+
+    tracing::info!("Loaded {} access rules from {path}", rules.rules.len());
+    access.reload(rules);
 
     Ok(())
 }
 
+/// Reload rules when the file changes (live connections stay, bad files are ignored)
+async fn watch_access_rules(path: String, access: AccessControl, mut modified: SystemTime) {
+    let mut interval = tokio::time::interval(ACCESS_RULES_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let Ok(time) = fs::metadata(&path).and_then(|meta| meta.modified()) else {
+            continue;
+        };
+        if time == modified {
+            continue;
+        }
+        modified = time;
+
+        if let Err(e) = load_access_rules(&path, &access) {
+            tracing::error!("Access rules not reloaded: {e:#}");
+        }
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     const SECONDS_PER_SEGMENT: u64 = 2;
 
@@ -127,16 +178,25 @@ fn main() -> anyhow::Result<()> {
     let current_segment = Arc::new(AtomicU64::new(0));
     let is_ended = Arc::new(AtomicBool::new(false));
 
-    tracing::info!("Starting SRT");
-    thread::spawn({
-        let current_segment = current_segment.clone();
-        let is_ended = is_ended.clone();
-
-        || run_srt(SECONDS_PER_SEGMENT, current_segment, is_ended).unwrap()
-    });
-
-    tracing::info!("Starting HLS");
-    hls::run(SECONDS_PER_SEGMENT, current_segment, is_ended)?;
+    // Ingest and HTTP share one runtime
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    rt.block_on(async {
+        tracing::info!("Starting SRT");
+        tokio::spawn({
+            let current_segment = current_segment.clone();
+            let is_ended = is_ended.clone();
+
+            async move {
+                run_srt(SECONDS_PER_SEGMENT, current_segment, is_ended)
+                    .await
+                    .unwrap()
+            }
+        });
 
-    Ok(())
+        tracing::info!("Starting HLS");
+        hls::serve(SECONDS_PER_SEGMENT, current_segment, is_ended).await
+    })
 }